@@ -14,19 +14,40 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{cell::RefCell, fs, io, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     Player, Scene, Storage,
-    audio::{Audio, AudioCell},
-    storage::StorageCredentials,
+    audio::{self, Audio, AudioCell, StreamContext},
+    autosave::{self, Autosave, Recovery},
+    command::{Command, UndoStack},
+    history::{PlayHistory, PlayStats},
+    project::{PROJECT_EXTENSION, Project},
+    schema,
+    storage::{
+        self, StorageCredentials, portable, relink::Relinker, source::Source, waveform::Waveform,
+        zipstorage,
+    },
 };
 
+/// Track which file is not found.
+pub struct MissingTrack {
+    pub title: String,
+    pub filename: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Application {
-    storage: Rc<RefCell<Storage>>,
+    #[serde(default)]
+    storages: Vec<Rc<RefCell<Storage>>>,
     root_map: Rc<RefCell<Scene>>,
 
     #[serde(skip)]
@@ -35,29 +56,123 @@ pub struct Application {
     selected_playlist: AudioCell,
     #[serde(skip)]
     current_playing: AudioCell,
+    #[serde(skip)]
+    history: PlayHistory,
+    /// Open project file.
+    #[serde(skip)]
+    project: Option<Project>,
+    /// Path of saved or opened project.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    autosave: Autosave,
+    #[serde(skip)]
+    undo: UndoStack,
 }
 
 impl Application {
     pub fn new(
-        storage: Rc<RefCell<Storage>>,
+        storages: Vec<Rc<RefCell<Storage>>>,
         root_map: Rc<RefCell<Scene>>,
         player: Rc<RefCell<Player>>,
     ) -> Application {
-        Application {
-            storage,
+        let mut application = Application {
+            storages,
             root_map,
             player,
             selected_playlist: Rc::new(RefCell::new(None)),
             current_playing: Rc::new(RefCell::new(None)),
+            history: PlayHistory::default(),
+            project: None,
+            path: None,
+            autosave: Autosave::default(),
+            undo: UndoStack::default(),
+        };
+        // Changes made before the first autosave are compared with it.
+        application.set_baseline();
+        application
+    }
+
+    pub fn get_storages(&self) -> Vec<Rc<RefCell<Storage>>> {
+        self.storages.clone()
+    }
+
+    /// Ids and revisions of storages, changed with sources of any storage.
+    pub fn storages_revision(&self) -> Vec<(String, u64)> {
+        self.storages
+            .iter()
+            .map(|s| (s.borrow().get_id(), s.borrow().get_revision()))
+            .collect()
+    }
+
+    /// Sources of project storages matched the query of smart thread.
+    pub fn find_sources(&self, query: &str) -> Vec<Source> {
+        storage::find_sources(&self.storages, query)
+    }
+
+    pub fn find_waveform(&self, filename: &str) -> Option<Waveform> {
+        storage::find_waveform(&self.storages, filename)
+    }
+
+    /// Mount one more storage to the project.
+    pub fn add_storage(&mut self, credentials: StorageCredentials, password: String) {
+        let mut storage = Storage::new();
+        storage.set_caption(credentials.default_caption());
+        storage.set_password(password);
+        storage.set_project(self.path.clone());
+        storage.setup_storage(credentials);
+        self.storages.push(Rc::new(RefCell::new(storage)));
+    }
+
+    pub fn remove_storage(&mut self, index: usize) {
+        if index < self.storages.len() {
+            self.storages.remove(index);
+        }
+    }
+
+    /// Change the project so it could be undone.
+    pub fn execute(&mut self, command: Command) -> bool {
+        self.undo.execute(command)
+    }
+
+    /// Change tags of many sources of storage so it could be undone, e.g.
+    /// import tags or accept suggestions.
+    pub fn edit_tags<T>(
+        &mut self,
+        storage: &Rc<RefCell<Storage>>,
+        edit: impl FnOnce(&mut Storage) -> T,
+    ) -> T {
+        let state = storage.borrow().get_tag_state();
+        let result = edit(&mut storage.borrow_mut());
+        if storage.borrow().get_tag_state() != state {
+            self.undo.record(Command::SetTagState {
+                storage: Rc::clone(storage),
+                state,
+            });
         }
+        result
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.undo.undo()
     }
 
-    pub fn get_storage(&self) -> Rc<RefCell<Storage>> {
-        Rc::clone(&self.storage)
+    pub fn redo(&mut self) -> bool {
+        self.undo.redo()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.undo.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.undo.can_redo()
     }
 
     pub fn reverse_colors(&mut self) {
-        self.storage.borrow_mut().reverse_colors();
+        for storage in &self.storages {
+            storage.borrow_mut().reverse_colors();
+        }
     }
 
     pub fn setup_storage(
@@ -66,6 +181,10 @@ impl Application {
     ) -> Result<(), Box<dyn std::error::Error>> {
         match credentials {
             StorageCredentials::Local(path) => self.open_local_project(path),
+            credentials => {
+                self.add_storage(credentials, String::new());
+                Ok(())
+            }
         }
     }
 
@@ -95,7 +214,17 @@ impl Application {
 
     pub fn player_set_audio(&mut self, audio: Audio) {
         self.current_playing.replace(Some(Rc::clone(&audio)));
-        let s = audio.borrow().get_stream();
+        let mut s = audio.borrow().get_stream(&self.stream_context());
+        let playlist_id = audio.borrow().get_id().unwrap_or_default();
+        let playlist = if audio.borrow().threads().is_ok() {
+            audio.borrow().get_title()
+        } else {
+            String::new()
+        };
+        let scene = Scene::find_scene(&self.root_map, &audio)
+            .map(|scene| scene.borrow().caption())
+            .unwrap_or_default();
+        s.set_context(playlist_id, playlist, scene);
         self.player.borrow_mut().set_stream(s);
         self.player
             .borrow_mut()
@@ -132,7 +261,7 @@ impl Application {
                 .as_ref()
                 .unwrap()
                 .borrow()
-                .get_stream();
+                .get_stream(&self.stream_context());
             self.player.borrow_mut().sync(stream);
         }
     }
@@ -145,24 +274,517 @@ impl Application {
         }
     }
 
-    pub fn save_project(&mut self, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-        let s = serde_yaml::to_string(self).unwrap();
-        fs::write(path, s)?;
+    /// Move finished playing from player to the play history.
+    pub fn poll_history(&mut self) {
+        let plays = self.player.borrow_mut().take_plays();
+        for play in plays {
+            self.history.push(play);
+        }
+    }
+
+    pub fn play_stats(&self, filename: &str) -> Option<PlayStats> {
+        self.history.stats(filename)
+    }
+
+    pub fn playlist_stats(&self, playlist_id: &str) -> Option<PlayStats> {
+        self.history.playlist_stats(playlist_id)
+    }
+
+    fn stream_context(&self) -> StreamContext<'_> {
+        StreamContext {
+            storages: &self.storages,
+            history: &self.history,
+        }
+    }
+
+    /// Update playing smart threads after storage changes.
+    pub fn sync_smart_threads(&mut self) {
+        let Some(playing) = self.current_playing.borrow().clone() else {
+            return;
+        };
+        let mut has_query = false;
+        audio::walk(&playing, &mut |audio| {
+            has_query |= audio.borrow().get_query().is_ok();
+        });
+        if has_query {
+            self.player_sync();
+        }
+    }
+
+    /// Tracks of all scenes which files are not found.
+    pub fn missing_tracks(&self) -> Vec<MissingTrack> {
+        let mut missing: Vec<MissingTrack> = vec![];
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            if let Ok(source) = audio.borrow().get_source()
+                && source.is_missing()
+                && !missing.iter().any(|m| m.filename == source.get_filename())
+            {
+                missing.push(MissingTrack {
+                    title: audio.borrow().get_title(),
+                    filename: source.get_filename(),
+                });
+            }
+        });
+        missing
+    }
+
+    /// Search missing files in directory and repair storage and tracks.
+    /// Return count of repaired references.
+    pub fn relink(&mut self, dir: PathBuf) -> usize {
+        self.relink_with(Relinker::scan(&dir))
+    }
+
+    fn relink_with(&mut self, mut relinker: Relinker) -> usize {
+        let mut count = 0;
+        for storage in &self.storages {
+            count += storage.borrow_mut().relink(&mut relinker);
+        }
+
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            let source = audio.borrow().get_source();
+            if let Ok(mut source) = source
+                && source.is_missing()
+                && let Some(path) = relinker.find(&source)
+            {
+                source.set_filename(path.to_string_lossy().to_string());
+                audio.borrow_mut().set_source(source);
+                count += 1;
+            }
+        });
+        count
+    }
+
+    /// Merge duplicated sources of storage and point tracks to the kept one.
+    /// Return count of repointed tracks. The merge is undone at once.
+    pub fn merge_duplicates(
+        &mut self,
+        storage: usize,
+        keep: usize,
+        others: Vec<usize>,
+        hide: bool,
+    ) -> usize {
+        let Some(storage) = self.storages.get(storage) else {
+            return 0;
+        };
+        let Some(kept) = storage.borrow().get(keep) else {
+            return 0;
+        };
+        let copies: Vec<_> = others
+            .iter()
+            .filter(|&&i| i != keep)
+            .filter_map(|&i| storage.borrow().get(i))
+            .collect();
+        let mut inverse = vec![Command::SetTagState {
+            storage: Rc::clone(storage),
+            state: storage.borrow().get_tag_state(),
+        }];
+        storage.borrow_mut().merge_duplicates(keep, &others, hide);
+
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            let source = audio.borrow().get_source();
+            // Filenames are compared, exact copies have the same id.
+            if let Ok(source) = source
+                && source.get_filename() != kept.get_filename()
+                && copies.iter().any(|c| c.get_filename() == source.get_filename())
+            {
+                audio.borrow_mut().set_source(kept.clone());
+                inverse.push(Command::SetSource {
+                    audio: Rc::clone(audio),
+                    source,
+                });
+            }
+        });
+        let count = inverse.len() - 1;
+        self.undo.record(Command::Batch(inverse));
+        count
+    }
+
+    /// Show hidden duplicates of all storages, undone at once.
+    pub fn unhide_all(&mut self) {
+        let inverse: Vec<Command> = self
+            .storages
+            .iter()
+            .filter(|storage| storage.borrow().hidden_count() > 0)
+            .map(|storage| {
+                let state = storage.borrow().get_tag_state();
+                storage.borrow_mut().unhide_all();
+                Command::SetTagState {
+                    storage: Rc::clone(storage),
+                    state,
+                }
+            })
+            .collect();
+        if !inverse.is_empty() {
+            self.undo.record(Command::Batch(inverse));
+        }
+    }
+
+    /// Save project to YAML or to project file by extension. Audio could be
+    /// embedded to project file only.
+    pub fn save_project(
+        &mut self,
+        path: PathBuf,
+        embed_audio: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if let Err(e) = autosave::backup(&path) {
+            eprintln!("Error making backup of project: {}", e);
+        }
+        if path.extension().is_some_and(|e| e == PROJECT_EXTENSION) {
+            let mut project = match self.project.take() {
+                Some(project) => project,
+                None => Project::new()?,
+            };
+            let saved = self.pack(&mut project, &path, &dir, embed_audio);
+            self.project = Some(project);
+            saved?;
+        } else {
+            self.make_portable(&dir);
+            let s = schema::to_yaml(self);
+            self.resolve_paths(&dir);
+            fs::write(&path, s?)?;
+        }
+        self.history.set_project(&path);
+        self.set_path(Some(path));
+        self.mark_saved();
         Ok(())
     }
 
+    /// Copy backgrounds and audio to project and save it to the file.
+    fn pack(
+        &mut self,
+        project: &mut Project,
+        path: &Path,
+        dir: &Path,
+        embed_audio: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Backgrounds are saved relative to the project directory.
+        let mut error = None;
+        let mut used = HashSet::new();
+        self.root_map.borrow_mut().walk_scenes(&mut |scene| {
+            if let Some(background) = scene.get_background_path() {
+                match project.add_background(&background) {
+                    Ok(relative) => {
+                        used.insert(relative.clone());
+                        scene.set_background_path(relative);
+                    }
+                    Err(e) => error = Some(e),
+                }
+            }
+        });
+        // Audio embedded before is kept.
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            let source = audio.borrow().get_source();
+            let Ok(source) = source else {
+                return;
+            };
+            if let Some(relative) = project.embedded_audio(&source.get_filename()) {
+                used.insert(relative);
+                return;
+            }
+            let filename = PathBuf::from(source.get_filename());
+            if filename.is_file() && embed_audio {
+                match project.embed_audio(&filename) {
+                    Ok(relative) => {
+                        used.insert(relative);
+                    }
+                    Err(e) => error = Some(e),
+                }
+            }
+        });
+        if let Err(e) = project.remove_unused(&used) {
+            eprintln!("Error removing unused project files: {}", e);
+        }
+        // Tracks play embedded audio of the saved file.
+        let archive = project.archive();
+        if let Some(archive) = &archive {
+            self.move_embedded_audio(archive, path);
+        }
+        self.make_portable(dir);
+        let yaml = schema::to_yaml(self);
+        // Scenes use the copies from now on.
+        resolve_backgrounds(&self.root_map, project.dir());
+        self.resolve_paths(dir);
+        let saved = (|| -> Result<(), Box<dyn std::error::Error>> {
+            if let Some(e) = error {
+                return Err(Box::new(e));
+            }
+            project.set_yaml(&yaml?)?;
+            project.save(path)?;
+            Ok(())
+        })();
+        if saved.is_err()
+            && let Some(archive) = &archive
+        {
+            self.move_embedded_audio(path, archive);
+        }
+        saved
+    }
+
+    /// Point tracks played from one project file to the other one.
+    fn move_embedded_audio(&self, from: &Path, to: &Path) {
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            let source = audio.borrow().get_source();
+            if let Ok(mut source) = source
+                && let Some((archive, entry)) = zipstorage::split_filename(&source.get_filename())
+                && archive == from
+            {
+                source.set_filename(zipstorage::entry_filename(to, &entry));
+                audio.borrow_mut().set_source(source);
+            }
+        });
+    }
+
+    /// Open project file, tracks missing on this computer are relinked to
+    /// embedded audio.
+    pub fn open_project_file(&mut self, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        let project = Project::open(&path)?;
+        let app = Application::from_yaml(&project.get_yaml()?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        resolve_backgrounds(&app.root_map, project.dir());
+        app.resolve_paths(dir);
+
+        self.poll_history();
+        self.history = PlayHistory::load(&path);
+        self.replace(app);
+        // Tracks could use audio of the closed project, they are relinked.
+        self.close_project();
+        if project.get_manifest().embedded_audio
+            && let Some(relinker) = project.audio_relinker()
+        {
+            self.relink_with(relinker);
+        }
+        self.project = Some(project);
+        self.set_path(Some(path));
+        self.mark_saved();
+        Ok(())
+    }
+
+    /// Project YAML for the recovery file. Backgrounds of open project file
+    /// are saved relative to it, they are restored from the file.
+    fn recovery_yaml(&self) -> Result<String, schema::SchemaError> {
+        let project_dir = self.project.as_ref().map(|p| p.dir());
+        if let Some(dir) = project_dir {
+            self.root_map.borrow_mut().walk_scenes(&mut |scene| {
+                let background = scene.get_background_path();
+                if let Some(Ok(relative)) = background.as_ref().map(|b| b.strip_prefix(dir)) {
+                    scene.set_background_path(relative.to_path_buf());
+                }
+            });
+        }
+        let yaml = schema::to_yaml(self);
+        if let Some(dir) = project_dir {
+            resolve_backgrounds(&self.root_map, dir);
+        }
+        yaml
+    }
+
+    /// Current state is saved, the recovery file is not needed.
+    fn mark_saved(&mut self) {
+        self.set_baseline();
+        self.autosave.discard();
+    }
+
+    /// Current state has no unsaved changes.
+    fn set_baseline(&mut self) {
+        match self.recovery_yaml() {
+            Ok(yaml) => self.autosave.set_baseline(&yaml),
+            Err(e) => eprintln!("Error serializing project: {}", e),
+        }
+    }
+
+    /// Write changed project to the recovery file from time to time.
+    pub fn poll_autosave(&mut self) {
+        if !self.autosave.is_due() {
+            return;
+        }
+        let saved = match self.recovery_yaml() {
+            Ok(yaml) => self.autosave.autosave(self.path.clone(), &yaml),
+            Err(e) => Err(io::Error::other(e)),
+        };
+        if let Err(e) = saved {
+            eprintln!("Error autosaving project: {}", e);
+        }
+    }
+
+    /// Unsaved project of the last session.
+    pub fn find_recovery(&self) -> Option<Recovery> {
+        self.autosave.find_recovery()
+    }
+
+    pub fn discard_recovery(&self, recovery: &Recovery) {
+        autosave::remove_recovery(&recovery.file);
+    }
+
+    /// Open unsaved project of the last session.
+    pub fn restore_recovery(
+        &mut self,
+        recovery: Recovery,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let app = Application::from_yaml(&recovery.yaml)?;
+        let project = match &recovery.project {
+            Some(path) if path.extension().is_some_and(|e| e == PROJECT_EXTENSION) => {
+                Some(Project::open(path)?)
+            }
+            _ => None,
+        };
+        if let Some(project) = &project {
+            resolve_backgrounds(&app.root_map, project.dir());
+        }
+
+        self.poll_history();
+        self.history = match &recovery.project {
+            Some(path) => PlayHistory::load(path),
+            None => PlayHistory::default(),
+        };
+        self.replace(app);
+        self.close_project();
+        self.project = project;
+        self.autosave.reset(&recovery);
+        self.set_path(recovery.project);
+        Ok(())
+    }
+
+    /// Make paths of storages, tracks and backgrounds relative to save the
+    /// project to the directory. `resolve_paths` makes them absolute again.
+    fn make_portable(&self, dir: &Path) {
+        // Tracks first, they are relative to absolute storage roots.
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            self.map_track_filename(audio, &|storage, filename| {
+                storage.portable_filename(filename, dir)
+            });
+        });
+        for storage in &self.storages {
+            storage.borrow_mut().make_portable(dir);
+        }
+        self.root_map.borrow_mut().walk_scenes(&mut |scene| {
+            let background = scene.get_background_path();
+            if let Some(relative) = background.and_then(|b| portable::relative_to(&b, dir)) {
+                scene.set_background_path(relative);
+            }
+        });
+    }
+
+    /// Make paths of project in the directory absolute.
+    fn resolve_paths(&self, dir: &Path) {
+        resolve_backgrounds(&self.root_map, dir);
+        for storage in &self.storages {
+            storage.borrow_mut().resolve_paths(dir);
+        }
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            self.map_track_filename(audio, &|storage, filename| {
+                storage.absolute_filename(filename, dir)
+            });
+        });
+    }
+
+    /// Change filename of track source by its storage.
+    fn map_track_filename(&self, audio: &Audio, f: &dyn Fn(&Storage, &str) -> String) {
+        let source = audio.borrow().get_source();
+        let Ok(mut source) = source else {
+            return;
+        };
+        let storage = self
+            .storages
+            .iter()
+            .find(|s| s.borrow().get_id() == source.get_storage());
+        if let Some(storage) = storage {
+            source.set_filename(f(&storage.borrow(), &source.get_filename()));
+            audio.borrow_mut().set_source(source);
+        }
+    }
+
+    /// Project of any schema version. Tracks of old projects get storages
+    /// of their files, so their paths could be remapped.
+    fn from_yaml(text: &str) -> Result<Application, schema::SchemaError> {
+        let app: Application = schema::from_yaml(text)?;
+        app.root_map.borrow().walk_audio(&mut |audio| {
+            let source = audio.borrow().get_source();
+            if let Ok(mut source) = source
+                && source.get_storage().is_empty()
+                && let Some(storage) = app
+                    .storages
+                    .iter()
+                    .find(|s| s.borrow().owns_file(&source.get_filename()))
+            {
+                source.set_storage(storage.borrow().get_id());
+                audio.borrow_mut().set_source(source);
+            }
+        });
+        Ok(app)
+    }
+
+    /// Local storages which folders are not found.
+    pub fn missing_roots(&self) -> Vec<(usize, PathBuf)> {
+        self.storages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((i, s.borrow().get_missing_root()?)))
+            .collect()
+    }
+
+    /// Move storage to other folder, tracks of its sources are moved too.
+    pub fn remap_root(&mut self, index: usize, root: PathBuf) {
+        let Some(storage) = self.storages.get(index) else {
+            return;
+        };
+        let Some(old_root) = storage.borrow_mut().remap_root(root.clone()) else {
+            return;
+        };
+        let id = storage.borrow().get_id();
+        self.root_map.borrow().walk_audio(&mut |audio| {
+            let source = audio.borrow().get_source();
+            if let Ok(mut source) = source
+                && source.get_storage() == id
+            {
+                let filename = portable::remap_filename(&source.get_filename(), &old_root, &root);
+                source.set_filename(filename);
+                audio.borrow_mut().set_source(source);
+            }
+        });
+    }
+
+    /// Change project file, storages keep their metadata cache next to it.
+    fn set_path(&mut self, path: Option<PathBuf>) {
+        for storage in &self.storages {
+            storage.borrow_mut().set_project(path.clone());
+        }
+        self.path = path;
+    }
+
+    /// Remove unpacked files of the open project file.
+    fn close_project(&mut self) {
+        if let Some(project) = self.project.take()
+            && let Err(e) = project.close()
+        {
+            eprintln!("Error closing project: {}", e);
+        }
+    }
+
     pub fn open_local_project(&mut self, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        self.close_project();
+        self.poll_history();
+        // Played tracks of folder without project are not saved to it.
+        self.history = PlayHistory::default();
+        self.set_path(None);
         match find_yaml_files(&path) {
             Ok(files) => {
                 if files.is_empty() {
-                    self.storage
-                        .borrow_mut()
-                        .setup_storage(StorageCredentials::Local(path));
+                    // Folder without project becomes the only storage.
+                    let import = self.storages.first().map(|s| s.borrow().get_import_options());
+                    self.storages.clear();
+                    self.add_storage(StorageCredentials::Local(path), String::new());
+                    if let Some(import) = import {
+                        self.storages[0].borrow_mut().set_import_options(import);
+                    }
                 } else {
                     let s = fs::read_to_string(&files[0]).unwrap();
-                    match serde_yaml::from_str::<Application>(s.as_str()) {
+                    match Application::from_yaml(s.as_str()) {
                         Ok(app) => {
+                            app.resolve_paths(&path);
                             self.replace(app);
+                            self.history = PlayHistory::load(&files[0]);
+                            self.set_path(Some(files[0].clone()));
                         }
                         Err(e) => return Err(Box::new(e)),
                     }
@@ -170,15 +792,20 @@ impl Application {
             }
             Err(e) => eprintln!("Error reading directory: {}", e),
         }
+        self.mark_saved();
         Ok(())
     }
 
     fn replace(&mut self, app: Application) {
+        self.undo.clear();
         self.current_playing.replace(None);
         self.player.borrow_mut().reset();
         self.root_map = app.root_map;
         self.selected_playlist.replace(None);
-        self.storage = app.storage;
+        self.storages = app.storages;
+        for storage in &self.storages {
+            storage.borrow_mut().restore();
+        }
 
         let current = Some(Rc::clone(&self.root_map));
         restore_map(&mut self.root_map, None, current);
@@ -192,7 +819,12 @@ fn find_yaml_files(dir_path: &PathBuf) -> io::Result<Vec<std::path::PathBuf>> {
         let entry = entry?;
         let path = entry.path();
 
+        // Hidden files are not projects, e.g. metadata cache of old versions.
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
         if path.is_file()
+            && !hidden
             && let Some(extension) = path.extension()
             && (extension == "yaml" || extension == "yml")
         {
@@ -200,9 +832,21 @@ fn find_yaml_files(dir_path: &PathBuf) -> io::Result<Vec<std::path::PathBuf>> {
         }
     }
 
+    yaml_files.sort();
     Ok(yaml_files)
 }
 
+/// Make relative background paths absolute in the directory.
+fn resolve_backgrounds(root_map: &Rc<RefCell<Scene>>, dir: &Path) {
+    root_map.borrow_mut().walk_scenes(&mut |scene| {
+        if let Some(background) = scene.get_background_path()
+            && background.is_relative()
+        {
+            scene.set_background_path(dir.join(background));
+        }
+    });
+}
+
 fn restore_map(
     map: &mut Rc<RefCell<Scene>>,
     parent: Option<Rc<RefCell<Scene>>>,
@@ -215,3 +859,162 @@ fn restore_map(
         restore_map(&mut m, current.clone(), next);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::track::Track;
+
+    fn application(storage: &str, dir: &Path) -> Application {
+        let mut storage: Storage = serde_yaml::from_str(storage).unwrap();
+        storage.restore();
+        let mut app = Application::new(
+            vec![Rc::new(RefCell::new(storage))],
+            Rc::new(RefCell::new(Scene::new(None))),
+            Rc::new(RefCell::new(Player::new())),
+        );
+        // Recovery files of the user are not touched.
+        app.autosave = Autosave::new(dir.join("recovery"));
+        app
+    }
+
+    #[test]
+    fn merge_exact_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = application(
+            "title: Weather
+credentials: null
+sources:
+- {id: a1, size: 5, filename: /music/rain.ogg, title: rain, tags: []}
+- {id: a1, size: 5, filename: /music/copy/rain.ogg, title: rain, tags: []}
+tags: []
+",
+            dir.path(),
+        );
+        let copy = app.storages[0].borrow().get(1).unwrap();
+        let track: Audio = Rc::new(RefCell::new(Box::new(Track::new(copy))));
+        app.root_map.borrow_mut().insert_audio(0, Rc::clone(&track));
+
+        assert_eq!(app.merge_duplicates(0, 0, vec![1], true), 1);
+        let source = track.borrow().get_source().unwrap();
+        assert_eq!(source.get_filename(), "/music/rain.ogg");
+        assert_eq!(app.storages[0].borrow().hidden_count(), 1);
+
+        // Merge is undone with the track.
+        assert!(app.undo());
+        let source = track.borrow().get_source().unwrap();
+        assert_eq!(source.get_filename(), "/music/copy/rain.ogg");
+        assert_eq!(app.storages[0].borrow().hidden_count(), 0);
+    }
+
+    #[test]
+    fn remap_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = application(
+            "title: Forest
+credentials: !Local /missing/Music
+sources:
+- {id: a1, size: 5, filename: /missing/Music/Forest/birds.ogg, title: birds, tags: []}
+tags: []
+",
+            dir.path(),
+        );
+        let birds = app.storages[0].borrow().get(0).unwrap();
+        let track: Audio = Rc::new(RefCell::new(Box::new(Track::new(birds))));
+        app.root_map.borrow_mut().insert_audio(0, Rc::clone(&track));
+        assert_eq!(
+            app.missing_roots(),
+            vec![(0, PathBuf::from("/missing/Music"))]
+        );
+
+        app.remap_root(0, dir.path().to_path_buf());
+        let moved = dir.path().join("Forest").join("birds.ogg");
+        let source = app.storages[0].borrow().get(0).unwrap();
+        assert_eq!(Path::new(&source.get_filename()), moved);
+        let source = track.borrow().get_source().unwrap();
+        assert_eq!(Path::new(&source.get_filename()), moved);
+        assert!(app.missing_roots().is_empty());
+    }
+
+    #[test]
+    fn remap_tracks_of_old_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = Application::from_yaml(include_str!("../fixtures/project_v0.yaml")).unwrap();
+        // Track of a folder, which is not a storage source.
+        let source = Source::new("/home/gm/Music/Forest/birds.ogg".to_string(), "birds".into());
+        let track: Audio = Rc::new(RefCell::new(Box::new(Track::new(source))));
+        app.root_map.borrow_mut().insert_audio(0, Rc::clone(&track));
+        let app_yaml = schema::to_yaml(&app).unwrap();
+        let mut app = Application::from_yaml(&app_yaml).unwrap();
+        app.storages[0].borrow_mut().restore();
+
+        app.remap_root(0, dir.path().to_path_buf());
+        let birds = app.root_map.borrow().get_audio(0);
+        let filename = birds.borrow().get_source().unwrap().get_filename();
+        assert_eq!(Path::new(&filename), dir.path().join("Forest").join("birds.ogg"));
+        let playlist = app.root_map.borrow().get_audio(1);
+        let tavern = playlist.borrow().get_audio("Music", 0).unwrap();
+        let filename = tavern.borrow().get_source().unwrap().get_filename();
+        assert_eq!(Path::new(&filename), dir.path().join("tavern.ogg"));
+    }
+
+    #[test]
+    fn save_and_open_project_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let music = dir.path().join("rain.ogg");
+        fs::write(&music, b"audio").unwrap();
+        let image = dir.path().join("map.png");
+        fs::write(&image, b"image").unwrap();
+        let mut app = application(
+            &format!(
+                "title: Weather
+credentials: null
+sources:
+- {{id: a1, size: 5, filename: '{}', title: rain, tags: []}}
+tags: []
+",
+                music.display()
+            ),
+            dir.path(),
+        );
+        let rain = app.storages[0].borrow().get(0).unwrap();
+        let track: Audio = Rc::new(RefCell::new(Box::new(Track::new(rain))));
+        app.root_map.borrow_mut().insert_audio(0, track);
+        app.root_map.borrow_mut().set_background_path(image.clone());
+
+        let path = dir.path().join(format!("Campaign.{PROJECT_EXTENSION}"));
+        app.save_project(path.clone(), true).unwrap();
+        fs::remove_file(&music).unwrap();
+        fs::remove_file(&image).unwrap();
+
+        // Files are taken from the project, tracks are relinked to audio
+        // in the project file.
+        let empty = "title: Empty\ncredentials: null\nsources: []\ntags: []\n";
+        let mut opened = application(empty, dir.path());
+        opened.open_project_file(path.clone()).unwrap();
+        let background = opened.root_map.borrow().get_background_path().unwrap();
+        assert_eq!(fs::read(background).unwrap(), b"image");
+        let archive = |app: &Application| -> PathBuf {
+            let track = app.root_map.borrow().get_audio(0);
+            let source = track.borrow().get_source().unwrap();
+            assert!(!source.is_missing());
+            zipstorage::split_filename(&source.get_filename())
+                .unwrap()
+                .0
+        };
+        assert_eq!(archive(&opened), path);
+
+        // Embedded audio in use is kept without embedding.
+        opened.save_project(path.clone(), false).unwrap();
+        opened.open_project_file(path.clone()).unwrap();
+        assert_eq!(archive(&opened), path);
+
+        // Tracks follow the project saved to other file.
+        let copy = dir.path().join(format!("Copy.{PROJECT_EXTENSION}"));
+        opened.save_project(copy.clone(), false).unwrap();
+        assert_eq!(archive(&opened), copy);
+        fs::remove_file(&path).unwrap();
+        opened.open_project_file(copy.clone()).unwrap();
+        assert_eq!(archive(&opened), copy);
+    }
+}
@@ -15,6 +15,8 @@
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
 pub mod playlist;
+pub mod shared;
+pub mod smartthread;
 pub mod track;
 
 use std::cell::RefCell;
@@ -22,12 +24,21 @@ use std::rc::Rc;
 
 use serde::{Deserialize, Serialize};
 
-use crate::storage::source::Source;
-use crate::stream::Stream;
+use crate::history::PlayHistory;
+use crate::storage::{Storage, source::Source};
+use crate::stream::{Stream, ThreadOrder};
 
 pub type Audio = Rc<RefCell<Box<dyn RawAudio>>>;
 pub type AudioCell = Rc<RefCell<Option<Audio>>>;
 
+/// Project data streams are built with.
+pub struct StreamContext<'a> {
+    /// Storages smart threads find sources in.
+    pub storages: &'a [Rc<RefCell<Storage>>],
+    /// Play history of the project to order threads.
+    pub history: &'a PlayHistory,
+}
+
 /// Audio trait. Describe Track and Playlist interface.
 #[typetag::serde(tag = "type")]
 pub trait RawAudio: erased_serde::Serialize {
@@ -35,26 +46,53 @@ pub trait RawAudio: erased_serde::Serialize {
     fn set_title(&mut self, title: String);
     fn get_source(&self) -> Result<Source, AudioError>;
     fn set_source(&mut self, source: Source);
+    /// Storage query of smart thread, see `storage::query`.
+    fn get_query(&self) -> Result<String, AudioError>;
+    fn set_query(&mut self, query: String);
     fn get_volume(&self) -> f32;
     fn set_volume(&mut self, volume: f32);
-    fn get_stream(&self) -> Stream;
+    /// Stream of the audio, smart threads find sources in the storages.
+    fn get_stream(&self, context: &StreamContext) -> Stream;
+    /// Id of playlist for the play history, titles could be the same.
+    fn get_id(&self) -> Result<String, AudioError>;
 
     fn push_thread(&mut self, caption: &str) -> Result<(), AudioError>;
+    /// Insert empty thread at the position, error if the thread exists.
+    fn insert_thread(&mut self, index: usize, caption: &str) -> Result<(), AudioError>;
     fn rename_thread(&mut self, old_caption: &str, new_caption: &str);
     fn remove_thread(&mut self, caption: &str);
     fn threads(&self) -> Result<Vec<String>, AudioError>;
+    fn get_thread_order(&self, thread: &str) -> ThreadOrder;
+    fn set_thread_order(&mut self, thread: &str, order: ThreadOrder);
     fn index_of_thread(&self, name: &str) -> usize;
     fn is_thread_empty(&self, name: &str) -> bool;
 
     fn push_audio(&mut self, thread: &str, audio: Audio) -> Result<(), AudioError>;
+    fn insert_audio(&mut self, thread: &str, index: usize, audio: Audio) -> Result<(), AudioError>;
     fn remove_audio(&mut self, thread: &str, index: usize) -> Result<(), AudioError>;
     fn get_audio(&self, thread: &str, index: usize) -> Result<Audio, AudioError>;
     fn audio_count(&self, thread: &str) -> usize;
 }
 
+/// Call `f` for audio and all nested audio.
+pub fn walk(audio: &Audio, f: &mut dyn FnMut(&Audio)) {
+    f(audio);
+    let threads = audio.borrow().threads().unwrap_or_default();
+    for thread in threads {
+        let count = audio.borrow().audio_count(&thread);
+        for i in 0..count {
+            let child = audio.borrow().get_audio(&thread, i);
+            if let Ok(child) = child {
+                walk(&child, f);
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum AudioError {
     NotAPlaylist,
     NotATrack,
+    NotASmartThread,
     OutOfRange,
 }
@@ -14,20 +14,30 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-use crate::audio::{Audio, AudioError, RawAudio};
+use crate::audio::{Audio, AudioError, RawAudio, StreamContext, shared};
 use crate::storage::source::Source;
-use crate::stream::Stream;
+use crate::stream::{Stream, ThreadOrder};
 
 /// Playlist is container for other playlists and tracks.
 /// Contains common settings for group of music and procedure summary Stream.
 /// Playlist implements Audio trait.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Playlist {
+    /// Playlists of old projects get ids on loading. Not `id`, it is the
+    /// key of shared audio, see `audio::shared`.
+    #[serde(default = "new_playlist_id")]
+    playlist_id: String,
     volume: f32,
     title: String,
+    #[serde(with = "shared::threads")]
     threads: Vec<(String, Vec<Audio>)>,
+    /// Play order of threads, sequential if not set.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    orders: BTreeMap<String, ThreadOrder>,
 }
 
 impl Playlist {
@@ -35,8 +45,10 @@ impl Playlist {
         let title = t!("new_playlist_name").to_string();
 
         Playlist {
+            playlist_id: new_playlist_id(),
             volume: 1.0,
             threads: Vec::new(),
+            orders: BTreeMap::new(),
             title,
         }
     }
@@ -50,6 +62,11 @@ impl Playlist {
     }
 }
 
+fn new_playlist_id() -> String {
+    let bytes: [u8; 8] = rand::random();
+    hex::encode(bytes)
+}
+
 #[typetag::serde]
 impl RawAudio for Playlist {
     fn get_title(&self) -> String {
@@ -68,6 +85,14 @@ impl RawAudio for Playlist {
         // Not implemented for playlist
     }
 
+    fn get_query(&self) -> Result<String, AudioError> {
+        Err(AudioError::NotASmartThread)
+    }
+
+    fn set_query(&mut self, _: String) {
+        // Not implemented for playlist
+    }
+
     fn get_volume(&self) -> f32 {
         self.volume
     }
@@ -76,19 +101,25 @@ impl RawAudio for Playlist {
         self.volume = volume.clamp(0.0, 1.0);
     }
 
-    fn get_stream(&self) -> Stream {
+    fn get_stream(&self, context: &StreamContext) -> Stream {
         let mut stream = Stream::new(vec![], self.volume);
 
-        for (_, pl) in self.threads.iter() {
+        for (caption, pl) in self.threads.iter() {
             let mut substream = Stream::new(vec![], self.volume);
             for audio in pl {
-                substream.merge(audio.borrow().get_stream());
+                substream.merge(audio.borrow().get_stream(context));
             }
+            substream.set_order(self.get_thread_order(caption), context.history);
+            substream.set_thread_id(caption);
             stream.merge_parallel(substream);
         }
         stream
     }
 
+    fn get_id(&self) -> Result<String, AudioError> {
+        Ok(self.playlist_id.clone())
+    }
+
     fn push_thread(&mut self, caption: &str) -> Result<(), AudioError> {
         if !self.contains_thread(caption) {
             self.threads.push((caption.to_string(), Vec::new()));
@@ -96,12 +127,25 @@ impl RawAudio for Playlist {
         Ok(())
     }
 
+    fn insert_thread(&mut self, index: usize, caption: &str) -> Result<(), AudioError> {
+        if self.contains_thread(caption) {
+            return Err(AudioError::OutOfRange);
+        }
+        let index = index.min(self.threads.len());
+        self.threads.insert(index, (caption.to_string(), Vec::new()));
+        Ok(())
+    }
+
     fn remove_thread(&mut self, caption: &str) {
         self.threads.retain(|th| th.0 != caption);
+        self.orders.remove(caption);
     }
 
     fn rename_thread(&mut self, old_caption: &str, new_caption: &str) {
         if !self.contains_thread(new_caption) {
+            if let Some(order) = self.orders.remove(old_caption) {
+                self.orders.insert(new_caption.to_string(), order);
+            }
             for thread in self.threads.iter_mut() {
                 if thread.0 == old_caption {
                     thread.0 = new_caption.to_string();
@@ -114,6 +158,18 @@ impl RawAudio for Playlist {
         Ok(self.threads.iter().map(|k| k.0.clone()).collect())
     }
 
+    fn get_thread_order(&self, thread: &str) -> ThreadOrder {
+        self.orders.get(thread).copied().unwrap_or_default()
+    }
+
+    fn set_thread_order(&mut self, thread: &str, order: ThreadOrder) {
+        if order == ThreadOrder::Sequential {
+            self.orders.remove(thread);
+        } else if self.contains_thread(thread) {
+            self.orders.insert(thread.to_string(), order);
+        }
+    }
+
     fn index_of_thread(&self, name: &str) -> usize {
         self.find_thread(name).unwrap_or(0)
     }
@@ -132,6 +188,16 @@ impl RawAudio for Playlist {
         }
     }
 
+    fn insert_audio(&mut self, thread: &str, index: usize, audio: Audio) -> Result<(), AudioError> {
+        match self.find_thread(thread) {
+            Some(i) if index <= self.threads[i].1.len() => {
+                self.threads[i].1.insert(index, audio);
+                Ok(())
+            }
+            _ => Err(AudioError::OutOfRange),
+        }
+    }
+
     fn remove_audio(&mut self, thread: &str, index: usize) -> Result<(), AudioError> {
         match self.find_thread(thread) {
             Some(i) => {
@@ -0,0 +1,276 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+//! Audio shared by several scenes or playlists. The first occurrence of
+//! shared audio is saved with `id`, the next ones as `ref: <id>`, and they
+//! are the same audio again after loading. Ids are written and read only
+//! inside `write_shared` and `read_shared`, other audio is saved by value.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::Error as _,
+    ser::{Error as _, SerializeSeq},
+};
+use serde_yaml::{Mapping, Value};
+
+use crate::audio::{Audio, RawAudio};
+
+const ID_KEY: &str = "id";
+const REF_KEY: &str = "ref";
+
+enum State {
+    /// First pass: occurrences of every audio.
+    Counting(HashMap<*const (), usize>),
+    /// Second pass: audio met several times get ids.
+    Writing {
+        counts: HashMap<*const (), usize>,
+        ids: HashMap<*const (), u64>,
+    },
+    Reading(HashMap<u64, Audio>),
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// Serialize with ids of shared audio. `f` is called twice: to find
+/// shared audio and to write it.
+pub fn write_shared<T>(f: impl Fn() -> T) -> T {
+    let previous = STATE.replace(Some(State::Counting(HashMap::new())));
+    f();
+    let counts = match STATE.take() {
+        Some(State::Counting(counts)) => counts,
+        _ => HashMap::new(),
+    };
+    STATE.set(Some(State::Writing {
+        counts,
+        ids: HashMap::new(),
+    }));
+    let result = f();
+    STATE.set(previous);
+    result
+}
+
+/// Deserialize restoring shared audio from ids.
+pub fn read_shared<T>(f: impl FnOnce() -> T) -> T {
+    let previous = STATE.replace(Some(State::Reading(HashMap::new())));
+    let result = f();
+    STATE.set(previous);
+    result
+}
+
+/// How to write the audio met once more.
+enum Occurrence {
+    ByValue,
+    First(u64),
+    Repeated(u64),
+}
+
+fn occurrence(audio: &Audio) -> Occurrence {
+    let key = Rc::as_ptr(audio) as *const ();
+    STATE.with_borrow_mut(|state| match state {
+        Some(State::Counting(counts)) => {
+            let count = counts.entry(key).or_default();
+            *count += 1;
+            // Children of repeated audio are counted once.
+            if *count > 1 {
+                Occurrence::Repeated(0)
+            } else {
+                Occurrence::ByValue
+            }
+        }
+        Some(State::Writing { counts, ids }) => {
+            if let Some(&id) = ids.get(&key) {
+                Occurrence::Repeated(id)
+            } else if counts.get(&key).is_some_and(|&count| count > 1) {
+                let id = ids.len() as u64 + 1;
+                ids.insert(key, id);
+                Occurrence::First(id)
+            } else {
+                Occurrence::ByValue
+            }
+        }
+        _ => Occurrence::ByValue,
+    })
+}
+
+/// Audio saved by value or by id.
+struct SharedAudio<'a>(&'a Audio);
+
+impl Serialize for SharedAudio<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match occurrence(self.0) {
+            Occurrence::ByValue => self.0.serialize(serializer),
+            Occurrence::First(id) => {
+                let Value::Mapping(fields) =
+                    serde_yaml::to_value(self.0).map_err(S::Error::custom)?
+                else {
+                    return Err(S::Error::custom("audio is not a mapping"));
+                };
+                let mut audio = Mapping::new();
+                audio.insert(ID_KEY.into(), id.into());
+                audio.extend(fields);
+                audio.serialize(serializer)
+            }
+            Occurrence::Repeated(id) => {
+                let mut audio = Mapping::new();
+                audio.insert(REF_KEY.into(), id.into());
+                audio.serialize(serializer)
+            }
+        }
+    }
+}
+
+/// Audio read by value or restored by id.
+struct LoadedAudio(Audio);
+
+impl<'de> Deserialize<'de> for LoadedAudio {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let mut id = None;
+        if let Value::Mapping(fields) = &mut value {
+            if let Some(reference) = fields.get(REF_KEY) {
+                let reference = reference
+                    .as_u64()
+                    .ok_or_else(|| D::Error::custom("wrong shared audio ref"))?;
+                let audio = STATE.with_borrow(|state| match state {
+                    Some(State::Reading(loaded)) => loaded.get(&reference).cloned(),
+                    _ => None,
+                });
+                return audio
+                    .map(LoadedAudio)
+                    .ok_or_else(|| D::Error::custom(format!("unknown shared audio {reference}")));
+            }
+            id = fields.remove(ID_KEY).and_then(|id| id.as_u64());
+        }
+
+        let raw: Box<dyn RawAudio> = serde_yaml::from_value(value).map_err(D::Error::custom)?;
+        let audio: Audio = Rc::new(RefCell::new(raw));
+        if let Some(id) = id {
+            STATE.with_borrow_mut(|state| {
+                if let Some(State::Reading(loaded)) = state {
+                    loaded.insert(id, Rc::clone(&audio));
+                }
+            });
+        }
+        Ok(LoadedAudio(audio))
+    }
+}
+
+/// `#[serde(with)]` for audio lists.
+pub mod list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(list: &[Audio], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(list.len()))?;
+        for audio in list {
+            seq.serialize_element(&SharedAudio(audio))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Audio>, D::Error> {
+        let list = Vec::<LoadedAudio>::deserialize(deserializer)?;
+        Ok(list.into_iter().map(|audio| audio.0).collect())
+    }
+}
+
+/// `#[serde(with)]` for named threads of playlist.
+pub mod threads {
+    use super::*;
+
+    type Threads = Vec<(String, Vec<Audio>)>;
+
+    pub fn serialize<S: Serializer>(threads: &Threads, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(threads.len()))?;
+        for (caption, list) in threads {
+            let list: Vec<SharedAudio> = list.iter().map(SharedAudio).collect();
+            seq.serialize_element(&(caption, list))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Threads, D::Error> {
+        let threads = Vec::<(String, Vec<LoadedAudio>)>::deserialize(deserializer)?;
+        Ok(threads
+            .into_iter()
+            .map(|(caption, list)| (caption, list.into_iter().map(|audio| audio.0).collect()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::{playlist::Playlist, track::Track},
+        scene::{Point, Scene},
+        schema,
+        storage::source::Source,
+    };
+
+    #[test]
+    fn shared_audio_after_reload() {
+        let source = Source::new("/music/rain.ogg".to_string(), "rain".to_string());
+        let track: Audio = Rc::new(RefCell::new(Box::new(Track::new(source))));
+        let playlist: Audio = Rc::new(RefCell::new(Box::new(Playlist::new())));
+        playlist.borrow_mut().push_thread("Weather").unwrap();
+        playlist.borrow_mut().push_thread("Ambient").unwrap();
+        playlist
+            .borrow_mut()
+            .push_audio("Weather", Rc::clone(&track))
+            .unwrap();
+        playlist
+            .borrow_mut()
+            .push_audio("Ambient", Rc::clone(&track))
+            .unwrap();
+        let root = Rc::new(RefCell::new(Scene::new(None)));
+        let child = Rc::new(RefCell::new(Scene::new(Some(Rc::clone(&root)))));
+        root.borrow_mut().insert_audio(0, Rc::clone(&playlist));
+        child.borrow_mut().insert_audio(0, Rc::clone(&playlist));
+        root.borrow_mut()
+            .insert_map(Point { x: 0.5, y: 0.5 }, Rc::clone(&child));
+
+        let yaml = schema::to_yaml(&*root.borrow()).unwrap();
+        assert_eq!(yaml.matches("type: Playlist").count(), 1);
+        assert_eq!(yaml.matches("type: Track").count(), 1);
+        let loaded: Scene = schema::from_yaml(&yaml).unwrap();
+
+        let playlist = loaded.get_audio(0);
+        let child = loaded.get_map(&Point { x: 0.5, y: 0.5 }).unwrap();
+        playlist.borrow_mut().set_title("Storm".to_string());
+        assert_eq!(child.borrow().get_audio(0).borrow().get_title(), "Storm");
+        let track = playlist.borrow().get_audio("Weather", 0).unwrap();
+        track.borrow_mut().set_volume(0.3);
+        let copy = playlist.borrow().get_audio("Ambient", 0).unwrap();
+        assert_eq!(copy.borrow().get_volume(), 0.3);
+
+        // Audio of old projects and unknown refs.
+        let unshared = serde_yaml::to_string(&*root.borrow()).unwrap();
+        let loaded: Scene = schema::from_yaml(&unshared).unwrap();
+        assert!(!Rc::ptr_eq(
+            &loaded.get_audio(0),
+            &loaded
+                .get_map(&Point { x: 0.5, y: 0.5 })
+                .unwrap()
+                .borrow()
+                .get_audio(0)
+        ));
+        assert!(schema::from_yaml::<Scene>("audio:\n- ref: 7\nmaps: {}\n").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+use std::{cell::RefCell, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{Audio, AudioError, RawAudio, StreamContext};
+use crate::storage::{self, Storage, source::Source};
+use crate::stream::{Stream, ThreadOrder};
+
+/// Smart thread plays storage sources matched the query, like
+/// `tag:tavern -tag:vocals`. Only the query is saved, sources are found
+/// again in project storages on every stream building.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SmartThread {
+    title: String,
+    volume: f32,
+    query: String,
+}
+
+impl SmartThread {
+    pub fn new(query: String) -> SmartThread {
+        SmartThread {
+            title: query.clone(),
+            volume: 1.0,
+            query,
+        }
+    }
+
+    /// Sources the query matches now.
+    pub fn sources(&self, storages: &[Rc<RefCell<Storage>>]) -> Vec<Source> {
+        storage::find_sources(storages, &self.query)
+    }
+}
+
+#[typetag::serde]
+impl RawAudio for SmartThread {
+    fn get_title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn set_title(&mut self, title: String) {
+        self.title = title
+    }
+
+    fn get_source(&self) -> Result<Source, AudioError> {
+        Err(AudioError::NotATrack)
+    }
+
+    fn set_source(&mut self, _source: Source) {
+        // Not implemented for smart thread
+    }
+
+    fn get_query(&self) -> Result<String, AudioError> {
+        Ok(self.query.clone())
+    }
+
+    fn set_query(&mut self, query: String) {
+        self.query = query;
+    }
+
+    fn get_volume(&self) -> f32 {
+        self.volume
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    fn get_stream(&self, context: &StreamContext) -> Stream {
+        let sources = self.sources(context.storages).iter().map(|s| s.opener()).collect();
+        Stream::from_sources(sources, self.volume)
+    }
+
+    fn get_id(&self) -> Result<String, AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn push_thread(&mut self, _caption: &str) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn insert_thread(&mut self, _index: usize, _caption: &str) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn rename_thread(&mut self, _old_caption: &str, _new_caption: &str) {
+        // Not implemented for smart thread
+    }
+
+    fn remove_thread(&mut self, _caption: &str) {
+        // Not implemented for smart thread
+    }
+
+    fn threads(&self) -> Result<Vec<String>, AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn get_thread_order(&self, _thread: &str) -> ThreadOrder {
+        ThreadOrder::Sequential
+    }
+
+    fn set_thread_order(&mut self, _thread: &str, _order: ThreadOrder) {
+        // Not implemented for smart thread
+    }
+
+    fn index_of_thread(&self, _name: &str) -> usize {
+        0
+    }
+
+    fn is_thread_empty(&self, _name: &str) -> bool {
+        true
+    }
+
+    fn push_audio(&mut self, _thread: &str, _audio: Audio) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn insert_audio(
+        &mut self,
+        _thread: &str,
+        _index: usize,
+        _audio: Audio,
+    ) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn remove_audio(&mut self, _thread: &str, _index: usize) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn get_audio(&self, _thread: &str, _index: usize) -> Result<Audio, AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
+    fn audio_count(&self, _thread: &str) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_as_query() {
+        let mut thread = SmartThread::new("tag:tavern -tag:vocals".to_string());
+        thread.set_volume(0.5);
+        let audio: Box<dyn RawAudio> = Box::new(thread);
+        let yaml = serde_yaml::to_string(&audio).unwrap();
+        assert_eq!(
+            yaml,
+            "type: SmartThread\ntitle: tag:tavern -tag:vocals\nvolume: 0.5\n\
+             query: tag:tavern -tag:vocals\n"
+        );
+
+        let audio: Box<dyn RawAudio> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(audio.get_query().unwrap(), "tag:tavern -tag:vocals");
+        assert!(audio.get_source().is_err());
+    }
+}
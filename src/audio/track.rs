@@ -16,9 +16,9 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::audio::{Audio, AudioError, RawAudio};
+use crate::audio::{Audio, AudioError, RawAudio, StreamContext};
 use crate::storage::source::Source;
-use crate::stream::Stream;
+use crate::stream::{Stream, ThreadOrder};
 
 /// Track is container one Stream and it's settings.
 /// Track implements Audio trait.
@@ -59,6 +59,14 @@ impl RawAudio for Track {
         self.source = source;
     }
 
+    fn get_query(&self) -> Result<String, AudioError> {
+        Err(AudioError::NotASmartThread)
+    }
+
+    fn set_query(&mut self, _: String) {
+        // Not implemented for track
+    }
+
     fn get_volume(&self) -> f32 {
         self.volume
     }
@@ -67,16 +75,24 @@ impl RawAudio for Track {
         self.volume = volume.clamp(0.0, 1.0);
     }
 
-    fn get_stream(&self) -> Stream {
+    fn get_stream(&self, _context: &StreamContext) -> Stream {
         let mut s = self.source.get_stream();
         s.set_partial_volume(self.volume, 0, 0);
         s
     }
 
+    fn get_id(&self) -> Result<String, AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
     fn push_thread(&mut self, _caption: &str) -> Result<(), AudioError> {
         Err(AudioError::NotAPlaylist)
     }
 
+    fn insert_thread(&mut self, _index: usize, _caption: &str) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
     fn rename_thread(&mut self, _old_caption: &str, _new_caption: &str) {
         // Not implemented for track
     }
@@ -89,6 +105,14 @@ impl RawAudio for Track {
         Err(AudioError::NotAPlaylist)
     }
 
+    fn get_thread_order(&self, _thread: &str) -> ThreadOrder {
+        ThreadOrder::Sequential
+    }
+
+    fn set_thread_order(&mut self, _thread: &str, _order: ThreadOrder) {
+        // Not implemented for track
+    }
+
     fn index_of_thread(&self, _name: &str) -> usize {
         0
     }
@@ -101,6 +125,15 @@ impl RawAudio for Track {
         Err(AudioError::NotAPlaylist)
     }
 
+    fn insert_audio(
+        &mut self,
+        _thread: &str,
+        _index: usize,
+        _audio: Audio,
+    ) -> Result<(), AudioError> {
+        Err(AudioError::NotAPlaylist)
+    }
+
     fn remove_audio(&mut self, _thread: &str, _index: usize) -> Result<(), AudioError> {
         Err(AudioError::NotAPlaylist)
     }
@@ -0,0 +1,340 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Crash recovery: changed project is periodically written to a recovery
+//! file in the user data directory, which is removed when the project is
+//! saved. Every project has its own recovery file, named by a hash of the
+//! project path, unsaved projects have one per instance. The instance
+//! writing a recovery file keeps it locked, so other instances do not
+//! offer to restore it. Saved project files are kept in rotating backups.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::history;
+
+const RECOVERY_DIR: &str = "recovery";
+const RECOVERY_EXTENSION: &str = "yaml";
+const LOCK_EXTENSION: &str = "lock";
+const BACKUPS_DIR: &str = "backups";
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Backups kept for every project file.
+const BACKUPS: usize = 5;
+
+/// Unsaved project.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Recovery {
+    /// Project file, None if the project was never saved.
+    pub project: Option<PathBuf>,
+    /// Unix time of the autosave.
+    pub saved: u64,
+    /// Project YAML.
+    pub yaml: String,
+    /// Recovery file.
+    #[serde(skip)]
+    pub file: PathBuf,
+}
+
+pub struct Autosave {
+    dir: PathBuf,
+    last_check: Instant,
+    /// Hash of the project without unsaved changes, taken on the first
+    /// check if None.
+    baseline: Option<[u8; 32]>,
+    /// Hash of the project in the recovery file.
+    written: Option<[u8; 32]>,
+    /// Recovery file of the instance and its lock.
+    file: Option<(PathBuf, File)>,
+    /// Instance id, names the recovery file of unsaved project.
+    instance: String,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        let dir = dirs::data_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("cyberbard")
+            .join(RECOVERY_DIR);
+        Autosave::new(dir)
+    }
+}
+
+impl Autosave {
+    pub fn new(dir: PathBuf) -> Autosave {
+        Autosave {
+            dir,
+            last_check: Instant::now(),
+            baseline: None,
+            written: None,
+            file: None,
+            instance: hex::encode(rand::random::<[u8; 8]>()),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_check.elapsed() >= AUTOSAVE_INTERVAL
+    }
+
+    /// Project has no unsaved changes, e.g. it is created, saved or opened.
+    pub fn set_baseline(&mut self, yaml: &str) {
+        self.baseline = Some(Sha256::digest(yaml).into());
+        self.written = None;
+        self.last_check = Instant::now();
+    }
+
+    /// Project is restored from the recovery file, which is kept until the
+    /// project is saved.
+    pub fn reset(&mut self, recovery: &Recovery) {
+        self.baseline = None;
+        self.written = None;
+        self.last_check = Instant::now();
+        self.file = lock(&recovery.file)
+            .ok()
+            .map(|lock| (recovery.file.clone(), lock));
+    }
+
+    /// Recovery files for the project, the first one not locked by other
+    /// instances is used. Same project open twice gets a file per instance.
+    fn candidates(&self, project: Option<&Path>) -> Vec<PathBuf> {
+        let names = match project {
+            Some(path) => {
+                let hash = Sha256::digest(path.as_os_str().as_encoded_bytes());
+                let hash = hex::encode(&hash[..8]);
+                vec![hash.clone(), format!("{hash}-{}", self.instance)]
+            }
+            None => vec![format!("unsaved-{}", self.instance)],
+        };
+        names
+            .into_iter()
+            .map(|name| self.dir.join(name).with_extension(RECOVERY_EXTENSION))
+            .collect()
+    }
+
+    /// Lock recovery file for the project, release the previous one.
+    fn take_file(&mut self, project: Option<&Path>) -> io::Result<PathBuf> {
+        let candidates = self.candidates(project);
+        if let Some((file, _)) = &self.file
+            && candidates.contains(file)
+        {
+            return Ok(file.clone());
+        }
+        for file in candidates {
+            let Ok(lock) = lock(&file) else {
+                continue;
+            };
+            // Recovery of the previous project is replaced by this one.
+            self.discard();
+            self.file = Some((file.clone(), lock));
+            return Ok(file);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "Recovery file is locked by another instance",
+        ))
+    }
+
+    /// Write recovery file if the project is changed since the baseline
+    /// and the last autosave. Return true if written.
+    pub fn autosave(&mut self, project: Option<PathBuf>, yaml: &str) -> io::Result<bool> {
+        self.last_check = Instant::now();
+        let hash: [u8; 32] = Sha256::digest(yaml).into();
+        if self.baseline.is_none() {
+            self.baseline = Some(hash);
+        }
+        if self.baseline == Some(hash) || self.written == Some(hash) {
+            return Ok(false);
+        }
+        fs::create_dir_all(&self.dir)?;
+        let file = self.take_file(project.as_deref())?;
+        let recovery = Recovery {
+            project,
+            saved: history::now(),
+            yaml: yaml.to_string(),
+            file: file.clone(),
+        };
+        let text = serde_yaml::to_string(&recovery).map_err(io::Error::other)?;
+        // Half-written file must not replace the previous recovery.
+        let partial = file.with_extension("part");
+        fs::write(&partial, text)?;
+        fs::rename(&partial, &file)?;
+        self.written = Some(hash);
+        Ok(true)
+    }
+
+    /// The newest recovery file, which is not locked by a running instance
+    /// and is newer than its project file.
+    pub fn find_recovery(&self) -> Option<Recovery> {
+        fs::read_dir(&self.dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|file| file.extension().is_some_and(|e| e == RECOVERY_EXTENSION))
+            .filter(|file| self.file.as_ref().is_none_or(|(own, _)| own != file))
+            .filter(|file| lock(file).is_ok())
+            .filter_map(|file| {
+                let text = fs::read_to_string(&file).ok()?;
+                let recovery: Recovery = serde_yaml::from_str(&text).ok()?;
+                Some(Recovery { file, ..recovery })
+            })
+            .filter(|recovery| {
+                let modified = recovery
+                    .project
+                    .as_ref()
+                    .and_then(|path| fs::metadata(path).ok()?.modified().ok())
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|time| time.as_secs());
+                modified.is_none_or(|modified| modified < recovery.saved)
+            })
+            .max_by_key(|recovery| recovery.saved)
+    }
+
+    /// Remove the recovery file of the instance.
+    pub fn discard(&mut self) {
+        if let Some((file, lock)) = self.file.take() {
+            drop(lock);
+            remove_recovery(&file);
+        }
+    }
+}
+
+/// Lock file of the recovery file, held while the instance runs.
+fn lock(file: &Path) -> io::Result<File> {
+    let lock = File::create(file.with_extension(LOCK_EXTENSION))?;
+    lock.try_lock()?;
+    Ok(lock)
+}
+
+/// Remove recovery file, e.g. not restored by the user.
+pub fn remove_recovery(file: &Path) {
+    for file in [file.to_path_buf(), file.with_extension(LOCK_EXTENSION)] {
+        match fs::remove_file(file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Error removing recovery file: {}", e)
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Keep copy of project file before it is overwritten. Backups are
+/// `backups/<name>.<n>.<extension>` near the project, 1 is the newest.
+pub fn backup(path: &Path) -> io::Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    let dir = path.parent().unwrap_or(Path::new("")).join(BACKUPS_DIR);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let backup = |n: usize| dir.join(format!("{stem}.{n}.{extension}"));
+
+    fs::create_dir_all(&dir)?;
+    if backup(BACKUPS).exists() {
+        fs::remove_file(backup(BACKUPS))?;
+    }
+    for n in (1..BACKUPS).rev() {
+        if backup(n).exists() {
+            fs::rename(backup(n), backup(n + 1))?;
+        }
+    }
+    fs::copy(path, backup(1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_and_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let recovery_dir = dir.path().join(RECOVERY_DIR);
+        let recoveries = || {
+            let files = fs::read_dir(&recovery_dir).unwrap();
+            let files = files.map(|e| e.unwrap().path());
+            files
+                .filter(|file| file.extension().is_some_and(|e| e == RECOVERY_EXTENSION))
+                .count()
+        };
+        let mut autosave = Autosave::new(recovery_dir.clone());
+        assert!(!autosave.is_due());
+        assert!(!autosave.autosave(None, "root_map: 1").unwrap());
+        assert!(autosave.autosave(None, "root_map: 2").unwrap());
+        assert!(!autosave.autosave(None, "root_map: 2").unwrap());
+
+        // Recovery of a running instance is not offered.
+        let mut restored = Autosave::new(recovery_dir.clone());
+        assert_eq!(restored.find_recovery(), None);
+        drop(autosave);
+        let recovery = restored.find_recovery().unwrap();
+        assert_eq!(recovery.yaml, "root_map: 2");
+
+        // Restored recovery is replaced by the recovery of saved project.
+        let project = dir.path().join("Campaign.yaml");
+        let saved = Some(project.clone());
+        restored.reset(&recovery);
+        assert!(!restored.autosave(None, "root_map: 2").unwrap());
+        assert!(recovery.file.exists());
+        assert!(restored.autosave(saved.clone(), "root_map: 3").unwrap());
+        assert!(!recovery.file.exists());
+        assert_eq!(recoveries(), 1);
+
+        // Same project in another instance has its own recovery.
+        let mut other = Autosave::new(recovery_dir.clone());
+        other.set_baseline("root_map: 3");
+        assert!(other.autosave(saved, "root_map: 4").unwrap());
+        assert_eq!(recoveries(), 2);
+
+        // Recovery older than the saved project is not offered.
+        fs::write(&project, "root_map: 2").unwrap();
+        let old = recovery_dir.join("old").with_extension(RECOVERY_EXTENSION);
+        let write_recovery = |saved| {
+            let recovery = Recovery {
+                project: Some(project.clone()),
+                saved,
+                yaml: "root_map: 3".to_string(),
+                file: PathBuf::new(),
+            };
+            fs::write(&old, serde_yaml::to_string(&recovery).unwrap()).unwrap();
+        };
+        let autosave = Autosave::new(recovery_dir.clone());
+        write_recovery(0);
+        assert_eq!(autosave.find_recovery(), None);
+        write_recovery(history::now() + 60);
+        assert_eq!(autosave.find_recovery().unwrap().file, old);
+
+        restored.discard();
+        other.discard();
+        remove_recovery(&old);
+        assert_eq!(fs::read_dir(&recovery_dir).unwrap().count(), 0);
+
+        for n in 0..BACKUPS + 2 {
+            fs::write(&project, format!("root_map: {n}")).unwrap();
+            backup(&project).unwrap();
+        }
+        let backups = dir.path().join(BACKUPS_DIR);
+        assert_eq!(fs::read_dir(&backups).unwrap().count(), BACKUPS);
+        let newest = fs::read_to_string(backups.join("Campaign.1.yaml")).unwrap();
+        assert_eq!(newest, format!("root_map: {}", BACKUPS + 1));
+        let oldest = fs::read_to_string(backups.join(format!("Campaign.{BACKUPS}.yaml")));
+        assert_eq!(oldest.unwrap(), "root_map: 2");
+    }
+}
@@ -0,0 +1,1036 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+//! Undoable editing of scenes, playlists, tags and storage settings.
+//! Every change is a
+//! command, its execution returns the inverse command, which is kept in
+//! the undo stack. Sources are kept by filenames, their indexes change
+//! on rescan.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    path::PathBuf,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audio::Audio,
+    scene::{Point, Scene},
+    storage::{ImportOptions, Storage, TagState, source::Source, tag::Tag},
+    stream::ThreadOrder,
+};
+
+/// Changes kept for undo.
+const UNDO_LIMIT: usize = 100;
+/// Changes of the same value in this interval, like typing or dragging
+/// a slider, are undone at once.
+const JOIN_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum Command {
+    /// Child scene at the point of the parent scene.
+    InsertScene {
+        parent: Rc<RefCell<Scene>>,
+        point: Point,
+        scene: Rc<RefCell<Scene>>,
+    },
+    RemoveScene {
+        parent: Rc<RefCell<Scene>>,
+        point: Point,
+    },
+    /// Background image of scene, removed if None.
+    SetBackground {
+        scene: Rc<RefCell<Scene>>,
+        path: Option<PathBuf>,
+    },
+    InsertPlaylist {
+        scene: Rc<RefCell<Scene>>,
+        index: usize,
+        playlist: Audio,
+    },
+    RemovePlaylist {
+        scene: Rc<RefCell<Scene>>,
+        index: usize,
+    },
+    SetTitle {
+        audio: Audio,
+        title: String,
+    },
+    SetVolume {
+        audio: Audio,
+        volume: f32,
+    },
+    /// Query of smart thread with its title.
+    SetQuery {
+        audio: Audio,
+        query: String,
+        title: String,
+    },
+    InsertThread {
+        playlist: Audio,
+        index: usize,
+        thread: String,
+        audio: Vec<Audio>,
+        order: ThreadOrder,
+    },
+    RemoveThread {
+        playlist: Audio,
+        thread: String,
+    },
+    RenameThread {
+        playlist: Audio,
+        old: String,
+        new: String,
+    },
+    SetThreadOrder {
+        playlist: Audio,
+        thread: String,
+        order: ThreadOrder,
+    },
+    InsertTrack {
+        playlist: Audio,
+        thread: String,
+        index: usize,
+        audio: Audio,
+    },
+    RemoveTrack {
+        playlist: Audio,
+        thread: String,
+        index: usize,
+    },
+    /// Source of track, e.g. merged duplicate.
+    SetSource {
+        audio: Audio,
+        source: Source,
+    },
+    /// Tag at the index attached to the sources.
+    InsertTag {
+        storage: Rc<RefCell<Storage>>,
+        index: usize,
+        tag: Tag,
+        sources: Vec<String>,
+    },
+    RemoveTag {
+        storage: Rc<RefCell<Storage>>,
+        tag: String,
+    },
+    RenameTag {
+        storage: Rc<RefCell<Storage>>,
+        old: String,
+        new: String,
+    },
+    SetTagColor {
+        storage: Rc<RefCell<Storage>>,
+        tag: String,
+        color: String,
+    },
+    SetTagCategory {
+        storage: Rc<RefCell<Storage>>,
+        tag: String,
+        category: String,
+    },
+    /// Attach tag to sources, the tag is created if not exists.
+    AttachTag {
+        storage: Rc<RefCell<Storage>>,
+        sources: Vec<String>,
+        tag: String,
+    },
+    UnattachTag {
+        storage: Rc<RefCell<Storage>>,
+        sources: Vec<String>,
+        tag: String,
+    },
+    /// Tags of storage and its sources, e.g. before import of tags.
+    SetTagState {
+        storage: Rc<RefCell<Storage>>,
+        state: TagState,
+    },
+    SetCaption {
+        storage: Rc<RefCell<Storage>>,
+        caption: String,
+    },
+    SetImportOptions {
+        storage: Rc<RefCell<Storage>>,
+        options: ImportOptions,
+    },
+    /// Keep played files of remote storage on disk.
+    SetDiskCache {
+        storage: Rc<RefCell<Storage>>,
+        disk_cache: bool,
+    },
+    /// Size limit of the disk cache, MiB.
+    SetDiskCacheLimit {
+        storage: Rc<RefCell<Storage>>,
+        limit: u64,
+    },
+    /// Password of remote storage, which is rescanned with it.
+    SetPassword {
+        storage: Rc<RefCell<Storage>>,
+        password: String,
+    },
+    /// Commands done and undone at once.
+    Batch(Vec<Command>),
+}
+
+impl Command {
+    /// Change the project. Return the inverse command, None if nothing is
+    /// changed.
+    pub fn execute(self) -> Option<Command> {
+        match self {
+            Command::InsertScene {
+                parent,
+                point,
+                scene,
+            } => {
+                let old = parent.borrow().get_map(&point);
+                parent.borrow_mut().insert_map(point.clone(), scene);
+                Some(match old {
+                    Some(scene) => Command::InsertScene {
+                        parent,
+                        point,
+                        scene,
+                    },
+                    None => Command::RemoveScene { parent, point },
+                })
+            }
+            Command::RemoveScene { parent, point } => {
+                let scene = parent.borrow().get_map(&point)?;
+                parent.borrow_mut().erase_map(point.clone());
+                Some(Command::InsertScene {
+                    parent,
+                    point,
+                    scene,
+                })
+            }
+            Command::SetBackground { scene, path } => {
+                let old = scene.borrow().get_background_path();
+                if old == path {
+                    return None;
+                }
+                scene.borrow_mut().reset_background(path);
+                Some(Command::SetBackground { scene, path: old })
+            }
+            Command::InsertPlaylist {
+                scene,
+                index,
+                playlist,
+            } => {
+                let index = index.min(scene.borrow().audio_count());
+                scene.borrow_mut().insert_audio(index, playlist);
+                Some(Command::RemovePlaylist { scene, index })
+            }
+            Command::RemovePlaylist { scene, index } => {
+                if index >= scene.borrow().audio_count() {
+                    return None;
+                }
+                let playlist = scene.borrow().get_audio(index);
+                scene.borrow_mut().erase_audio(index);
+                Some(Command::InsertPlaylist {
+                    scene,
+                    index,
+                    playlist,
+                })
+            }
+            Command::SetTitle { audio, title } => {
+                let old = audio.borrow().get_title();
+                if old == title {
+                    return None;
+                }
+                audio.borrow_mut().set_title(title);
+                Some(Command::SetTitle { audio, title: old })
+            }
+            Command::SetVolume { audio, volume } => {
+                let old = audio.borrow().get_volume();
+                if old == volume {
+                    return None;
+                }
+                audio.borrow_mut().set_volume(volume);
+                Some(Command::SetVolume { audio, volume: old })
+            }
+            Command::SetQuery {
+                audio,
+                query,
+                title,
+            } => {
+                let old_query = audio.borrow().get_query().ok()?;
+                let old_title = audio.borrow().get_title();
+                if old_query == query && old_title == title {
+                    return None;
+                }
+                audio.borrow_mut().set_query(query);
+                audio.borrow_mut().set_title(title);
+                Some(Command::SetQuery {
+                    audio,
+                    query: old_query,
+                    title: old_title,
+                })
+            }
+            Command::InsertThread {
+                playlist,
+                index,
+                thread,
+                audio,
+                order,
+            } => {
+                let mut raw = playlist.borrow_mut();
+                raw.insert_thread(index, &thread).ok()?;
+                for audio in audio {
+                    raw.push_audio(&thread, audio).ok()?;
+                }
+                raw.set_thread_order(&thread, order);
+                drop(raw);
+                Some(Command::RemoveThread { playlist, thread })
+            }
+            Command::RemoveThread { playlist, thread } => {
+                let threads = playlist.borrow().threads().ok()?;
+                let index = threads.iter().position(|t| *t == thread)?;
+                let count = playlist.borrow().audio_count(&thread);
+                let audio = (0..count)
+                    .filter_map(|i| playlist.borrow().get_audio(&thread, i).ok())
+                    .collect();
+                let order = playlist.borrow().get_thread_order(&thread);
+                playlist.borrow_mut().remove_thread(&thread);
+                Some(Command::InsertThread {
+                    playlist,
+                    index,
+                    thread,
+                    audio,
+                    order,
+                })
+            }
+            Command::RenameThread { playlist, old, new } => {
+                let threads = playlist.borrow().threads().ok()?;
+                if old == new || !threads.contains(&old) || threads.contains(&new) {
+                    return None;
+                }
+                playlist.borrow_mut().rename_thread(&old, &new);
+                Some(Command::RenameThread {
+                    playlist,
+                    old: new,
+                    new: old,
+                })
+            }
+            Command::SetThreadOrder {
+                playlist,
+                thread,
+                order,
+            } => {
+                let old = playlist.borrow().get_thread_order(&thread);
+                if old == order {
+                    return None;
+                }
+                playlist.borrow_mut().set_thread_order(&thread, order);
+                Some(Command::SetThreadOrder {
+                    playlist,
+                    thread,
+                    order: old,
+                })
+            }
+            Command::InsertTrack {
+                playlist,
+                thread,
+                index,
+                audio,
+            } => {
+                playlist
+                    .borrow_mut()
+                    .insert_audio(&thread, index, audio)
+                    .ok()?;
+                Some(Command::RemoveTrack {
+                    playlist,
+                    thread,
+                    index,
+                })
+            }
+            Command::RemoveTrack {
+                playlist,
+                thread,
+                index,
+            } => {
+                let audio = playlist.borrow().get_audio(&thread, index).ok()?;
+                playlist.borrow_mut().remove_audio(&thread, index).ok()?;
+                Some(Command::InsertTrack {
+                    playlist,
+                    thread,
+                    index,
+                    audio,
+                })
+            }
+            Command::SetSource { audio, source } => {
+                let old = audio.borrow().get_source().ok()?;
+                if old.get_filename() == source.get_filename() {
+                    return None;
+                }
+                audio.borrow_mut().set_source(source);
+                Some(Command::SetSource { audio, source: old })
+            }
+            Command::InsertTag {
+                storage,
+                index,
+                tag,
+                sources,
+            } => {
+                let text = tag.get_text();
+                let sources = storage.borrow().source_indexes(&sources);
+                if !storage.borrow_mut().insert_tag(index, tag, &sources) {
+                    return None;
+                }
+                Some(Command::RemoveTag { storage, tag: text })
+            }
+            Command::RemoveTag { storage, tag } => {
+                let (index, tag, sources) = storage.borrow_mut().remove_tag(tag)?;
+                let sources = storage.borrow().source_filenames(&sources);
+                Some(Command::InsertTag {
+                    storage,
+                    index,
+                    tag,
+                    sources,
+                })
+            }
+            Command::RenameTag { storage, old, new } => {
+                let new = storage.borrow_mut().rename_tag(old.clone(), new)?;
+                Some(Command::RenameTag {
+                    storage,
+                    old: new,
+                    new: old,
+                })
+            }
+            Command::SetTagColor {
+                storage,
+                tag,
+                color,
+            } => {
+                let old = storage.borrow().find_tag(&tag)?.get_color();
+                if old == color {
+                    return None;
+                }
+                storage.borrow_mut().set_tag_color(tag.clone(), color);
+                Some(Command::SetTagColor {
+                    storage,
+                    tag,
+                    color: old,
+                })
+            }
+            Command::SetTagCategory {
+                storage,
+                tag,
+                category,
+            } => {
+                let old = storage.borrow().find_tag(&tag)?.get_category();
+                if old == category {
+                    return None;
+                }
+                storage.borrow_mut().set_tag_category(tag.clone(), category);
+                Some(Command::SetTagCategory {
+                    storage,
+                    tag,
+                    category: old,
+                })
+            }
+            Command::AttachTag {
+                storage,
+                sources,
+                tag,
+            } => {
+                let tag = Tag::new(tag).get_text();
+                let is_new = storage.borrow().find_tag(&tag).is_none();
+                let tagged = storage.borrow().sources_with_tag(&tag);
+                let sources: Vec<usize> = storage
+                    .borrow()
+                    .source_indexes(&sources)
+                    .into_iter()
+                    .filter(|i| !tagged.contains(i))
+                    .collect();
+                if sources.is_empty() {
+                    return None;
+                }
+                storage.borrow_mut().attach_tag_to(&sources, tag.clone());
+                let sources = storage.borrow().source_filenames(&sources);
+                // New tag is removed with its attachments.
+                Some(if is_new {
+                    Command::RemoveTag { storage, tag }
+                } else {
+                    Command::UnattachTag {
+                        storage,
+                        sources,
+                        tag,
+                    }
+                })
+            }
+            Command::UnattachTag {
+                storage,
+                sources,
+                tag,
+            } => {
+                let tagged = storage.borrow().sources_with_tag(&tag);
+                let sources: Vec<usize> = storage
+                    .borrow()
+                    .source_indexes(&sources)
+                    .into_iter()
+                    .filter(|i| tagged.contains(i))
+                    .collect();
+                if sources.is_empty() {
+                    return None;
+                }
+                storage
+                    .borrow_mut()
+                    .unattach_tag_from(&sources, tag.clone());
+                let sources = storage.borrow().source_filenames(&sources);
+                Some(Command::AttachTag {
+                    storage,
+                    sources,
+                    tag,
+                })
+            }
+            Command::SetTagState { storage, state } => {
+                let old = storage.borrow().get_tag_state();
+                if old == state {
+                    return None;
+                }
+                storage.borrow_mut().set_tag_state(state);
+                Some(Command::SetTagState {
+                    storage,
+                    state: old,
+                })
+            }
+            Command::SetCaption { storage, caption } => {
+                let old = storage.borrow().get_caption();
+                if old == caption {
+                    return None;
+                }
+                storage.borrow_mut().set_caption(caption);
+                Some(Command::SetCaption {
+                    storage,
+                    caption: old,
+                })
+            }
+            Command::SetImportOptions { storage, options } => {
+                let old = storage.borrow().get_import_options();
+                if old == options {
+                    return None;
+                }
+                storage.borrow_mut().set_import_options(options);
+                Some(Command::SetImportOptions {
+                    storage,
+                    options: old,
+                })
+            }
+            Command::SetDiskCache {
+                storage,
+                disk_cache,
+            } => {
+                let old = storage.borrow().get_disk_cache();
+                if old == disk_cache {
+                    return None;
+                }
+                storage.borrow_mut().set_disk_cache(disk_cache);
+                Some(Command::SetDiskCache {
+                    storage,
+                    disk_cache: old,
+                })
+            }
+            Command::SetDiskCacheLimit { storage, limit } => {
+                let old = storage.borrow().get_disk_cache_limit();
+                if old == limit {
+                    return None;
+                }
+                storage.borrow_mut().set_disk_cache_limit(limit);
+                Some(Command::SetDiskCacheLimit {
+                    storage,
+                    limit: old,
+                })
+            }
+            Command::SetPassword { storage, password } => {
+                let old = storage.borrow().get_password();
+                if old == password {
+                    return None;
+                }
+                storage.borrow_mut().set_password(password);
+                storage.borrow_mut().rescan();
+                Some(Command::SetPassword {
+                    storage,
+                    password: old,
+                })
+            }
+            Command::Batch(commands) => {
+                let mut inverse: Vec<Command> =
+                    commands.into_iter().filter_map(Command::execute).collect();
+                if inverse.is_empty() {
+                    return None;
+                }
+                inverse.reverse();
+                Some(Command::Batch(inverse))
+            }
+        }
+    }
+
+    /// Join the inverse of the next change of the same value, so typing
+    /// or dragging a slider is undone at once. True if joined.
+    fn join(&mut self, next: &Command) -> bool {
+        match (self, next) {
+            (Command::SetTitle { audio, .. }, Command::SetTitle { audio: next, .. })
+            | (Command::SetVolume { audio, .. }, Command::SetVolume { audio: next, .. })
+            | (Command::SetQuery { audio, .. }, Command::SetQuery { audio: next, .. }) => {
+                Rc::ptr_eq(audio, next)
+            }
+            (
+                Command::RenameThread { playlist, old, .. },
+                Command::RenameThread {
+                    playlist: next,
+                    old: next_old,
+                    new: next_new,
+                },
+            ) if Rc::ptr_eq(playlist, next) && old == next_new => {
+                *old = next_old.clone();
+                true
+            }
+            (
+                Command::RenameTag { storage, old, .. },
+                Command::RenameTag {
+                    storage: next,
+                    old: next_old,
+                    new: next_new,
+                },
+            ) if Rc::ptr_eq(storage, next) && old == next_new => {
+                *old = next_old.clone();
+                true
+            }
+            (Command::SetCaption { storage, .. }, Command::SetCaption { storage: next, .. })
+            | (
+                Command::SetImportOptions { storage, .. },
+                Command::SetImportOptions { storage: next, .. },
+            )
+            | (
+                Command::SetDiskCacheLimit { storage, .. },
+                Command::SetDiskCacheLimit { storage: next, .. },
+            ) => Rc::ptr_eq(storage, next),
+            (
+                Command::SetTagColor { storage, tag, .. },
+                Command::SetTagColor {
+                    storage: next,
+                    tag: next_tag,
+                    ..
+                },
+            ) => Rc::ptr_eq(storage, next) && tag == next_tag,
+            _ => false,
+        }
+    }
+}
+
+/// Inverse commands of the last changes.
+#[derive(Default)]
+pub struct UndoStack {
+    undo: VecDeque<Command>,
+    redo: Vec<Command>,
+    /// Time of the last change, None after undo and redo.
+    last_change: Option<Instant>,
+}
+
+impl UndoStack {
+    /// Execute command and keep it for undo. True if changed.
+    pub fn execute(&mut self, command: Command) -> bool {
+        let Some(inverse) = command.execute() else {
+            return false;
+        };
+        self.record(inverse);
+        true
+    }
+
+    /// Keep inverse of a change made without a command, e.g. by import.
+    pub fn record(&mut self, inverse: Command) {
+        self.redo.clear();
+        let recent = self
+            .last_change
+            .is_some_and(|time| time.elapsed() < JOIN_INTERVAL);
+        self.last_change = Some(Instant::now());
+        if recent
+            && let Some(last) = self.undo.back_mut()
+            && last.join(&inverse)
+        {
+            return;
+        }
+        self.push_undo(inverse);
+    }
+
+    /// Undo the last change. False if nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.last_change = None;
+        while let Some(command) = self.undo.pop_back() {
+            // Changes which are already undone by other ways are skipped.
+            if let Some(inverse) = command.execute() {
+                self.redo.push(inverse);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Redo the last undone change. False if nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        self.last_change = None;
+        while let Some(command) = self.redo.pop() {
+            if let Some(inverse) = command.execute() {
+                self.push_undo(inverse);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.last_change = None;
+    }
+
+    fn push_undo(&mut self, inverse: Command) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(inverse);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{RawAudio, playlist::Playlist};
+
+    fn playlist() -> Audio {
+        Rc::new(RefCell::new(Box::new(Playlist::new())))
+    }
+
+    fn storage() -> Rc<RefCell<Storage>> {
+        let storage: Storage = serde_yaml::from_str(
+            "title: Weather
+credentials: null
+sources:
+- {filename: /music/rain.ogg, title: rain, tags: []}
+- {filename: /music/wind.ogg, title: wind, tags: [], suggestions: [calm]}
+tags: []
+",
+        )
+        .unwrap();
+        Rc::new(RefCell::new(storage))
+    }
+
+    /// Playlist with the thread of one track.
+    fn playlist_with_thread(stack: &mut UndoStack, track: &Audio) -> Audio {
+        let playlist = playlist();
+        stack.execute(Command::Batch(vec![
+            Command::InsertThread {
+                playlist: Rc::clone(&playlist),
+                index: 0,
+                thread: "Music".to_string(),
+                audio: vec![],
+                order: ThreadOrder::Sequential,
+            },
+            Command::InsertTrack {
+                playlist: Rc::clone(&playlist),
+                thread: "Music".to_string(),
+                index: 0,
+                audio: Rc::clone(track),
+            },
+        ]));
+        playlist
+    }
+
+    #[test]
+    fn insert_scene() {
+        let root = Rc::new(RefCell::new(Scene::new(None)));
+        let child = Rc::new(RefCell::new(Scene::new(Some(Rc::clone(&root)))));
+        let point = Point { x: 0.5, y: 0.5 };
+        let mut stack = UndoStack::default();
+
+        stack.execute(Command::InsertScene {
+            parent: Rc::clone(&root),
+            point: point.clone(),
+            scene: Rc::clone(&child),
+        });
+        stack.undo();
+        assert!(root.borrow().get_map(&point).is_none());
+        assert!(!stack.undo());
+        stack.redo();
+        assert!(Rc::ptr_eq(&root.borrow().get_map(&point).unwrap(), &child));
+        assert!(!stack.redo());
+
+        stack.execute(Command::RemoveScene {
+            parent: Rc::clone(&root),
+            point: point.clone(),
+        });
+        assert!(root.borrow().get_map(&point).is_none());
+        stack.undo();
+        assert!(Rc::ptr_eq(&root.borrow().get_map(&point).unwrap(), &child));
+    }
+
+    #[test]
+    fn insert_playlist() {
+        let scene = Rc::new(RefCell::new(Scene::new(None)));
+        let playlist = playlist();
+        let mut stack = UndoStack::default();
+
+        stack.execute(Command::InsertPlaylist {
+            scene: Rc::clone(&scene),
+            index: 0,
+            playlist: Rc::clone(&playlist),
+        });
+        stack.undo();
+        assert_eq!(scene.borrow().audio_count(), 0);
+        stack.redo();
+        assert!(Rc::ptr_eq(&scene.borrow().get_audio(0), &playlist));
+    }
+
+    #[test]
+    fn insert_thread_and_track() {
+        let track = playlist();
+        let mut stack = UndoStack::default();
+        let playlist = playlist_with_thread(&mut stack, &track);
+        assert_eq!(stack.undo.len(), 1);
+
+        // Batch is undone at once.
+        stack.undo();
+        assert!(playlist.borrow().threads().unwrap().is_empty());
+        stack.redo();
+        assert_eq!(playlist.borrow().threads().unwrap(), ["Music"]);
+        assert!(Rc::ptr_eq(
+            &playlist.borrow().get_audio("Music", 0).unwrap(),
+            &track
+        ));
+    }
+
+    #[test]
+    fn remove_thread() {
+        let track = playlist();
+        let mut stack = UndoStack::default();
+        let playlist = playlist_with_thread(&mut stack, &track);
+        stack.execute(Command::SetThreadOrder {
+            playlist: Rc::clone(&playlist),
+            thread: "Music".to_string(),
+            order: ThreadOrder::Shuffle,
+        });
+
+        // Removed thread comes back to its place with its tracks and order.
+        stack.execute(Command::RemoveThread {
+            playlist: Rc::clone(&playlist),
+            thread: "Music".to_string(),
+        });
+        assert!(playlist.borrow().threads().unwrap().is_empty());
+        stack.undo();
+        assert_eq!(playlist.borrow().threads().unwrap(), ["Music"]);
+        assert!(Rc::ptr_eq(
+            &playlist.borrow().get_audio("Music", 0).unwrap(),
+            &track
+        ));
+        assert_eq!(
+            playlist.borrow().get_thread_order("Music"),
+            ThreadOrder::Shuffle
+        );
+        stack.undo();
+        assert_eq!(
+            playlist.borrow().get_thread_order("Music"),
+            ThreadOrder::Sequential
+        );
+    }
+
+    #[test]
+    fn set_title() {
+        let playlist = playlist();
+        let mut stack = UndoStack::default();
+
+        // Typing is undone at once.
+        for title in ["T", "Ta", "Tav"] {
+            stack.execute(Command::SetTitle {
+                audio: Rc::clone(&playlist),
+                title: title.to_string(),
+            });
+        }
+        assert!(!stack.execute(Command::SetTitle {
+            audio: Rc::clone(&playlist),
+            title: "Tav".to_string(),
+        }));
+        assert_eq!(stack.undo.len(), 1);
+        stack.undo();
+        assert_eq!(playlist.borrow().get_title(), Playlist::new().get_title());
+        stack.redo();
+        assert_eq!(playlist.borrow().get_title(), "Tav");
+    }
+
+    #[test]
+    fn set_volume() {
+        let track = playlist();
+        let mut stack = UndoStack::default();
+
+        for i in 0..UNDO_LIMIT + 10 {
+            stack.execute(Command::SetVolume {
+                audio: Rc::clone(&track),
+                volume: i as f32 / 1000.0,
+            });
+            stack.last_change = None;
+        }
+        assert_eq!(stack.undo.len(), UNDO_LIMIT);
+        assert!(!stack.can_redo());
+        stack.undo();
+        assert_eq!(
+            track.borrow().get_volume(),
+            (UNDO_LIMIT + 8) as f32 / 1000.0
+        );
+    }
+
+    #[test]
+    fn attach_and_remove_tag() {
+        let storage = storage();
+        let mut stack = UndoStack::default();
+
+        // Removed tag is attached again, new attached tag is removed.
+        stack.execute(Command::AttachTag {
+            storage: Rc::clone(&storage),
+            sources: vec!["/music/rain.ogg".to_string(), "/music/wind.ogg".to_string()],
+            tag: "weather".to_string(),
+        });
+        stack.execute(Command::AttachTag {
+            storage: Rc::clone(&storage),
+            sources: vec!["/music/wind.ogg".to_string()],
+            tag: "calm".to_string(),
+        });
+        stack.execute(Command::RemoveTag {
+            storage: Rc::clone(&storage),
+            tag: "weather".to_string(),
+        });
+        assert_eq!(storage.borrow().get_tags(1)[0].get_text(), "calm");
+        stack.undo();
+        assert_eq!(storage.borrow().sources_with_tag("weather"), [0, 1]);
+        assert_eq!(storage.borrow().sources_with_tag("calm"), [1]);
+        stack.undo();
+        assert!(storage.borrow().find_tag("calm").is_none());
+        assert_eq!(storage.borrow().get_tags(1).len(), 1);
+    }
+
+    #[test]
+    fn set_tag_state() {
+        let storage = storage();
+        let mut stack = UndoStack::default();
+
+        // Accepted suggestion is suggested again after undo.
+        let state = storage.borrow().get_tag_state();
+        storage.borrow_mut().accept_suggestions(&[1]);
+        stack.record(Command::SetTagState {
+            storage: Rc::clone(&storage),
+            state,
+        });
+        assert_eq!(storage.borrow().sources_with_tag("calm"), [1]);
+        stack.undo();
+        assert!(storage.borrow().sources_with_tag("calm").is_empty());
+        assert_eq!(storage.borrow().get(1).unwrap().get_suggestions(), ["calm"]);
+        stack.redo();
+        assert_eq!(storage.borrow().sources_with_tag("calm"), [1]);
+    }
+
+    #[test]
+    fn set_caption() {
+        let storage = storage();
+        let mut stack = UndoStack::default();
+
+        for caption in ["R", "Ra", "Rain"] {
+            stack.execute(Command::SetCaption {
+                storage: Rc::clone(&storage),
+                caption: caption.to_string(),
+            });
+        }
+        assert_eq!(stack.undo.len(), 1);
+        stack.undo();
+        assert_eq!(storage.borrow().get_caption(), "Weather");
+        stack.redo();
+        assert_eq!(storage.borrow().get_caption(), "Rain");
+    }
+
+    #[test]
+    fn set_import_options() {
+        let storage = storage();
+        let mut stack = UndoStack::default();
+
+        let mut options = storage.borrow().get_import_options();
+        options.genre_tags = true;
+        stack.execute(Command::SetImportOptions {
+            storage: Rc::clone(&storage),
+            options,
+        });
+        assert!(storage.borrow().get_import_options().genre_tags);
+        stack.undo();
+        assert_eq!(
+            storage.borrow().get_import_options(),
+            ImportOptions::default()
+        );
+        stack.redo();
+        assert!(storage.borrow().get_import_options().genre_tags);
+    }
+
+    #[test]
+    fn set_disk_cache() {
+        let storage = storage();
+        let mut stack = UndoStack::default();
+        let limit = storage.borrow().get_disk_cache_limit();
+
+        stack.execute(Command::SetDiskCache {
+            storage: Rc::clone(&storage),
+            disk_cache: true,
+        });
+        stack.last_change = None;
+        // Dragging the limit is undone at once.
+        for limit in [100, 200, 300] {
+            stack.execute(Command::SetDiskCacheLimit {
+                storage: Rc::clone(&storage),
+                limit,
+            });
+        }
+        assert_eq!(stack.undo.len(), 2);
+        stack.undo();
+        assert_eq!(storage.borrow().get_disk_cache_limit(), limit);
+        assert!(storage.borrow().get_disk_cache());
+        stack.undo();
+        assert!(!storage.borrow().get_disk_cache());
+        while stack.redo() {}
+        assert!(storage.borrow().get_disk_cache());
+        assert_eq!(storage.borrow().get_disk_cache_limit(), 300);
+    }
+
+    #[test]
+    fn set_password() {
+        let storage = storage();
+        let mut stack = UndoStack::default();
+
+        assert!(stack.execute(Command::SetPassword {
+            storage: Rc::clone(&storage),
+            password: "secret".to_string(),
+        }));
+        assert!(!stack.execute(Command::SetPassword {
+            storage: Rc::clone(&storage),
+            password: "secret".to_string(),
+        }));
+        stack.undo();
+        assert_eq!(storage.borrow().get_password(), "");
+        stack.redo();
+        assert_eq!(storage.borrow().get_password(), "secret");
+    }
+}
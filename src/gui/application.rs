@@ -20,18 +20,27 @@ use std::rc::Rc;
 use std::{env, thread};
 
 use eframe::NativeOptions;
-use egui::{Style, ViewportBuilder, Visuals};
+use egui::{Key, KeyboardShortcut, Modifiers, Style, ViewportBuilder, Visuals};
 use rfd::MessageDialog;
 
 use crate::application::Application;
+use crate::autosave::Recovery;
 use crate::colors;
 use crate::gui::events::Event;
+use crate::history;
+use crate::scene::Scene;
 use crate::settings::Settings;
 
 use super::playlist::PlaylistWidget;
 use super::scene::MapWidget;
 use super::player::PlayerWidget;
-use super::storage::StorageWidget;
+use super::storage::{StorageWidget, format_ago};
+
+const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO_SHORTCUTS: [KeyboardShortcut; 2] = [
+    KeyboardShortcut::new(Modifiers::COMMAND, Key::Y),
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z),
+];
 
 /// Describe Cyberbard main window.
 /// Create and update all widgets and connect to core application.
@@ -44,44 +53,156 @@ pub struct ApplicationImp {
     playlist_widget: PlaylistWidget,
     settings: Rc<RefCell<Settings>>,
     last_upd: std::time::Instant,
+    /// Unsaved project of the last session, offered to restore.
+    recovery: Option<Recovery>,
 }
 
 impl ApplicationImp {
     /// Create main window struct
     pub fn new(application: Application, settings: Rc<RefCell<Settings>>) -> ApplicationImp {
         let application = Rc::new(RefCell::new(application));
-        let storage = application.borrow().get_storage();
         let map = application.borrow().get_root_map();
         let player = application.borrow().get_player();
+        let recovery = application.borrow().find_recovery();
         ApplicationImp {
             application: Rc::clone(&application),
             events: VecDeque::new(),
-            storage_widget: StorageWidget::new(storage, Rc::clone(&application)),
+            storage_widget: StorageWidget::new(Rc::clone(&application)),
             map_widget: MapWidget::new(map, Rc::clone(&application)),
-            player_widget: PlayerWidget::new(player),
+            player_widget: PlayerWidget::new(player, Rc::clone(&application)),
             playlist_widget: PlaylistWidget::new(Rc::clone(&application)),
             settings,
             last_upd: std::time::Instant::now(),
+            recovery,
+        }
+    }
+
+    /// Widgets of the newly opened project.
+    fn recreate_widgets(&mut self) {
+        let map = self.application.borrow().get_root_map();
+        let player = self.application.borrow().get_player();
+        self.storage_widget = StorageWidget::new(Rc::clone(&self.application));
+        self.map_widget = MapWidget::new(map, Rc::clone(&self.application));
+        self.player_widget = PlayerWidget::new(player, Rc::clone(&self.application));
+        self.playlist_widget = PlaylistWidget::new(Rc::clone(&self.application));
+    }
+
+    /// Show opened project or error of opening.
+    fn show_opened(&mut self, opened: Result<(), Box<dyn std::error::Error>>) {
+        match opened {
+            Ok(_) => self.recreate_widgets(),
+            Err(err) => {
+                let err = err.to_string();
+                thread::spawn(move || {
+                    MessageDialog::new()
+                        .set_title(t!("error_opening_file"))
+                        .set_description(err)
+                        .set_level(rfd::MessageLevel::Error)
+                        .show();
+                });
+            }
+        }
+    }
+
+    /// Offer to restore unsaved project of the last session.
+    fn render_recovery_dialog(&mut self, ctx: &egui::Context) {
+        let Some(recovery) = &self.recovery else {
+            return;
+        };
+        let mut restore = None;
+        egui::Window::new(t!("restore_unsaved"))
+            .resizable(false)
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(t!("restore_unsaved_question"));
+                let project = match &recovery.project {
+                    Some(path) => path.to_string_lossy().to_string(),
+                    None => t!("untitled_project").to_string(),
+                };
+                ui.label(egui::RichText::new(project).strong());
+                let ago = format_ago(history::now().saturating_sub(recovery.saved));
+                ui.label(egui::RichText::new(format!("{} {}", t!("autosaved"), ago)).weak());
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button(t!("restore")).clicked() {
+                        restore = Some(true);
+                    }
+                    if ui.button(t!("discard")).clicked() {
+                        restore = Some(false);
+                    }
+                });
+            });
+        match restore {
+            Some(true) => {
+                let recovery = self.recovery.take().unwrap();
+                let restored = self.application.borrow_mut().restore_recovery(recovery);
+                self.show_opened(restored);
+            }
+            Some(false) => {
+                let recovery = self.recovery.take().unwrap();
+                self.application.borrow_mut().discard_recovery(&recovery);
+            }
+            None => (),
+        }
+    }
+
+    /// Undo and redo shortcuts, text fields have their own.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        ctx.input_mut(|input| {
+            // Shortcut with shift goes first, the one without matches it too.
+            if REDO_SHORTCUTS.iter().any(|s| input.consume_shortcut(s)) {
+                self.events.push_back(Event::Redo);
+            } else if input.consume_shortcut(&UNDO_SHORTCUT) {
+                self.events.push_back(Event::Undo);
+            }
+        });
+    }
+
+    /// Widgets showing the project after undo or redo.
+    fn show_undone(&mut self) {
+        self.map_widget.sync_with_application();
+        let root = self.application.borrow().get_root_map();
+        let selected = self.application.borrow().get_selected_playlist();
+        let removed = selected
+            .borrow()
+            .as_ref()
+            .is_some_and(|playlist| Scene::find_scene(&root, playlist).is_none());
+        if removed {
+            self.application.borrow_mut().select_playlist(None);
         }
+        self.playlist_widget.sync_with_application();
+        self.storage_widget.find();
+        self.application.borrow_mut().player_sync();
     }
 
     fn handle_events(&mut self, ctx: &egui::Context) {
         while let Some(event) = self.events.pop_front() {
             match event {
                 Event::SetupStorage { credentials } => {
-                    // TODO: show error message
-                    let setup_error = self.application.borrow_mut().setup_storage(credentials);
-                    if setup_error.is_ok() {
-                        let storage = self.application.borrow().get_storage();
-                        let map = self.application.borrow().get_root_map();
-                        let player = self.application.borrow().get_player();
-                        self.storage_widget =
-                            StorageWidget::new(storage, Rc::clone(&self.application));
-                        self.map_widget = MapWidget::new(map, Rc::clone(&self.application));
-                        self.player_widget = PlayerWidget::new(player);
-                        self.playlist_widget =
-                            PlaylistWidget::new(Rc::clone(&self.application));
-                    }
+                    let opened = self.application.borrow_mut().setup_storage(credentials);
+                    self.show_opened(opened);
+                }
+                Event::OpenProject { path } => {
+                    let opened = self.application.borrow_mut().open_project_file(path);
+                    self.show_opened(opened);
+                }
+                Event::AddStorage {
+                    credentials,
+                    password,
+                } => {
+                    self.application
+                        .borrow_mut()
+                        .add_storage(credentials, password);
+                    self.storage_widget.sync_with_storage();
+                }
+                Event::RemoveStorage { index } => {
+                    self.application.borrow_mut().remove_storage(index);
+                    self.storage_widget.sync_with_storage();
+                    self.storage_widget.refresh_missing_files();
                 }
                 Event::Play { audio } => {
                     self.player_widget.play(&audio);
@@ -123,8 +244,8 @@ impl ApplicationImp {
                         .select_playlist(Some(audio));
                     self.playlist_widget.sync_with_application();
                 }
-                Event::SaveProject { path } => {
-                    match self.application.borrow_mut().save_project(path) {
+                Event::SaveProject { path, embed_audio } => {
+                    match self.application.borrow_mut().save_project(path, embed_audio) {
                         Ok(_) => (),
                         Err(err) => {
                             let err = err.to_string();
@@ -138,6 +259,46 @@ impl ApplicationImp {
                         }
                     }
                 }
+                Event::Relink { path } => {
+                    self.application.borrow_mut().relink(path);
+                    self.storage_widget.sync_with_storage();
+                    self.storage_widget.refresh_missing_files();
+                    self.application.borrow_mut().player_sync();
+                }
+                Event::RemapStorageRoot { index, path } => {
+                    self.application.borrow_mut().remap_root(index, path);
+                    self.storage_widget.sync_with_storage();
+                    self.storage_widget.refresh_missing_files();
+                    self.application.borrow_mut().player_sync();
+                }
+                Event::MergeDuplicates {
+                    storage,
+                    keep,
+                    others,
+                    hide,
+                } => {
+                    self.application
+                        .borrow_mut()
+                        .merge_duplicates(storage, keep, others, hide);
+                    self.storage_widget.sync_with_storage();
+                    self.application.borrow_mut().player_sync();
+                }
+                Event::StorageChanged => {
+                    self.storage_widget.refresh_missing_files();
+                    self.application.borrow_mut().sync_smart_threads();
+                }
+                Event::Undo => {
+                    let undone = self.application.borrow_mut().undo();
+                    if undone {
+                        self.show_undone();
+                    }
+                }
+                Event::Redo => {
+                    let redone = self.application.borrow_mut().redo();
+                    if redone {
+                        self.show_undone();
+                    }
+                }
                 Event::ToggleTheme => {
                     let is_dark = self.settings.borrow().dark_theme;
                     if is_dark {
@@ -157,7 +318,11 @@ impl ApplicationImp {
 
 impl eframe::App for ApplicationImp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_shortcuts(ctx);
         self.handle_events(ctx);
+        self.application.borrow_mut().poll_history();
+        self.application.borrow_mut().poll_autosave();
+        self.render_recovery_dialog(ctx);
 
         egui::SidePanel::left("Storage")
             .resizable(true)
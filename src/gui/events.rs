@@ -24,8 +24,36 @@ pub enum Event {
     SetupStorage {
         credentials: StorageCredentials,
     },
+    /// Mount one more storage to the project.
+    AddStorage {
+        credentials: StorageCredentials,
+        password: String,
+    },
+    RemoveStorage {
+        index: usize,
+    },
     SaveProject {
         path: PathBuf,
+        embed_audio: bool,
+    },
+    /// Open project file.
+    OpenProject {
+        path: PathBuf,
+    },
+    Relink {
+        path: PathBuf,
+    },
+    /// Move storage which folder is not found to the other folder.
+    RemapStorageRoot {
+        index: usize,
+        path: PathBuf,
+    },
+    /// Merge tags of duplicated sources and point tracks to the kept one.
+    MergeDuplicates {
+        storage: usize,
+        keep: usize,
+        others: Vec<usize>,
+        hide: bool,
     },
     Play {
         audio: Audio,
@@ -45,5 +73,9 @@ pub enum Event {
     Select {
         audio: Audio,
     },
+    /// Storage sources or tags are changed.
+    StorageChanged,
+    Undo,
+    Redo,
     ToggleTheme
 }
@@ -19,8 +19,12 @@ use std::{cell::RefCell, rc::Rc, time::Duration};
 use egui::Ui;
 
 use crate::{
+    application::Application,
     audio::Audio,
-    gui::events::{Event, Events},
+    gui::{
+        events::{Event, Events},
+        widgets,
+    },
     player::Player,
 };
 
@@ -28,14 +32,16 @@ pub struct PlayerWidget {
     title: String,
     volume: f32,
     player: Rc<RefCell<Player>>,
+    application: Rc<RefCell<Application>>,
 }
 
 impl PlayerWidget {
-    pub fn new(player: Rc<RefCell<Player>>) -> PlayerWidget {
+    pub fn new(player: Rc<RefCell<Player>>, application: Rc<RefCell<Application>>) -> PlayerWidget {
         PlayerWidget {
             title: "".to_string(),
             volume: 1.0,
             player,
+            application,
         }
     }
 
@@ -70,9 +76,21 @@ impl PlayerWidget {
         });
 
         ui.add_space(20.0);
-        let progress_bar =
-            egui::ProgressBar::new(self.player.borrow().get_position()).desired_height(4.0);
-        ui.add(progress_bar);
+        let current = self.player.borrow().get_current_tracks();
+        let waveform = current.first().and_then(|(position, filename)| {
+            Some((
+                *position,
+                self.application.borrow().find_waveform(filename)?,
+            ))
+        });
+        if let Some((position, waveform)) = waveform {
+            let size = egui::vec2(ui.available_width(), 32.0);
+            widgets::waveform(ui, &waveform, Some(position), size);
+        } else {
+            let progress_bar =
+                egui::ProgressBar::new(self.player.borrow().get_position()).desired_height(4.0);
+            ui.add(progress_bar);
+        }
         ui.add_space(10.0);
 
         ui.horizontal(|ui| {
@@ -99,6 +117,28 @@ impl PlayerWidget {
         });
         ui.add_space(10.0);
 
+        let errors = self.player.borrow().get_errors();
+        if !errors.is_empty() {
+            ui.horizontal(|ui| {
+                ui.label(
+                    egui::RichText::new(t!("playback_errors"))
+                        .color(ui.visuals().error_fg_color),
+                );
+                if ui.small_button("🗙").clicked() {
+                    self.player.borrow_mut().clear_errors();
+                }
+            });
+            egui::ScrollArea::vertical()
+                .id_salt("playback_errors")
+                .max_height(80.0)
+                .show(ui, |ui| {
+                    for error in errors {
+                        ui.label(egui::RichText::new(error).small().weak());
+                    }
+                });
+            ui.add_space(10.0);
+        }
+
         if !self.player.borrow().is_paused() {
             ctx.request_repaint_after(Duration::from_millis(30));
         }
@@ -14,19 +14,29 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use egui::{Color32, Label, RichText, Sense, Slider, TextEdit, Ui, UiBuilder};
 
 use crate::{
     application::Application,
     audio::Audio,
-    gui::events::{Event, Events},
+    command::Command,
+    gui::{
+        events::{Event, Events},
+        widgets,
+    },
+    storage::{query::Query, source::Source},
+    stream::ThreadOrder,
 };
 
+/// Found sources of smart thread query with storages revision.
+type FoundSources = HashMap<String, (Vec<(String, u64)>, Vec<Source>)>;
+
 pub struct PlaylistWidget {
     current_thread: Option<String>,
     application: Rc<RefCell<Application>>,
+    found: RefCell<FoundSources>,
 }
 
 impl PlaylistWidget {
@@ -34,9 +44,23 @@ impl PlaylistWidget {
         PlaylistWidget {
             current_thread: None,
             application,
+            found: RefCell::new(HashMap::new()),
         }
     }
 
+    /// Sources of smart thread query, searched again when storages change.
+    fn find_sources(&self, query: &str) -> Vec<Source> {
+        let revision = self.application.borrow().storages_revision();
+        let mut found = self.found.borrow_mut();
+        found.retain(|_, (r, _)| *r == revision);
+        if let Some((_, sources)) = found.get(query) {
+            return sources.clone();
+        }
+        let sources = self.application.borrow().find_sources(query);
+        found.insert(query.to_string(), (revision, sources.clone()));
+        sources
+    }
+
     pub fn sync_with_application(&mut self) {
         if self
             .application
@@ -50,24 +74,44 @@ impl PlaylistWidget {
     }
 
     pub fn insert_audio(&mut self, audio: Audio) {
-        if let Some(playlist) = self
-            .application
-            .borrow()
-            .get_selected_playlist()
-            .borrow_mut()
-            .as_ref()
-        {
-            let thread = if let Some(thread) = &self.current_thread {
-                thread.clone()
-            } else if let Some(thread) = playlist.borrow().threads().unwrap().first() {
+        let selected = self.application.borrow().get_selected_playlist();
+        let selected = selected.borrow().clone();
+        if let Some(playlist) = &selected {
+            // Smart thread is alone in its thread, tracks are never added to it.
+            let threads = playlist.borrow().threads().unwrap();
+            let query = audio.borrow().get_query();
+            let thread = if let Ok(query) = query {
+                unique_thread_name(&query, &threads)
+            } else if let Some(thread) = self
+                .current_thread
+                .clone()
+                .filter(|thread| !is_smart_thread(playlist, thread))
+            {
+                thread
+            } else if let Some(thread) = threads.iter().find(|t| !is_smart_thread(playlist, t)) {
                 thread.clone()
             } else {
-                let thread = generate_thread_name(Vec::new());
-                playlist.borrow_mut().push_thread(&thread).unwrap();
-                thread
+                generate_thread_name(threads.clone())
             };
-
-            playlist.borrow_mut().push_audio(&thread, audio).unwrap();
+            let mut commands = vec![];
+            if !threads.contains(&thread) {
+                commands.push(Command::InsertThread {
+                    playlist: Rc::clone(playlist),
+                    index: threads.len(),
+                    thread: thread.clone(),
+                    audio: vec![],
+                    order: ThreadOrder::default(),
+                });
+            }
+            commands.push(Command::InsertTrack {
+                playlist: Rc::clone(playlist),
+                index: playlist.borrow().audio_count(&thread),
+                thread,
+                audio,
+            });
+            self.application
+                .borrow_mut()
+                .execute(Command::Batch(commands));
         }
     }
 
@@ -92,11 +136,29 @@ impl PlaylistWidget {
                                     )
                                     .changed()
                                 {
-                                    playlist.borrow_mut().set_title(title);
+                                    self.application.borrow_mut().execute(Command::SetTitle {
+                                        audio: Rc::clone(playlist),
+                                        title,
+                                    });
                                     sync_with_player(events, playlist);
                                 }
                             });
 
+                            let id = playlist.borrow().get_id().unwrap_or_default();
+                            let stats = self.application.borrow().playlist_stats(&id);
+                            if let Some(stats) = stats {
+                                ui.vertical_centered(|ui| {
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "{}: {}",
+                                            t!("play_count"),
+                                            stats.count
+                                        ))
+                                        .weak(),
+                                    );
+                                });
+                            }
+
                             ui.add_space(20.0);
 
                             ui.horizontal(|ui| {
@@ -112,7 +174,12 @@ impl PlaylistWidget {
                                             )
                                             .changed()
                                         {
-                                            playlist.borrow_mut().set_volume(total_volume);
+                                            self.application.borrow_mut().execute(
+                                                Command::SetVolume {
+                                                    audio: Rc::clone(playlist),
+                                                    volume: total_volume,
+                                                },
+                                            );
                                             sync_with_player(events, playlist);
                                         }
                                     },
@@ -133,6 +200,14 @@ impl PlaylistWidget {
                                 } else {
                                     None
                                 };
+                            // Positions of current tracks of threads.
+                            let positions: Vec<f32> = if current_playing.is_some() {
+                                let player = self.application.borrow().get_player();
+                                let tracks = player.borrow().get_current_tracks();
+                                tracks.into_iter().map(|(position, _)| position).collect()
+                            } else {
+                                vec![]
+                            };
 
                             for mut thread in threads {
                                 let mut remove_elements = vec![];
@@ -144,15 +219,29 @@ impl PlaylistWidget {
                                     &mut remove_elements,
                                     &mut thread,
                                     playlist,
-                                    current_playing.as_ref().unwrap_or(&vec![]).get(index),
+                                    current_playing
+                                        .as_ref()
+                                        .and_then(|current| current.get(index))
+                                        .map(|i| (i, positions.get(index).copied().unwrap_or(0.0))),
                                 );
 
                                 for element in remove_elements {
-                                    let _ = playlist.borrow_mut().remove_audio(&thread, element);
-                                    sync_with_player(events, playlist);
-                                    if playlist.borrow().is_thread_empty(&thread) {
-                                        playlist.borrow_mut().remove_thread(&thread);
+                                    // Thread of the last track is removed with it.
+                                    let mut commands = vec![Command::RemoveTrack {
+                                        playlist: Rc::clone(playlist),
+                                        thread: thread.clone(),
+                                        index: element,
+                                    }];
+                                    if playlist.borrow().audio_count(&thread) == 1 {
+                                        commands.push(Command::RemoveThread {
+                                            playlist: Rc::clone(playlist),
+                                            thread: thread.clone(),
+                                        });
                                     }
+                                    self.application
+                                        .borrow_mut()
+                                        .execute(Command::Batch(commands));
+                                    sync_with_player(events, playlist);
                                 }
                             }
 
@@ -161,11 +250,16 @@ impl PlaylistWidget {
                                 let last_thread: Option<&String> = threads.last();
                                 if ui.button("+").clicked()
                                 && (last_thread.is_none() || !playlist.borrow().is_thread_empty(last_thread.unwrap())) {
-                                    let thread = generate_thread_name(
-                                        playlist.borrow().threads().unwrap(),
+                                    let thread = generate_thread_name(threads.clone());
+                                    self.application.borrow_mut().execute(
+                                        Command::InsertThread {
+                                            playlist: Rc::clone(playlist),
+                                            index: threads.len(),
+                                            thread: thread.clone(),
+                                            audio: vec![],
+                                            order: ThreadOrder::default(),
+                                        },
                                     );
-
-                                    playlist.borrow_mut().push_thread(&thread).unwrap();
                                     self.current_thread = Some(thread);
                                 }
                             });
@@ -186,8 +280,10 @@ impl PlaylistWidget {
         remove_elements: &mut Vec<usize>,
         thread: &mut String,
         playlist: &Audio,
-        current_playing: Option<&usize>,
+        current: Option<(&usize, f32)>,
     ) {
+        let current_playing = current.map(|c| c.0);
+        let position = current.map(|c| c.1);
         let mut title = thread.clone();
         let color =
             if self.current_thread.is_some() && &title == self.current_thread.as_ref().unwrap() {
@@ -210,7 +306,11 @@ impl PlaylistWidget {
                         .background_color(Color32::TRANSPARENT),
                 );
                 if title_edit.changed() {
-                    playlist.borrow_mut().rename_thread(thread, &title);
+                    self.application.borrow_mut().execute(Command::RenameThread {
+                        playlist: Rc::clone(playlist),
+                        old: thread.clone(),
+                        new: title.clone(),
+                    });
                     *thread = title;
                 }
 
@@ -219,11 +319,40 @@ impl PlaylistWidget {
                 }
 
                 if ui.label("🗙").clicked() {
-                    playlist.borrow_mut().remove_thread(thread);
+                    self.application.borrow_mut().execute(Command::RemoveThread {
+                        playlist: Rc::clone(playlist),
+                        thread: thread.clone(),
+                    });
+                }
+
+                let order = playlist.borrow().get_thread_order(thread);
+                let (icon, hint, next) = match order {
+                    ThreadOrder::Sequential => ("➡", t!("sequential_order"), ThreadOrder::Shuffle),
+                    ThreadOrder::Shuffle => ("🔀", t!("shuffle_order"), ThreadOrder::LeastRecent),
+                    ThreadOrder::LeastRecent => {
+                        ("🕘", t!("least_recent_order"), ThreadOrder::Sequential)
+                    }
+                };
+                if ui
+                    .add(Label::new(icon).sense(Sense::click()).selectable(false))
+                    .on_hover_text(hint)
+                    .clicked()
+                {
+                    self.application.borrow_mut().execute(Command::SetThreadOrder {
+                        playlist: Rc::clone(playlist),
+                        thread: thread.clone(),
+                        order: next,
+                    });
+                    sync_with_player(events, playlist);
                 }
             });
 
             ui.add_space(5.0);
+            if is_smart_thread(playlist, thread) {
+                let audio = playlist.borrow().get_audio(thread, 0).unwrap();
+                self.render_smart_thread(ui, events, &audio, thread, playlist, current);
+                return;
+            }
             let n = playlist.borrow().audio_count(thread);
 
             for i in 0..n {
@@ -246,6 +375,7 @@ impl PlaylistWidget {
                             .borrow_mut()
                             .goto_track(playlist.borrow().index_of_thread(thread), i);
                     };
+                    let is_current = current_playing == Some(&i);
 
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                         ui.add_space(15.0);
@@ -261,7 +391,10 @@ impl PlaylistWidget {
                             .add(Slider::new(&mut volume, 0.0..=1.0).show_value(false))
                             .changed()
                         {
-                            audio.borrow_mut().set_volume(volume);
+                            self.application.borrow_mut().execute(Command::SetVolume {
+                                audio: Rc::clone(&audio),
+                                volume,
+                            });
                             events.push_back(Event::PlayerSetTrackVolume {
                                 volume,
                                 playlist_index: playlist
@@ -274,6 +407,12 @@ impl PlaylistWidget {
                                 index: i,
                             });
                         }
+
+                        let source = audio.borrow().get_source();
+                        if let Ok(source) = source {
+                            let position = position.filter(|_| is_current);
+                            self.render_waveform(ui, &source.get_filename(), position);
+                        }
                     });
                 });
                 ui.add_space(5.0);
@@ -281,6 +420,95 @@ impl PlaylistWidget {
         });
         ui.add_space(5.0);
     }
+
+    /// Query, volume and found tracks of smart thread.
+    fn render_smart_thread(
+        &self,
+        ui: &mut Ui,
+        events: &mut Events,
+        audio: &Audio,
+        thread: &str,
+        playlist: &Audio,
+        current: Option<(&usize, f32)>,
+    ) {
+        let mut query = audio.borrow().get_query().unwrap_or_default();
+        let sources = self.find_sources(&query);
+        ui.horizontal(|ui| {
+            ui.label("🔎".to_string());
+            let is_valid = Query::parse(&query).is_ok();
+            let mut edit = TextEdit::singleline(&mut query).hint_text(t!("smart_thread_query"));
+            if !is_valid {
+                edit = edit.text_color(ui.visuals().error_fg_color);
+            }
+            if ui.add(edit).changed() {
+                self.application.borrow_mut().execute(Command::SetQuery {
+                    audio: Rc::clone(audio),
+                    query: query.clone(),
+                    title: query.clone(),
+                });
+                if Query::parse(&query).is_ok() {
+                    sync_with_player(events, playlist);
+                }
+            }
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.add_space(15.0);
+                let mut volume = audio.borrow().get_volume();
+                if ui
+                    .add(Slider::new(&mut volume, 0.0..=1.0).show_value(false))
+                    .changed()
+                {
+                    self.application.borrow_mut().execute(Command::SetVolume {
+                        audio: Rc::clone(audio),
+                        volume,
+                    });
+                    let playlist_index = playlist.borrow().index_of_thread(thread);
+                    for index in 0..sources.len() {
+                        events.push_back(Event::PlayerSetTrackVolume {
+                            volume,
+                            playlist_index,
+                            index,
+                        });
+                    }
+                }
+            });
+        });
+        ui.add_space(5.0);
+
+        if sources.is_empty() {
+            ui.label(RichText::new(t!("no_matching_tracks")).weak());
+        }
+        for (i, source) in sources.iter().enumerate() {
+            let position = current.filter(|c| *c.0 == i).map(|c| c.1);
+            let text = if position.is_some() {
+                RichText::new(source.get_title()).strong()
+            } else {
+                RichText::new(source.get_title())
+            };
+            ui.horizontal(|ui| {
+                if ui.add(Label::new(text).sense(Sense::click())).clicked()
+                    && self.application.borrow().is_playing(playlist)
+                {
+                    self.application
+                        .borrow_mut()
+                        .get_player()
+                        .borrow_mut()
+                        .goto_track(playlist.borrow().index_of_thread(thread), i);
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.add_space(15.0);
+                    self.render_waveform(ui, &source.get_filename(), position);
+                });
+            });
+        }
+    }
+
+    /// Small waveform of track with position of the playing one.
+    fn render_waveform(&self, ui: &mut Ui, filename: &str, position: Option<f32>) {
+        if let Some(waveform) = self.application.borrow().find_waveform(filename) {
+            widgets::waveform(ui, &waveform, position, egui::vec2(60.0, 14.0));
+        }
+    }
 }
 
 fn sync_with_player(
@@ -290,6 +518,25 @@ fn sync_with_player(
     events.push_back(super::events::Event::PlayerSync);
 }
 
+/// Thread contains smart thread audio.
+fn is_smart_thread(playlist: &Audio, thread: &str) -> bool {
+    playlist
+        .borrow()
+        .get_audio(thread, 0)
+        .is_ok_and(|audio| audio.borrow().get_query().is_ok())
+}
+
+/// Name, or name with number if it is already taken.
+fn unique_thread_name(name: &str, names: &[String]) -> String {
+    let mut unique = name.to_string();
+    let mut i: usize = 2;
+    while names.contains(&unique) {
+        unique = format!("{name} ({i})");
+        i += 1;
+    }
+    unique
+}
+
 fn generate_thread_name(names: Vec<String>) -> String {
     let mut i: usize = 1;
     while i < 100_000 {
@@ -14,15 +14,20 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::{
     application::Application,
-    audio::Audio,
+    audio::{Audio, playlist::Playlist},
+    command::Command,
     gui::events::{Event, Events},
     scene::{Scene, Point},
 };
-use egui::{Label, Sense, Ui, UiBuilder, Vec2, load::SizedTexture, vec2};
+use egui::{Label, Sense, TextureHandle, Ui, UiBuilder, Vec2, load::SizedTexture, vec2};
 use rfd::FileDialog;
 
 pub struct MapWidget {
@@ -63,7 +68,20 @@ impl MapWidget {
     }
 
     fn remove_child_map(&mut self, point: Point) {
-        self.map.borrow_mut().erase_map(point);
+        self.application.borrow_mut().execute(Command::RemoveScene {
+            parent: Rc::clone(&self.map),
+            point,
+        });
+    }
+
+    /// Go to the root scene if the shown one is removed by undo.
+    pub fn sync_with_application(&mut self) {
+        let root = self.application.borrow().get_root_map();
+        if !Scene::contains_scene(&root, &self.map) {
+            self.map = root;
+            self.is_root = true;
+            self.hide_map = self.map.borrow().get_background_path().is_none();
+        }
     }
 
     fn select_playlist(&self, audio: Audio, events: &mut Events) {
@@ -74,12 +92,15 @@ impl MapWidget {
     }
 
     fn add_playlist(&mut self) {
-        let i = self.map.borrow().audio_count();
-        self.map.borrow_mut().push_new_audio();
-        let comp = self.map.borrow().get_audio(i);
+        let playlist: Audio = Rc::new(RefCell::new(Box::new(Playlist::new())));
+        self.application.borrow_mut().execute(Command::InsertPlaylist {
+            scene: Rc::clone(&self.map),
+            index: self.map.borrow().audio_count(),
+            playlist: Rc::clone(&playlist),
+        });
         self.application
             .borrow_mut()
-            .select_playlist(Some(comp));
+            .select_playlist(Some(playlist));
     }
 
     pub fn update(&mut self, ctx: &egui::Context, ui: &mut Ui, events: &mut Events) {
@@ -158,7 +179,10 @@ impl MapWidget {
                             }
 
                             if let Some(index) = remove_after_render {
-                                self.map.borrow_mut().erase_audio(index);
+                                self.application.borrow_mut().execute(Command::RemovePlaylist {
+                                    scene: Rc::clone(&self.map),
+                                    index,
+                                });
                             }
                         });
 
@@ -227,6 +251,23 @@ impl MapWidget {
                 events.push_back(Event::ToggleTheme);
                 self.application.borrow_mut().reverse_colors();
             };
+
+            let can_redo = self.application.borrow().can_redo();
+            if ui
+                .add_enabled(can_redo, egui::Button::new("⟳"))
+                .on_hover_text(t!("redo"))
+                .clicked()
+            {
+                events.push_back(Event::Redo);
+            }
+            let can_undo = self.application.borrow().can_undo();
+            if ui
+                .add_enabled(can_undo, egui::Button::new("⟲"))
+                .on_hover_text(t!("undo"))
+                .clicked()
+            {
+                events.push_back(Event::Undo);
+            }
         });
     }
 
@@ -238,7 +279,10 @@ impl MapWidget {
             ui.horizontal(|ui| {
                 ui.add_space(5.0);
                 if self.map.borrow().get_background().is_some() && ui.button("🗙").clicked() {
-                    self.map.borrow_mut().remove_background();
+                    self.application.borrow_mut().execute(Command::SetBackground {
+                        scene: Rc::clone(&self.map),
+                        path: None,
+                    });
                     map_removed = true;
                 }
                 self.reneder_tools_panel(ctx, ui, events);
@@ -291,8 +335,12 @@ impl MapWidget {
         {
             let x = (pos.x - o.x - 0.5 * child_radius * w) / w + 0.5;
             let y = (pos.y - o.y - 0.5 * child_radius * w) / h + 0.5;
-            let parent = Rc::new(RefCell::new(Scene::new(Some(Rc::clone(&self.map)))));
-            self.map.borrow_mut().insert_map(Point { x, y }, parent);
+            let child = Rc::new(RefCell::new(Scene::new(Some(Rc::clone(&self.map)))));
+            self.application.borrow_mut().execute(Command::InsertScene {
+                parent: Rc::clone(&self.map),
+                point: Point { x, y },
+                scene: child,
+            });
         }
 
         // Render childs
@@ -335,22 +383,23 @@ impl MapWidget {
             .add_filter("Image", &["png", "jpg", "jpeg", "webp", "bmp"])
             .pick_file();
 
-        if let Some(path) = path {
-            // TODO: add load animation
-            // TODO: cut map rectangle from image
-            self.try_load_background(ctx, path);
+        // TODO: add load animation
+        // TODO: cut map rectangle from image
+        if let Some(path) = path
+            && let Some(handle) = load_texture(ctx, &path)
+        {
+            self.application.borrow_mut().execute(Command::SetBackground {
+                scene: Rc::clone(&self.map),
+                path: Some(path),
+            });
+            self.map.borrow_mut().set_background(handle);
         }
         self.show_open_map_dialog = false;
     }
 
     fn try_load_background(&mut self, ctx: &egui::Context, path: PathBuf) {
-        if let Ok(image_data) = load_image_from_path(path.to_str().unwrap()) {
-            let handle = ctx.load_texture(
-                path.to_str().unwrap(), // A unique name for the texture
-                image_data,
-                Default::default(),
-            );
-            self.map.borrow_mut().set_background(path, handle);
+        if let Some(handle) = load_texture(ctx, &path) {
+            self.map.borrow_mut().set_background(handle);
         }
     }
 }
@@ -364,6 +413,15 @@ fn scale_texture(win_w: f32, win_h: f32, w: f32, h: f32) -> (f32, f32) {
     }
 }
 
+fn load_texture(ctx: &egui::Context, path: &Path) -> Option<TextureHandle> {
+    let image_data = load_image_from_path(path.to_str().unwrap()).ok()?;
+    Some(ctx.load_texture(
+        path.to_str().unwrap(), // A unique name for the texture
+        image_data,
+        Default::default(),
+    ))
+}
+
 /// Helper function to load image data
 fn load_image_from_path(path: &str) -> Result<egui::ColorImage, String> {
     // TODO: fix performance.
@@ -14,49 +14,142 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    rc::Rc,
+    sync::Arc,
+};
 
-use egui::{Color32, Label, RichText, Sense, Ui};
-use rfd::FileDialog;
+use egui::{Color32, Galley, Label, RichText, Sense, TextBuffer, Ui, text::LayoutJob};
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageDialogResult};
 
 use crate::{
-    application::Application, audio::{Audio, track::Track}, colors, gui::{
+    application::{Application, MissingTrack}, history, audio::{Audio, smartthread::SmartThread, track::Track}, colors, command::Command, gui::{
         events::{Event, Events},
         widgets,
-    }, storage::{Storage, StorageCredentials}
+    }, project::PROJECT_EXTENSION, storage::{
+        Storage, StorageCredentials,
+        duplicates::{DuplicateGroup, DuplicateKind},
+        pathtags::{FolderTags, PathRules, Rewrite},
+        query::{Query, QueryError},
+        tag::Tag,
+        tagexport,
+    }
 };
 
+/// Address of network storage to add.
+#[derive(Default)]
+struct RemoteForm {
+    /// Subsonic server, else WebDAV folder.
+    subsonic: bool,
+    url: String,
+    user: String,
+    password: String,
+}
+
 pub struct StorageWidget {
     caption: widgets::EditableHeader,
     search_pattern: String,
-    shown_music: Vec<usize>,
-    storage: Rc<RefCell<Storage>>,
-    edit_track_index: Option<usize>,
+    search_error: Option<QueryError>,
+    /// Pairs of storage and source indexes.
+    shown_music: Vec<(usize, usize)>,
+    storages: Vec<Rc<RefCell<Storage>>>,
+    /// Shown storage, all storages are shown if None.
+    selected: Option<usize>,
+    edit_track_index: Option<(usize, usize)>,
+    missing_files: Option<(Vec<MissingTrack>, usize)>,
+    /// Found duplicates: storage index, group and index of the kept source.
+    duplicates: Option<Vec<(usize, DuplicateGroup, usize)>>,
+    show_import_options: bool,
+    remote_form: Option<RemoteForm>,
+    /// Password typed in settings of remote storage.
+    password: String,
+    /// Imported tags replace tags of sources.
+    replace_tags: bool,
+    /// Result of tags export or import.
+    tags_message: Option<String>,
+    /// Sum of storage revisions, smart threads are updated on its change.
+    revision: u64,
+    /// Selected pairs of storage and source indexes.
+    selection: BTreeSet<(usize, usize)>,
+    /// Row of shown music where shift selection starts.
+    selection_anchor: Option<usize>,
+    /// Tag to attach to or remove from selected sources.
+    bulk_tag: String,
+    /// Local storages which folders are not found on project open.
+    missing_roots: Vec<(usize, PathBuf)>,
+    /// Position and filename of playing tracks, updated every frame.
+    current_tracks: Vec<(f32, String)>,
     application: Rc<RefCell<Application>>,
 }
 
 impl StorageWidget {
-    pub fn new(
-        storage: Rc<RefCell<Storage>>,
-        application: Rc<RefCell<Application>>,
-    ) -> StorageWidget {
+    pub fn new(application: Rc<RefCell<Application>>) -> StorageWidget {
         let mut widget = StorageWidget {
             caption: widgets::EditableHeader::new("".to_string()),
             search_pattern: "".to_string(),
-            storage,
+            search_error: None,
+            storages: vec![],
+            selected: Some(0),
             shown_music: vec![],
             edit_track_index: None,
+            missing_files: None,
+            duplicates: None,
+            show_import_options: false,
+            remote_form: None,
+            password: String::new(),
+            replace_tags: false,
+            tags_message: None,
+            revision: 0,
+            missing_roots: vec![],
+            current_tracks: vec![],
+            selection: BTreeSet::new(),
+            selection_anchor: None,
+            bulk_tag: String::new(),
             application,
         };
         widget.sync_with_storage();
+        widget.missing_roots = widget.application.borrow().missing_roots();
         widget
     }
 
     pub fn sync_with_storage(&mut self) {
-        self.caption.set_text(self.storage.borrow().get_caption());
+        self.storages = self.application.borrow().get_storages();
+        if self.storages.len() > 1 {
+            self.selected = self.selected.filter(|i| *i < self.storages.len());
+        } else {
+            self.selected = Some(0);
+        }
+        self.edit_track_index = None;
+        self.selection.clear();
+        self.select(self.selected);
+    }
+
+    fn select(&mut self, selected: Option<usize>) {
+        self.selected = selected;
+        if let Some(storage) = self.current() {
+            self.caption.set_text(storage.borrow().get_caption());
+        }
         self.find();
     }
 
+    /// Selected storage.
+    fn current(&self) -> Option<Rc<RefCell<Storage>>> {
+        self.selected.and_then(|i| self.storages.get(i)).cloned()
+    }
+
+    fn storage(&self, index: usize) -> std::cell::Ref<'_, Storage> {
+        self.storages[index].borrow()
+    }
+
+    /// Change tags of storage so it could be undone.
+    fn edit_tags<T>(&self, index: usize, edit: impl FnOnce(&mut Storage) -> T) -> T {
+        let storage = &self.storages[index];
+        self.application.borrow_mut().edit_tags(storage, edit)
+    }
+
     fn open_project(&mut self, events: &mut Events) {
         let path = FileDialog::new()
             .set_title(t!("open_project"))
@@ -69,31 +162,192 @@ impl StorageWidget {
         }
     }
 
+    fn add_storage(&mut self, events: &mut Events) {
+        let path = FileDialog::new()
+            .set_title(t!("add_storage"))
+            .pick_folder();
+
+        if let Some(path) = path {
+            events.push_back(Event::AddStorage {
+                credentials: StorageCredentials::Local(path),
+                password: String::new(),
+            });
+        }
+    }
+
+    fn add_archives(&mut self, events: &mut Events) {
+        let paths = FileDialog::new()
+            .set_title(t!("add_archive_storage"))
+            .add_filter(t!("zip_file_type"), &["zip"])
+            .pick_files();
+
+        if let Some(paths) = paths {
+            events.push_back(Event::AddStorage {
+                credentials: StorageCredentials::Zip(paths),
+                password: String::new(),
+            });
+        }
+    }
+
+    fn open_project_file(&mut self, events: &mut Events) {
+        let path = FileDialog::new()
+            .set_title(t!("open_project_file"))
+            .add_filter(t!("project_file_type"), &[PROJECT_EXTENSION])
+            .pick_file();
+
+        if let Some(path) = path {
+            events.push_back(Event::OpenProject { path });
+        }
+    }
+
     fn save_project(&self, events: &mut Events) {
         let path = FileDialog::new()
             .set_title(t!("save_project"))
+            .add_filter(t!("project_file_type"), &[PROJECT_EXTENSION])
             .add_filter(t!("storage_file_type"), &["yaml"])
             .save_file();
 
         if let Some(path) = path {
-            events.push_back(Event::SaveProject { path });
+            // Only project file could contain audio.
+            let embed_audio = path.extension().is_some_and(|e| e == PROJECT_EXTENSION)
+                && MessageDialog::new()
+                    .set_title(t!("save_project"))
+                    .set_description(t!("embed_audio_question"))
+                    .set_buttons(MessageButtons::YesNo)
+                    .show()
+                    == MessageDialogResult::Yes;
+            events.push_back(Event::SaveProject { path, embed_audio });
+        }
+    }
+
+    /// Recheck missing files if report is shown.
+    pub fn refresh_missing_files(&mut self) {
+        if self.missing_files.is_some() {
+            self.show_missing_files();
+        }
+    }
+
+    fn show_missing_files(&mut self) {
+        let tracks = self.application.borrow().missing_tracks();
+        let sources = self.storages.iter().map(|s| s.borrow().missing().len()).sum();
+        self.missing_files = Some((tracks, sources));
+    }
+
+    fn find_duplicates(&mut self) {
+        let mut duplicates = vec![];
+        for (i, storage) in self.storages.iter().enumerate() {
+            if self.selected.is_none_or(|selected| selected == i) {
+                for group in storage.borrow().find_duplicates() {
+                    let keep = group.sources[0];
+                    duplicates.push((i, group, keep));
+                }
+            }
+        }
+        self.duplicates = Some(duplicates);
+    }
+
+    /// Ask new folder of storage which root is not found.
+    fn locate_root(&mut self, index: usize, events: &mut Events) {
+        let path = FileDialog::new()
+            .set_title(t!("locate_folder"))
+            .pick_folder();
+
+        if let Some(path) = path {
+            self.missing_roots.retain(|(i, _)| *i != index);
+            events.push_back(Event::RemapStorageRoot { index, path });
+        }
+    }
+
+    fn relink(&self, events: &mut Events) {
+        let path = FileDialog::new()
+            .set_title(t!("relink_directory"))
+            .pick_folder();
+
+        if let Some(path) = path {
+            events.push_back(Event::Relink { path });
         }
     }
 
-    fn find(&mut self) {
-        let pattern = self.search_pattern.clone();
-        self.shown_music = self.storage.borrow().find(pattern);
+    /// Search in selected storage or in all storages merged by relevance.
+    pub fn find(&mut self) {
+        let query = match Query::parse(&self.search_pattern) {
+            Ok(query) => query,
+            // Keep previous results while query is typing.
+            Err(err) => {
+                self.search_error = Some(err);
+                return;
+            }
+        };
+        self.search_error = None;
+
+        let mut found = vec![];
+        for (i, storage) in self.storages.iter().enumerate() {
+            if self.selected.is_none_or(|selected| selected == i) {
+                let scored = storage.borrow().find(&query);
+                found.extend(scored.into_iter().map(|(index, score)| ((i, index), score)));
+            }
+        }
+        // Stable sort keeps storage order for equal scores.
+        found.sort_by(|a, b| b.1.total_cmp(&a.1));
+        self.shown_music = found.into_iter().map(|(index, _)| index).collect();
+        self.selection_anchor = None;
     }
 
-    fn send_source_to_player(&self, index: usize, events: &mut Events) {
+    /// Toggle selection of shown row, or select rows from the previous one.
+    fn select_row(&mut self, row: usize, range: bool) {
+        match self.selection_anchor.filter(|_| range) {
+            Some(anchor) => {
+                let rows = anchor.min(row)..=anchor.max(row);
+                self.selection.extend(self.shown_music[rows].iter().copied());
+            }
+            None => {
+                let index = self.shown_music[row];
+                if !self.selection.remove(&index) {
+                    self.selection.insert(index);
+                }
+            }
+        }
+        self.selection_anchor = Some(row);
+    }
+
+    /// Selected source indexes of every storage.
+    fn selection_by_storage(&self) -> BTreeMap<usize, Vec<usize>> {
+        let mut selection: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &(storage, index) in &self.selection {
+            selection.entry(storage).or_default().push(index);
+        }
+        selection
+    }
+
+    fn export_selected_tags(&mut self) {
+        let path = FileDialog::new()
+            .set_title(t!("export_tags"))
+            .add_filter("CSV", &["csv"])
+            .add_filter("JSON", &["json"])
+            .set_file_name("tags.csv")
+            .save_file();
+        if let Some(path) = path {
+            let records: Vec<_> = self
+                .selection_by_storage()
+                .iter()
+                .flat_map(|(&storage, indexes)| self.storage(storage).tag_records_of(indexes))
+                .collect();
+            self.tags_message = Some(match tagexport::save_records(&path, &records) {
+                Ok(()) => format!("{} {}", t!("tags_exported"), path.display()),
+                Err(e) => format!("{}: {}", t!("error"), e),
+            });
+        }
+    }
+
+    fn send_source_to_player(&self, (storage, index): (usize, usize), events: &mut Events) {
         let audio: Audio = Rc::new(RefCell::new(Box::new(Track::new(
-            self.storage.borrow().get(index).unwrap(),
+            self.storage(storage).get(index).unwrap(),
         ))));
         events.push_back(Event::Play { audio });
     }
 
-    fn send_source_to_map(&self, index: usize, events: &mut Events) {
-        let source = self.storage.borrow().get(index).unwrap();
+    fn send_source_to_map(&self, (storage, index): (usize, usize), events: &mut Events) {
+        let source = self.storage(storage).get(index).unwrap();
         let audio: Audio = Rc::new(RefCell::new(Box::new(Track::new(source))));
         events.push_back(Event::AddAudioToPlaylist { audio });
     }
@@ -103,31 +357,257 @@ impl StorageWidget {
             self.render_edit_track_dialog(ctx, ui, index, events);
         }
 
+        if self.missing_files.is_some() {
+            self.render_missing_files_dialog(ctx, events);
+        }
+
+        if !self.missing_roots.is_empty() {
+            self.render_missing_roots_dialog(ctx, events);
+        }
+
+        if self.show_import_options {
+            self.render_import_options_dialog(ctx);
+        }
+
+        if self.duplicates.is_some() {
+            self.render_duplicates_dialog(ctx, events);
+        }
+
+        if self.remote_form.is_some() {
+            self.render_remote_storage_dialog(ctx, events);
+        }
+
+        let mut scan_finished = false;
+        for storage in &self.storages {
+            scan_finished |= storage.borrow_mut().poll_scan();
+        }
+        if scan_finished {
+            // Sources are reordered by scan.
+            self.selection.clear();
+            self.find();
+        }
+        let revision = self
+            .storages
+            .iter()
+            .fold(0, |sum: u64, s| sum.wrapping_add(s.borrow().get_revision()));
+        if scan_finished || revision != self.revision {
+            self.revision = revision;
+            events.push_back(Event::StorageChanged);
+        }
+        for storage in &self.storages {
+            storage.borrow_mut().poll_analysis();
+            storage.borrow_mut().poll_waveforms();
+        }
+        let player = self.application.borrow().get_player();
+        self.current_tracks = player.borrow().get_current_tracks();
+
         ui.add_space(10.0);
         ui.horizontal(|ui| {
             if ui.button("🗁".to_string()).clicked() {
                 self.open_project(events)
             };
+            if ui
+                .button("📦".to_string())
+                .on_hover_text(t!("open_project_file"))
+                .clicked()
+            {
+                self.open_project_file(events)
+            };
             if ui.button("💾".to_string()).clicked() {
                 self.save_project(events)
             };
+            if ui
+                .button("＋".to_string())
+                .on_hover_text(t!("add_storage"))
+                .clicked()
+            {
+                self.add_storage(events)
+            };
+            if ui
+                .button("🗜".to_string())
+                .on_hover_text(t!("add_archive_storage"))
+                .clicked()
+            {
+                self.add_archives(events)
+            };
+            if ui
+                .button("🌐".to_string())
+                .on_hover_text(t!("add_remote_storage"))
+                .clicked()
+            {
+                self.remote_form = Some(RemoteForm::default());
+            };
+            if ui
+                .button("⚠".to_string())
+                .on_hover_text(t!("missing_files"))
+                .clicked()
+            {
+                self.show_missing_files();
+            };
+            if ui
+                .button("⧉".to_string())
+                .on_hover_text(t!("find_duplicates"))
+                .clicked()
+            {
+                self.find_duplicates();
+            };
+            if ui
+                .add_enabled(self.selected.is_some(), egui::Button::new("⚙".to_string()))
+                .on_hover_text(t!("import_options"))
+                .clicked()
+            {
+                self.show_import_options = true;
+            };
+            let is_scanning = self.storages.iter().any(|s| s.borrow().is_scanning());
+            if ui
+                .add_enabled(!is_scanning, egui::Button::new("⟳".to_string()))
+                .on_hover_text(t!("rescan"))
+                .clicked()
+            {
+                for (i, storage) in self.storages.iter().enumerate() {
+                    if self.selected.is_none_or(|selected| selected == i) {
+                        storage.borrow_mut().rescan();
+                    }
+                }
+            };
+            let is_analyzing = self.storages.iter().any(|s| s.borrow().is_analyzing());
+            if ui
+                .add_enabled(!is_analyzing, egui::Button::new("〰".to_string()))
+                .on_hover_text(t!("analyze_audio"))
+                .clicked()
+            {
+                for (i, storage) in self.storages.iter().enumerate() {
+                    if self.selected.is_none_or(|selected| selected == i) {
+                        storage.borrow_mut().analyze();
+                    }
+                }
+            };
+            let can_remove = self.selected.is_some() && self.storages.len() > 1;
+            if ui
+                .add_enabled(can_remove, egui::Button::new("🗑".to_string()))
+                .on_hover_text(t!("remove_storage"))
+                .clicked()
+            {
+                events.push_back(Event::RemoveStorage {
+                    index: self.selected.unwrap(),
+                });
+            };
             ui.vertical_centered(|ui| {
-                if let Some(new_caption) = self.caption.update(ui) {
-                    self.storage.borrow_mut().set_caption(new_caption);
+                if let Some(storage) = self.current() {
+                    if let Some(caption) = self.caption.update(ui) {
+                        self.application
+                            .borrow_mut()
+                            .execute(Command::SetCaption { storage, caption });
+                    }
+                } else {
+                    ui.heading(t!("all_storages"));
                 }
             });
         });
 
+        if self.storages.len() > 1 {
+            self.render_storage_switcher(ui);
+        }
+
+        let scan_progress = self
+            .storages
+            .iter()
+            .filter_map(|s| s.borrow().scan_progress())
+            .reduce(|a, b| (a.0 + b.0, a.1 + b.1));
+        if let Some((done, total)) = scan_progress {
+            let progress = if total == 0 { 0.0 } else { done as f32 / total as f32 };
+            ui.add(
+                egui::ProgressBar::new(progress)
+                    .text(format!("{} {done}/{total}", t!("scanning")))
+                    .desired_height(14.0),
+            );
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+        let analysis_progress = self
+            .storages
+            .iter()
+            .filter_map(|s| s.borrow().analysis_progress())
+            .reduce(|a, b| (a.0 + b.0, a.1 + b.1));
+        if let Some((done, total)) = analysis_progress {
+            let progress = if total == 0 { 0.0 } else { done as f32 / total as f32 };
+            ui.add(
+                egui::ProgressBar::new(progress)
+                    .text(format!("{} {done}/{total}", t!("analyzing")))
+                    .desired_height(14.0),
+            );
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+        let waveform_progress = self
+            .storages
+            .iter()
+            .filter_map(|s| s.borrow().waveform_progress())
+            .reduce(|a, b| (a.0 + b.0, a.1 + b.1));
+        if let Some((done, total)) = waveform_progress {
+            let progress = if total == 0 { 0.0 } else { done as f32 / total as f32 };
+            ui.add(
+                egui::ProgressBar::new(progress)
+                    .text(format!("{} {done}/{total}", t!("generating_waveforms")))
+                    .desired_height(14.0),
+            );
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+        for storage in &self.storages {
+            if let Some(error) = storage.borrow().get_scan_error() {
+                ui.label(
+                    RichText::new(format!("{}: {}", storage.borrow().get_caption(), error))
+                        .color(ui.visuals().error_fg_color),
+                );
+            }
+        }
+
         ui.add_space(10.0);
         ui.separator();
         ui.horizontal(|ui| {
             ui.label("🔎".to_string());
-            let search =
-                egui::TextEdit::singleline(&mut self.search_pattern).hint_text(t!("search_title_or_tag"));
+            let error_span = self.search_error.as_ref().map(|e| e.span.clone());
+            let mut layouter = |ui: &Ui, text: &dyn TextBuffer, _wrap_width: f32| {
+                highlight_query(ui, text.as_str(), error_span.clone())
+            };
+            let search = egui::TextEdit::singleline(&mut self.search_pattern)
+                .hint_text(t!("search_title_or_tag"))
+                .layouter(&mut layouter);
             if ui.add(search).changed() {
                 self.find();
             }
+            let has_playlist = self
+                .application
+                .borrow()
+                .get_selected_playlist()
+                .borrow()
+                .is_some();
+            let is_valid = self.search_error.is_none() && !self.search_pattern.trim().is_empty();
+            if ui
+                .add_enabled(has_playlist && is_valid, egui::Button::new("＋🔎".to_string()))
+                .on_hover_text(t!("add_smart_thread"))
+                .clicked()
+            {
+                let thread = SmartThread::new(self.search_pattern.trim().to_string());
+                let audio: Audio = Rc::new(RefCell::new(Box::new(thread)));
+                events.push_back(Event::AddAudioToPlaylist { audio });
+            }
+            if ui
+                .add_enabled(!self.shown_music.is_empty(), egui::Button::new("☑".to_string()))
+                .on_hover_text(t!("select_all"))
+                .clicked()
+            {
+                self.selection.extend(self.shown_music.iter().copied());
+            }
         });
+        if let Some(err) = &self.search_error {
+            ui.label(
+                RichText::new(format!("{}: {}", t!("query_error"), err))
+                    .color(ui.visuals().error_fg_color),
+            );
+        }
+
+        if !self.selection.is_empty() {
+            self.render_selection_bar(ui, events);
+        }
 
         ui.add_space(10.0);
         let text_style = egui::TextStyle::Body;
@@ -138,7 +618,7 @@ impl StorageWidget {
             .auto_shrink(false)
             .show_rows(ui, row_height, self.shown_music.len(), |ui, range| {
                 for i in range {
-                    let new_search_pattern = self.render_music(ui, self.shown_music[i], events);
+                    let new_search_pattern = self.render_music(ui, i, events);
                     if let Some(pattern) = new_search_pattern {
                         self.search_pattern = pattern;
                         self.find();
@@ -148,16 +628,126 @@ impl StorageWidget {
             });
     }
 
-    /// Display one track. Could return new search pattern
-    fn render_music(&mut self, ui: &mut Ui, index: usize, events: &mut Events) -> Option<String> {
+    fn render_storage_switcher(&mut self, ui: &mut Ui) {
+        let caption = |i: Option<usize>| match i {
+            Some(i) => self.storages[i].borrow().get_caption(),
+            None => t!("all_storages").to_string(),
+        };
+        let mut selected = self.selected;
+        egui::ComboBox::from_id_salt("storage_switcher")
+            .width(ui.available_width())
+            .selected_text(caption(selected))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut selected, None, caption(None));
+                for i in 0..self.storages.len() {
+                    ui.selectable_value(&mut selected, Some(i), caption(Some(i)));
+                }
+            });
+        if selected != self.selected {
+            self.select(selected);
+        }
+    }
+
+    /// Actions with selected sources.
+    fn render_selection_bar(&mut self, ui: &mut Ui, events: &mut Events) {
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            ui.label(format!("{} {}", t!("selected_sources"), self.selection.len()));
+            ui.add(
+                egui::TextEdit::singleline(&mut self.bulk_tag)
+                    .hint_text(t!("tag_path_hint"))
+                    .desired_width(120.0),
+            );
+            let tag = self.bulk_tag.trim().to_string();
+            let attach = ui
+                .add_enabled(!tag.is_empty(), egui::Button::new("🏷＋".to_string()))
+                .on_hover_text(t!("attach_tag_to_selected"))
+                .clicked();
+            let unattach = ui
+                .add_enabled(!tag.is_empty(), egui::Button::new("🏷－".to_string()))
+                .on_hover_text(t!("remove_tag_from_selected"))
+                .clicked();
+            if attach || unattach {
+                let mut commands = vec![];
+                for (storage, sources) in self.selection_by_storage() {
+                    let sources = self.storage(storage).source_filenames(&sources);
+                    let storage = Rc::clone(&self.storages[storage]);
+                    let tag = tag.clone();
+                    commands.push(if attach {
+                        Command::AttachTag { storage, sources, tag }
+                    } else {
+                        Command::UnattachTag { storage, sources, tag }
+                    });
+                }
+                self.application
+                    .borrow_mut()
+                    .execute(Command::Batch(commands));
+                self.find();
+            }
+            if ui
+                .add_enabled(
+                    self.application.borrow().has_selected_playlist(),
+                    egui::Button::new("+".to_string()),
+                )
+                .on_hover_text(t!("add_selected_to_playlist"))
+                .clicked()
+            {
+                for &index in &self.selection {
+                    self.send_source_to_map(index, events);
+                }
+            }
+            if ui
+                .button("💾".to_string())
+                .on_hover_text(t!("export_tags"))
+                .clicked()
+            {
+                self.export_selected_tags();
+            }
+            if ui
+                .button("🗙".to_string())
+                .on_hover_text(t!("clear_selection"))
+                .clicked()
+            {
+                self.selection.clear();
+                self.tags_message = None;
+            }
+        });
+        if let Some(message) = &self.tags_message {
+            ui.label(message);
+        }
+    }
+
+    /// Display one row of shown music. Could return new search pattern
+    fn render_music(&mut self, ui: &mut Ui, row: usize, events: &mut Events) -> Option<String> {
+        let index = self.shown_music[row];
         let mut new_search_pattern = None;
         ui.horizontal(|ui| {
-            let title_label = Label::new(self.storage.borrow().get(index).unwrap().get_title())
-                .sense(Sense::click())
-                .selectable(false);
+            let source = self.storage(index.0).get(index.1).unwrap();
+            let mut title = RichText::new(source.get_title());
+            if self.selection.contains(&index) {
+                title = title.background_color(ui.visuals().selection.bg_fill);
+            }
+            let title_label = Label::new(title).sense(Sense::click()).selectable(false);
             let ui_label = ui.add(title_label);
+            let metadata = source.metadata();
+            let hover: Vec<&str> = [&metadata.artist, &metadata.album, &metadata.genre]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.as_str())
+                .collect();
+            let ui_label = if hover.is_empty() {
+                ui_label
+            } else {
+                ui_label.on_hover_text(hover.join("\n"))
+            };
             if ui_label.clicked() {
-                self.send_source_to_player(index, events);
+                // Ctrl toggles selection, shift selects a range.
+                let modifiers = ui.input(|i| i.modifiers);
+                if modifiers.command || modifiers.shift {
+                    self.select_row(row, modifiers.shift);
+                } else {
+                    self.send_source_to_player(index, events);
+                }
             }
 
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
@@ -168,8 +758,33 @@ impl StorageWidget {
                     self.send_source_to_map(index, events);
                 }
 
+                let waveform = self.storage(index.0).get_waveform(index.1);
+                if let Some(waveform) = waveform {
+                    let filename = source.get_filename();
+                    let position = self
+                        .current_tracks
+                        .iter()
+                        .find(|(_, current)| *current == filename)
+                        .map(|(position, _)| *position);
+                    widgets::waveform(ui, &waveform, position, egui::vec2(60.0, 14.0));
+                }
+
+                let stats = self.application.borrow().play_stats(&source.get_filename());
+                if let Some(stats) = stats {
+                    let ago = format_ago(history::now().saturating_sub(stats.last_played));
+                    ui.label(RichText::new(format!("{}× {}", stats.count, ago)).weak())
+                        .on_hover_text(format!(
+                            "{}: {}\n{}: {}",
+                            t!("play_count"),
+                            stats.count,
+                            t!("last_played"),
+                            ago
+                        ));
+                }
+
                 let mut total_length = 0;
-                for tag in self.storage.borrow().get_tags(index) {
+                let storage = Rc::clone(&self.storages[index.0]);
+                for tag in storage.borrow().get_tags(index.1) {
                     // TODO: contrasting background and text colors
                     let frame = egui::Frame::new()
                         .fill(Color32::from_hex(&tag.get_color()).unwrap())
@@ -181,13 +796,15 @@ impl StorageWidget {
                             ui.add(Label::new("   ").sense(Sense::hover()))
                                 .on_hover_text(tag.get_text())
                         } else {
-                            total_length += tag.get_text().len();
-                            ui.label(RichText::new(tag.get_text()).color(colors::text_color()))
+                            // Child tags are shown by name, full path on hover.
+                            total_length += tag.get_name().len();
+                            ui.label(RichText::new(tag.get_name()).color(colors::text_color()))
+                                .on_hover_text(tag.get_text())
                         };
 
                         // Search tag on clicked.
                         if response.clicked() {
-                            new_search_pattern = Some(tag.get_text());
+                            new_search_pattern = Some(format!("tag:\"{}\"", tag.get_text()));
                         }
 
                         if response.secondary_clicked() {
@@ -205,7 +822,7 @@ impl StorageWidget {
         &mut self,
         ctx: &egui::Context,
         _ui: &mut Ui,
-        index: usize,
+        (storage, index): (usize, usize),
         _events: &mut Events,
     ) {
         egui::Window::new(t!("tag_settings"))
@@ -215,79 +832,711 @@ impl StorageWidget {
                 ui.heading(format!(
                     "{} {}",
                     t!("tags_for"),
-                    self.storage.borrow().get(index).unwrap().get_title()
+                    self.storage(storage).get(index).unwrap().get_title()
                 ));
+                self.render_suggestions(ui, (storage, index));
                 ui.separator();
 
-                let tags = self.storage.borrow().all_tags(index);
+                let mut groups: BTreeMap<String, Vec<(Tag, bool)>> = BTreeMap::new();
+                for (tag, is_checked) in self.storage(storage).all_tags(index) {
+                    groups.entry(tag.group()).or_default().push((tag, is_checked));
+                }
+                let categories = self.storage(storage).tag_categories();
+
                 egui::ScrollArea::vertical()
                     .max_height(400.0)
                     .vscroll(true)
                     .show(ui, |ui| {
-                        for (tag, mut is_checked) in tags {
-                            ui.horizontal(|ui| {
-                                // attach unattach tag
-                                if ui.checkbox(&mut is_checked, "").changed() {
-                                    if is_checked {
-                                        self.storage.borrow_mut().attach_tag(index, tag.get_text());
-                                    } else {
-                                        self.storage
-                                            .borrow_mut()
-                                            .unattach_tag(index, tag.get_text());
+                        // Tags without category are shown last.
+                        let other = groups.remove("");
+                        let groups = groups
+                            .into_iter()
+                            .chain(other.map(|tags| (String::new(), tags)));
+                        for (group, mut tags) in groups {
+                            tags.sort_by_key(|(tag, _)| tag.get_text());
+                            let title = if group.is_empty() {
+                                t!("no_category").to_string()
+                            } else {
+                                group.clone()
+                            };
+                            egui::CollapsingHeader::new(title)
+                                .id_salt(("tag_group", &group))
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    for (tag, is_checked) in tags {
+                                        self.render_tag_row(
+                                            ui,
+                                            (storage, index),
+                                            tag,
+                                            is_checked,
+                                            &categories,
+                                        );
                                     }
-                                }
+                                });
+                        }
+                    });
 
-                                // Pick color
-                                let color = Color32::from_hex(&tag.get_color()).unwrap();
-                                let mut col = [color.r(), color.g(), color.b()];
+                ui.add_space(10.0);
+                ui.vertical_centered(|ui| {
+                    if ui.button(t!("add_tag")).clicked() {
+                        self.application.borrow_mut().execute(Command::InsertTag {
+                            storage: Rc::clone(&self.storages[storage]),
+                            index: self.storage(storage).tag_count(),
+                            tag: Tag::random(),
+                            sources: vec![],
+                        });
+                    }
+                });
+                ui.add_space(10.0);
 
-                                if ui.color_edit_button_srgb(&mut col).changed() {
-                                    let color = Color32::from_rgb_additive(col[0], col[1], col[2]);
-                                    self.storage.borrow_mut().set_tag_color(
-                                        tag.get_text(),
-                                        color.to_hex().chars().take(7).collect(),
-                                    );
-                                }
+                ui.separator();
+                ui.vertical_centered_justified(|ui| {
+                    if ui.button(t!("done")).clicked() {
+                        self.edit_track_index = None;
+                    }
+                });
+            });
+    }
 
-                                // Change tag text
-                                let frame = egui::Frame::new()
-                                    .fill(Color32::from_hex(&tag.get_color()).unwrap())
-                                    .corner_radius(5)
-                                    .inner_margin(egui::Margin::same(2));
+    /// Analyzed features and suggested tags of the source.
+    fn render_suggestions(&self, ui: &mut Ui, (storage, index): (usize, usize)) {
+        let Some(source) = self.storage(storage).get(index) else {
+            return;
+        };
+        if let Some(features) = source.get_features() {
+            let bpm = if features.bpm > 0.0 {
+                format!("{:.0} BPM · ", features.bpm)
+            } else {
+                String::new()
+            };
+            ui.label(
+                RichText::new(format!(
+                    "{bpm}{:.0} dB · {:.0} Hz",
+                    features.loudness, features.brightness
+                ))
+                .weak(),
+            );
+        }
 
-                                let mut text = tag.get_text();
-                                frame.show(ui, |ui| {
-                                    if ui.text_edit_singleline(&mut text).changed() {
-                                        self.storage.borrow_mut().rename_tag(tag.get_text(), text);
-                                    }
-                                });
+        let suggestions = source.get_suggestions();
+        if !suggestions.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(t!("suggested_tags"));
+                for tag in suggestions {
+                    if ui
+                        .button(format!("+ {tag}"))
+                        .on_hover_text(t!("accept_suggestion"))
+                        .clicked()
+                    {
+                        self.edit_tags(storage, |s| s.accept_suggestion(index, tag));
+                    }
+                }
+                if ui.button(t!("accept_all")).clicked() {
+                    self.edit_tags(storage, |s| s.accept_suggestions(&[index]));
+                }
+                if ui.button(t!("reject_all")).clicked() {
+                    self.edit_tags(storage, |s| s.reject_suggestions(&[index]));
+                }
+            });
+        }
+
+        // Suggestions of all storage sources.
+        let suggested = self.storage(storage).suggested_sources();
+        if !suggested.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(format!("{} {}", t!("suggested_sources"), suggested.len()));
+                if ui.button(t!("accept_all")).clicked() {
+                    self.edit_tags(storage, |s| s.accept_suggestions(&suggested));
+                }
+                if ui.button(t!("reject_all")).clicked() {
+                    self.edit_tags(storage, |s| s.reject_suggestions(&suggested));
+                }
+            });
+        }
+    }
+
+    fn render_tag_row(
+        &self,
+        ui: &mut Ui,
+        (storage, index): (usize, usize),
+        tag: Tag,
+        mut is_checked: bool,
+        categories: &[String],
+    ) {
+        ui.horizontal(|ui| {
+            // Children are shifted right of parents.
+            ui.add_space(12.0 * tag.depth() as f32);
+
+            // attach unattach tag
+            if ui.checkbox(&mut is_checked, "").changed() {
+                let (storage, sources, tag) = (
+                    Rc::clone(&self.storages[storage]),
+                    self.storage(storage).source_filenames(&[index]),
+                    tag.get_text(),
+                );
+                self.application.borrow_mut().execute(if is_checked {
+                    Command::AttachTag { storage, sources, tag }
+                } else {
+                    Command::UnattachTag { storage, sources, tag }
+                });
+            }
+
+            // Pick color
+            let color = Color32::from_hex(&tag.get_color()).unwrap();
+            let mut col = [color.r(), color.g(), color.b()];
+
+            if ui.color_edit_button_srgb(&mut col).changed() {
+                let color = Color32::from_rgb_additive(col[0], col[1], col[2]);
+                self.application.borrow_mut().execute(Command::SetTagColor {
+                    storage: Rc::clone(&self.storages[storage]),
+                    tag: tag.get_text(),
+                    color: color.to_hex().chars().take(7).collect(),
+                });
+            }
+
+            // Change tag text
+            let frame = egui::Frame::new()
+                .fill(Color32::from_hex(&tag.get_color()).unwrap())
+                .corner_radius(5)
+                .inner_margin(egui::Margin::same(2));
+
+            let mut text = tag.get_text();
+            frame.show(ui, |ui| {
+                if ui
+                    .text_edit_singleline(&mut text)
+                    .on_hover_text(t!("tag_path_hint"))
+                    .changed()
+                {
+                    self.application.borrow_mut().execute(Command::RenameTag {
+                        storage: Rc::clone(&self.storages[storage]),
+                        old: tag.get_text(),
+                        new: text,
+                    });
+                }
+            });
+
+            // Choose category
+            let mut category = tag.get_category();
+            egui::ComboBox::from_id_salt(("tag_category", tag.get_text()))
+                .width(90.0)
+                .selected_text(category.clone())
+                .show_ui(ui, |ui| {
+                    let mut changed = ui
+                        .selectable_value(&mut category, String::new(), t!("no_category"))
+                        .changed();
+                    for option in categories {
+                        changed |= ui
+                            .selectable_value(&mut category, option.clone(), option)
+                            .changed();
+                    }
+                    changed |= ui
+                        .add(
+                            egui::TextEdit::singleline(&mut category)
+                                .hint_text(t!("new_category")),
+                        )
+                        .lost_focus();
+                    if changed {
+                        self.application.borrow_mut().execute(Command::SetTagCategory {
+                            storage: Rc::clone(&self.storages[storage]),
+                            tag: tag.get_text(),
+                            category,
+                        });
+                    }
+                });
+
+            // Remove tag
+            if ui
+                .label(RichText::new("x".to_string()).color(Color32::RED))
+                .clicked()
+            {
+                self.application.borrow_mut().execute(Command::RemoveTag {
+                    storage: Rc::clone(&self.storages[storage]),
+                    tag: tag.get_text(),
+                });
+            }
+            ui.add_space(20.0);
+        });
+    }
+
+    fn render_missing_files_dialog(&mut self, ctx: &egui::Context, events: &mut Events) {
+        let mut close = false;
+        egui::Window::new(t!("missing_files"))
+            .resizable(true)
+            .default_size(egui::vec2(400.0, 300.0))
+            .show(ctx, |ui| {
+                let (tracks, sources) = self.missing_files.as_ref().unwrap();
+                ui.label(format!("{}: {}", t!("missing_storage_files"), sources));
+                ui.separator();
+
+                if tracks.is_empty() {
+                    ui.label(t!("no_missing_tracks"));
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .vscroll(true)
+                    .show(ui, |ui| {
+                        for track in tracks {
+                            ui.label(RichText::new(&track.title).strong());
+                            ui.label(RichText::new(&track.filename).weak());
+                            ui.add_space(5.0);
+                        }
+                    });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button(t!("relink")).clicked() {
+                        self.relink(events);
+                    }
+                    if ui.button(t!("done")).clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if close {
+            self.missing_files = None;
+        }
+    }
+
+    fn render_missing_roots_dialog(&mut self, ctx: &egui::Context, events: &mut Events) {
+        let mut locate = None;
+        let mut close = false;
+        egui::Window::new(t!("storage_folders_not_found"))
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(t!("locate_folder_hint"));
+                ui.separator();
+                for (index, root) in &self.missing_roots {
+                    ui.horizontal(|ui| {
+                        ui.vertical(|ui| {
+                            let caption = self.storage(*index).get_caption();
+                            ui.label(RichText::new(caption).strong());
+                            ui.label(RichText::new(root.to_string_lossy()).weak());
+                        });
+                        if ui.button(t!("locate_folder")).clicked() {
+                            locate = Some(*index);
+                        }
+                    });
+                    ui.add_space(5.0);
+                }
+                ui.separator();
+                if ui.button(t!("skip")).clicked() {
+                    close = true;
+                }
+            });
+        if let Some(index) = locate {
+            self.locate_root(index, events);
+        }
+        if close {
+            self.missing_roots.clear();
+        }
+    }
+
+    fn export_tags(&mut self, storage: &Rc<RefCell<Storage>>) {
+        let path = FileDialog::new()
+            .set_title(t!("export_tags"))
+            .add_filter("CSV", &["csv"])
+            .add_filter("JSON", &["json"])
+            .set_file_name(format!("{}.csv", storage.borrow().get_caption()))
+            .save_file();
+        if let Some(path) = path {
+            self.tags_message = Some(match storage.borrow().export_tags(&path) {
+                Ok(()) => format!("{} {}", t!("tags_exported"), path.display()),
+                Err(e) => format!("{}: {}", t!("error"), e),
+            });
+        }
+    }
+
+    fn import_tags(&mut self, storage: &Rc<RefCell<Storage>>) {
+        let path = FileDialog::new()
+            .set_title(t!("import_tags"))
+            .add_filter("CSV, JSON", &["csv", "json"])
+            .pick_file();
+        if let Some(path) = path {
+            let replace = self.replace_tags;
+            let result = self
+                .application
+                .borrow_mut()
+                .edit_tags(storage, |s| s.import_tags(&path, replace));
+            self.tags_message = Some(match result {
+                Ok((matched, unmatched)) => format!(
+                    "{} {matched}, {} {unmatched}",
+                    t!("tags_imported"),
+                    t!("tags_not_matched")
+                ),
+                Err(e) => format!("{}: {}", t!("error"), e),
+            });
+            self.find();
+        }
+    }
 
-                                // Remove tag
-                                if ui
-                                    .label(RichText::new("x".to_string()).color(Color32::RED))
-                                    .clicked()
-                                {
-                                    self.storage.borrow_mut().remove_tag(tag.get_text());
+    fn render_duplicates_dialog(&mut self, ctx: &egui::Context, events: &mut Events) {
+        let mut close = false;
+        // Group index and whether copies are hidden, None to skip the group.
+        let mut merged: Option<(usize, Option<bool>)> = None;
+        let mut duplicates = self.duplicates.take().unwrap();
+        egui::Window::new(t!("duplicates"))
+            .resizable(true)
+            .default_size(egui::vec2(450.0, 350.0))
+            .show(ctx, |ui| {
+                if duplicates.is_empty() {
+                    ui.label(t!("no_duplicates"));
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .vscroll(true)
+                    .show(ui, |ui| {
+                        for (g, (storage, group, keep)) in duplicates.iter_mut().enumerate() {
+                            let kind = match group.kind {
+                                DuplicateKind::Exact => t!("exact_duplicates"),
+                                DuplicateKind::Similar => t!("similar_tracks"),
+                            };
+                            ui.label(RichText::new(kind).strong());
+                            for &i in &group.sources {
+                                let Some(source) = self.storage(*storage).get(i) else {
+                                    continue;
+                                };
+                                let duration = source.metadata().duration as u32;
+                                ui.radio_value(
+                                    keep,
+                                    i,
+                                    format!(
+                                        "{} ({}:{:02})",
+                                        source.get_title(),
+                                        duration / 60,
+                                        duration % 60
+                                    ),
+                                )
+                                .on_hover_text(t!("keep_source"));
+                                ui.label(RichText::new(source.get_filename()).weak().small());
+                            }
+                            ui.horizontal(|ui| {
+                                if ui.button(t!("merge_and_hide")).clicked() {
+                                    merged = Some((g, Some(true)));
+                                }
+                                if ui.button(t!("merge_tags")).clicked() {
+                                    merged = Some((g, Some(false)));
+                                }
+                                if ui.button(t!("skip")).clicked() {
+                                    merged = Some((g, None));
                                 }
-                                ui.add_space(20.0);
                             });
+                            ui.separator();
                         }
                     });
 
-                ui.add_space(10.0);
-                ui.vertical_centered(|ui| {
-                    if ui.button(t!("add_tag")).clicked() {
-                        self.storage.borrow_mut().add_tag();
+                ui.horizontal(|ui| {
+                    let hidden: usize =
+                        self.storages.iter().map(|s| s.borrow().hidden_count()).sum();
+                    if ui
+                        .add_enabled(hidden > 0, egui::Button::new(t!("show_hidden")))
+                        .on_hover_text(format!("{}: {hidden}", t!("hidden_sources")))
+                        .clicked()
+                    {
+                        self.application.borrow_mut().unhide_all();
+                        self.find();
+                    }
+                    if ui.button(t!("done")).clicked() {
+                        close = true;
                     }
                 });
-                ui.add_space(10.0);
+            });
+
+        if let Some((g, hide)) = merged {
+            let (storage, group, keep) = duplicates.remove(g);
+            if let Some(hide) = hide {
+                events.push_back(Event::MergeDuplicates {
+                    storage,
+                    keep,
+                    others: group.sources,
+                    hide,
+                });
+            }
+        }
+        if !close {
+            self.duplicates = Some(duplicates);
+        }
+    }
+
+    fn render_import_options_dialog(&mut self, ctx: &egui::Context) {
+        let Some(storage) = self.current() else {
+            self.show_import_options = false;
+            return;
+        };
+        let mut options = storage.borrow().get_import_options();
+        let mut changed = false;
+        egui::Window::new(t!("import_options"))
+            .resizable(false)
+            .show(ctx, |ui| {
+                changed |= ui
+                    .checkbox(&mut options.genre_tags, t!("genre_tags"))
+                    .changed();
+                changed |= ui
+                    .checkbox(&mut options.album_tags, t!("album_tags"))
+                    .changed();
+                changed |= render_path_rules(ui, &mut options.path);
+                ui.label(RichText::new(t!("import_options_hint")).weak());
+
+                ui.separator();
+                ui.label(t!("tag_assignments"));
+                ui.horizontal(|ui| {
+                    if ui.button(t!("export_tags")).clicked() {
+                        self.export_tags(&storage);
+                    }
+                    if ui.button(t!("import_tags")).clicked() {
+                        self.import_tags(&storage);
+                    }
+                    ui.checkbox(&mut self.replace_tags, t!("replace_tags"));
+                });
+                if let Some(message) = &self.tags_message {
+                    ui.label(RichText::new(message).weak());
+                }
+
+                if storage.borrow().is_remote() {
+                    ui.separator();
+                    let mut disk_cache = storage.borrow().get_disk_cache();
+                    if ui.checkbox(&mut disk_cache, t!("disk_cache")).changed() {
+                        self.application
+                            .borrow_mut()
+                            .execute(Command::SetDiskCache {
+                                storage: Rc::clone(&storage),
+                                disk_cache,
+                            });
+                    }
+                    ui.add_enabled_ui(disk_cache, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(t!("disk_cache_limit"));
+                            let mut limit = storage.borrow().get_disk_cache_limit();
+                            let limit_value = egui::DragValue::new(&mut limit).range(64..=1 << 20);
+                            if ui.add(limit_value).changed() {
+                                self.application
+                                    .borrow_mut()
+                                    .execute(Command::SetDiskCacheLimit {
+                                        storage: Rc::clone(&storage),
+                                        limit,
+                                    });
+                            }
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(t!("password"));
+                        ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+                        if ui.button(t!("connect")).clicked() {
+                            let password = std::mem::take(&mut self.password);
+                            let changed =
+                                self.application.borrow_mut().execute(Command::SetPassword {
+                                    storage: Rc::clone(&storage),
+                                    password,
+                                });
+                            // Same password is a retry.
+                            if !changed {
+                                storage.borrow_mut().rescan();
+                            }
+                        }
+                    });
+                }
 
                 ui.separator();
                 ui.vertical_centered_justified(|ui| {
                     if ui.button(t!("done")).clicked() {
-                        self.edit_track_index = None;
+                        self.show_import_options = false;
                     }
                 });
             });
+        if changed {
+            self.application
+                .borrow_mut()
+                .execute(Command::SetImportOptions { storage, options });
+        }
+    }
+
+    fn render_remote_storage_dialog(&mut self, ctx: &egui::Context, events: &mut Events) {
+        let mut close = false;
+        let form = self.remote_form.as_mut().unwrap();
+        egui::Window::new(t!("add_remote_storage"))
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut form.subsonic, false, "WebDAV");
+                    ui.radio_value(&mut form.subsonic, true, "Subsonic / Navidrome");
+                });
+                egui::Grid::new("remote_storage").num_columns(2).show(ui, |ui| {
+                    ui.label(t!("address"));
+                    let hint = if form.subsonic {
+                        "https://music.example.com"
+                    } else {
+                        "https://nas.local/webdav/music"
+                    };
+                    ui.add(egui::TextEdit::singleline(&mut form.url).hint_text(hint));
+                    ui.end_row();
+                    ui.label(t!("user"));
+                    ui.text_edit_singleline(&mut form.user);
+                    ui.end_row();
+                    ui.label(t!("password"));
+                    ui.add(egui::TextEdit::singleline(&mut form.password).password(true));
+                    ui.end_row();
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let is_url = form.url.starts_with("http://") || form.url.starts_with("https://");
+                    if ui.add_enabled(is_url, egui::Button::new(t!("connect"))).clicked() {
+                        let url = form.url.trim().to_string();
+                        let user = form.user.clone();
+                        let credentials = if form.subsonic {
+                            StorageCredentials::Subsonic { url, user }
+                        } else {
+                            StorageCredentials::WebDav { url, user }
+                        };
+                        events.push_back(Event::AddStorage {
+                            credentials,
+                            password: std::mem::take(&mut form.password),
+                        });
+                        close = true;
+                    }
+                    if ui.button(t!("cancel")).clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if close {
+            self.remote_form = None;
+        }
+    }
+}
+
+/// Short time since event, like `5 min ago`.
+pub fn format_ago(seconds: u64) -> String {
+    match seconds {
+        0..60 => t!("just_now").to_string(),
+        60..3600 => format!("{} {}", seconds / 60, t!("minutes_ago")),
+        3600..86400 => format!("{} {}", seconds / 3600, t!("hours_ago")),
+        _ => format!("{} {}", seconds / 86400, t!("days_ago")),
+    }
+}
+
+/// Settings of tags from paths, true if changed.
+fn render_path_rules(ui: &mut Ui, rules: &mut PathRules) -> bool {
+    let mut changed = false;
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.label(t!("folder_tags"));
+        let modes = [
+            (FolderTags::None, t!("folder_tags_none")),
+            (FolderTags::Parent, t!("folder_tags_parent")),
+            (FolderTags::All, t!("folder_tags_all")),
+            (FolderTags::Nested, t!("folder_tags_nested")),
+        ];
+        let selected = modes
+            .iter()
+            .find(|(mode, _)| *mode == rules.folders)
+            .map(|(_, text)| text.to_string())
+            .unwrap_or_default();
+        egui::ComboBox::from_id_salt("folder_tags")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                for (mode, text) in modes {
+                    changed |= ui.selectable_value(&mut rules.folders, mode, text).changed();
+                }
+            });
+    });
+    ui.horizontal(|ui| {
+        ui.label(t!("folder_depth"));
+        changed |= ui
+            .add(egui::DragValue::new(&mut rules.max_depth).range(0..=20))
+            .on_hover_text(t!("folder_depth_hint"))
+            .changed();
+    });
+    ui.horizontal(|ui| {
+        ui.label(t!("ignored_folders"));
+        let mut ignored = rules.ignored.join(",");
+        if ui
+            .add(egui::TextEdit::singleline(&mut ignored).hint_text("CD1, Disc 2"))
+            .changed()
+        {
+            rules.ignored = ignored.split(',').map(|i| i.to_string()).collect();
+            changed = true;
+        }
+    });
+
+    ui.label(t!("rewrites"));
+    let mut removed = None;
+    for (i, rewrite) in rules.rewrites.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(
+                    egui::TextEdit::singleline(&mut rewrite.pattern)
+                        .desired_width(120.0)
+                        .hint_text(t!("pattern")),
+                )
+                .changed();
+            ui.label("→");
+            changed |= ui
+                .add(
+                    egui::TextEdit::singleline(&mut rewrite.replace)
+                        .desired_width(80.0)
+                        .hint_text(t!("replace")),
+                )
+                .changed();
+            if ui
+                .label(RichText::new("x".to_string()).color(Color32::RED))
+                .clicked()
+            {
+                removed = Some(i);
+            }
+        });
+        if let Err(e) = rewrite.regex() {
+            ui.label(RichText::new(e).color(Color32::RED).small());
+        }
+    }
+    if let Some(i) = removed {
+        rules.rewrites.remove(i);
+        changed = true;
+    }
+    if ui.button(t!("add_rewrite")).clicked() {
+        rules.rewrites.push(Rewrite {
+            pattern: r"^\d+\s*-\s*".to_string(),
+            replace: String::new(),
+        });
+        changed = true;
+    }
+
+    changed |= ui
+        .checkbox(&mut rules.filename_tags, t!("filename_tags"))
+        .changed();
+    changed
+}
+
+/// Layout search query, mark error span with red underline.
+fn highlight_query(
+    ui: &Ui,
+    text: &str,
+    error_span: Option<std::ops::Range<usize>>,
+) -> Arc<Galley> {
+    let font_id = egui::TextStyle::Body.resolve(ui.style());
+    let color = ui.visuals().text_color();
+    let normal = egui::TextFormat::simple(font_id.clone(), color);
+    let error = egui::TextFormat {
+        underline: egui::Stroke::new(2.0, ui.visuals().error_fg_color),
+        color: ui.visuals().error_fg_color,
+        ..egui::TextFormat::simple(font_id, color)
+    };
+
+    let mut job = LayoutJob::default();
+    // Span may be stale after editing, so check it is still inside the text.
+    let parts = error_span.and_then(|span| {
+        Some((
+            text.get(..span.start)?,
+            text.get(span.clone())?,
+            text.get(span.end..)?,
+        ))
+    });
+    match parts {
+        Some((before, wrong, after)) => {
+            job.append(before, 0.0, normal.clone());
+            job.append(wrong, 0.0, error);
+            job.append(after, 0.0, normal);
+        }
+        None => job.append(text, 0.0, normal),
     }
+    ui.fonts_mut(|f| f.layout_job(job))
 }
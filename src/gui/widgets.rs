@@ -1,4 +1,6 @@
-use egui::Ui;
+use egui::{Rect, Response, Sense, Stroke, Ui, Vec2, pos2};
+
+use crate::storage::waveform::Waveform;
 
 pub struct EditableHeader {
     text: String,
@@ -33,3 +35,33 @@ impl EditableHeader {
         }
     }
 }
+
+/// Waveform overview: RMS level inside peak level, and position line.
+pub fn waveform(ui: &mut Ui, waveform: &Waveform, position: Option<f32>, size: Vec2) -> Response {
+    let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+    if !ui.is_rect_visible(rect) {
+        return response;
+    }
+    let painter = ui.painter();
+    let visuals = ui.visuals();
+    let peak_color = visuals.weak_text_color().gamma_multiply(0.5);
+    let rms_color = visuals.weak_text_color();
+    let width = rect.width() / waveform.peaks.len().max(1) as f32;
+    let center = rect.center().y;
+    let half_height = |level: u8| (level as f32 / 255.0 * rect.height() / 2.0).max(0.5);
+    for (i, (&peak, &rms)) in waveform.peaks.iter().zip(&waveform.rms).enumerate() {
+        let x = rect.left() + i as f32 * width;
+        for (level, color) in [(peak, peak_color), (rms, rms_color)] {
+            let bar = Rect::from_min_max(
+                pos2(x, center - half_height(level)),
+                pos2(x + width, center + half_height(level)),
+            );
+            painter.rect_filled(bar, 0.0, color);
+        }
+    }
+    if let Some(position) = position {
+        let x = rect.left() + rect.width() * position.clamp(0.0, 1.0);
+        painter.vline(x, rect.y_range(), Stroke::new(1.5, visuals.selection.bg_fill));
+    }
+    response
+}
@@ -0,0 +1,251 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+//! Play log of the project: what was played, where and how long.
+//! The log is a JSON lines file next to the project file, named after it,
+//! new records are appended to it.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Extension of history file, `Campaign.yaml` has `Campaign.history.jsonl`.
+pub const HISTORY_EXTENSION: &str = "history.jsonl";
+
+/// One playing of a source.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct PlayRecord {
+    /// Source filename.
+    pub filename: String,
+    /// Id of playlist, titles of playlists could be the same.
+    #[serde(default)]
+    pub playlist_id: String,
+    #[serde(default)]
+    pub playlist: String,
+    #[serde(default)]
+    pub scene: String,
+    /// Unix time in seconds.
+    pub start: u64,
+    pub end: u64,
+    /// Played to the end, not skipped or stopped.
+    pub completed: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayStats {
+    pub count: usize,
+    /// Unix time of the last playing end.
+    pub last_played: u64,
+}
+
+/// Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+pub struct PlayHistory {
+    records: Vec<PlayRecord>,
+    /// History file of the project, records are kept in memory only if None.
+    file: Option<PathBuf>,
+    /// Records not written to the file yet.
+    unsaved: Vec<PlayRecord>,
+    /// Statistics of records by filename.
+    stats: HashMap<String, PlayStats>,
+}
+
+impl PlayHistory {
+    /// Load history of the project file. Broken lines are skipped.
+    pub fn load(project: &Path) -> PlayHistory {
+        let file = history_file(project);
+        let mut history = PlayHistory {
+            records: read_records(&file),
+            file: Some(file),
+            unsaved: vec![],
+            stats: HashMap::new(),
+        };
+        history.update_stats();
+        history
+    }
+
+    /// Move history to the project file, e.g. after saving the project as
+    /// another file. Records not written yet are added to the history of
+    /// that file, records of the previous file are left there.
+    pub fn set_project(&mut self, project: &Path) {
+        let file = history_file(project);
+        if self.file.as_ref() == Some(&file) {
+            return;
+        }
+        self.records = read_records(&file);
+        self.records.extend(self.unsaved.iter().cloned());
+        self.file = Some(file);
+        self.update_stats();
+        self.flush();
+    }
+
+    pub fn push(&mut self, record: PlayRecord) {
+        add_record(&mut self.stats, &record);
+        self.records.push(record.clone());
+        self.unsaved.push(record);
+        self.flush();
+    }
+
+    /// Statistics of source.
+    pub fn stats(&self, filename: &str) -> Option<PlayStats> {
+        self.stats.get(filename).copied()
+    }
+
+    /// Indexes of files, never played first, then the least recently played.
+    pub fn least_recent_first(&self, filenames: &[String]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..filenames.len()).collect();
+        order.sort_by_key(|&i| {
+            self.stats(&filenames[i])
+                .map(|s| s.last_played)
+                .unwrap_or(0)
+        });
+        order
+    }
+
+    /// Statistics of tracks played in playlist with the id.
+    pub fn playlist_stats(&self, playlist_id: &str) -> Option<PlayStats> {
+        let played = self.records.iter().filter(|r| r.playlist_id == playlist_id);
+        played.fold(None, |stats: Option<PlayStats>, record| {
+            let stats = stats.unwrap_or_default();
+            Some(PlayStats {
+                count: stats.count + 1,
+                last_played: stats.last_played.max(record.end),
+            })
+        })
+    }
+
+    /// Append unsaved records to the history file.
+    fn flush(&mut self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut text = String::new();
+        for record in &self.unsaved {
+            if let Ok(line) = serde_json::to_string(record) {
+                text += &line;
+                text.push('\n');
+            }
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .and_then(|mut file| file.write_all(text.as_bytes()));
+        match written {
+            Ok(()) => self.unsaved.clear(),
+            Err(e) => eprintln!("Error saving play history: {}", e),
+        }
+    }
+
+    fn update_stats(&mut self) {
+        self.stats.clear();
+        for record in &self.records {
+            add_record(&mut self.stats, record);
+        }
+    }
+}
+
+fn add_record(stats: &mut HashMap<String, PlayStats>, record: &PlayRecord) {
+    let entry = stats.entry(record.filename.clone()).or_default();
+    entry.count += 1;
+    entry.last_played = entry.last_played.max(record.end);
+}
+
+fn history_file(project: &Path) -> PathBuf {
+    project.with_extension(HISTORY_EXTENSION)
+}
+
+fn read_records(file: &Path) -> Vec<PlayRecord> {
+    fs::read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(filename: &str, end: u64) -> PlayRecord {
+        PlayRecord {
+            filename: filename.to_string(),
+            playlist_id: "f1".to_string(),
+            playlist: "Fights".to_string(),
+            start: end - 60,
+            end,
+            completed: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn project_history_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("Campaign.yaml");
+        let mut history = PlayHistory::default();
+        history.push(record("battle1.ogg", 1000));
+        history.push(record("battle2.ogg", 2000));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        history.set_project(&project);
+        history.push(record("battle1.ogg", 3000));
+        let path = dir.path().join("Campaign.history.jsonl");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"broken line\n").unwrap();
+
+        // Other project in the same directory has its own history.
+        let other = PlayHistory::load(&dir.path().join("Other.cyberbard"));
+        assert!(other.records.is_empty());
+
+        let history = PlayHistory::load(&project);
+        assert_eq!(history.records.len(), 3);
+        assert_eq!(history.records[2], record("battle1.ogg", 3000));
+        assert_eq!(history.playlist_stats("f1").unwrap().count, 3);
+        // Other playlist of the same title.
+        assert_eq!(history.playlist_stats("f2"), None);
+        assert_eq!(
+            history.stats("battle1.ogg"),
+            Some(PlayStats {
+                count: 2,
+                last_played: 3000
+            })
+        );
+
+        let names = ["battle1.ogg", "battle2.ogg", "battle3.ogg"].map(String::from);
+        assert_eq!(history.least_recent_first(&names), vec![2, 1, 0]);
+
+        // Statistics belong to the history, not to the process.
+        assert_eq!(PlayHistory::default().stats("battle1.ogg"), None);
+        assert_eq!(
+            PlayHistory::default().least_recent_first(&names),
+            vec![0, 1, 2]
+        );
+    }
+}
@@ -26,16 +26,20 @@ use crate::{
     application::Application,
     player::Player,
     scene::Scene,
-    storage::{Storage, StorageCredentials},
+    storage::Storage,
 };
 
 mod application;
 mod audio;
+mod autosave;
 mod colors;
+mod command;
 mod gui;
+mod history;
 mod player;
 mod project;
 mod scene;
+mod schema;
 mod settings;
 mod storage;
 mod stream;
@@ -54,7 +58,7 @@ fn main() {
     let storage: Rc<RefCell<Storage>> = Rc::new(RefCell::new(Storage::new()));
     let map = Rc::new(RefCell::new(Scene::new(None)));
     let player = Rc::new(RefCell::new(Player::new()));
-    let application = Application::new(storage, map, player);
+    let application = Application::new(vec![storage], map, player);
 
     gui::application::run_gui(application, Rc::clone(&settings));
     settings.borrow_mut().language = locale().to_string();
@@ -19,6 +19,7 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use crate::history::PlayRecord;
 use crate::stream::Stream;
 
 enum Command {
@@ -39,7 +40,12 @@ pub struct Player {
     cmd_tx: Sender<Command>,
     paused: bool,
     progress: Arc<Mutex<f32>>,
-    current_playing: Arc<Mutex<Vec<usize>>>
+    current_playing: Arc<Mutex<Vec<usize>>>,
+    /// Position and filename of current track of every thread.
+    current_tracks: Arc<Mutex<Vec<(f32, String)>>>,
+    errors: Arc<Mutex<Vec<String>>>,
+    /// Finished playing of tracks, not taken yet.
+    plays: Arc<Mutex<Vec<PlayRecord>>>,
 }
 
 impl Player {
@@ -47,9 +53,15 @@ impl Player {
         let (cmd_tx, cmd_rx): (Sender<Command>, Receiver<Command>) = mpsc::channel();
         let progress = Arc::new(Mutex::new(0.0));
         let current_playing = Arc::new(Mutex::new(vec![]));
+        let current_tracks = Arc::new(Mutex::new(vec![]));
+        let errors = Arc::new(Mutex::new(vec![]));
+        let plays = Arc::new(Mutex::new(vec![]));
 
         let total_progress = Arc::clone(&progress);
         let current = Arc::clone(&current_playing);
+        let tracks = Arc::clone(&current_tracks);
+        let stream_errors = Arc::clone(&errors);
+        let stream_plays = Arc::clone(&plays);
 
         let _ = thread::spawn(move || {
             let mut opt_stream: Option<Stream> = None;
@@ -68,6 +80,15 @@ impl Player {
                     },
                     Some(stream) => {
                         stream.update();
+                        let new_errors = stream.take_errors();
+                        if !new_errors.is_empty() {
+                            let mut errors = stream_errors.lock().unwrap();
+                            for e in new_errors {
+                                if !errors.contains(&e) {
+                                    errors.push(e);
+                                }
+                            }
+                        }
                         match cmd_rx.try_recv() {
                             Ok(Command::Play) => stream.play(),
 
@@ -81,10 +102,15 @@ impl Player {
                             Ok(Command::Reset) => {
                                 stream.stop();
                                 stream.pause();
+                                stream_plays.lock().unwrap().append(&mut stream.take_plays());
                                 opt_stream = None;
                             }
 
-                            Ok(Command::SetStream(s)) => opt_stream = Some(s),
+                            Ok(Command::SetStream(s)) => {
+                                stream.finish_plays();
+                                stream_plays.lock().unwrap().append(&mut stream.take_plays());
+                                opt_stream = Some(s);
+                            }
                             Ok(Command::SyncStream(s)) => stream.sync(s),
                             Ok(Command::SetVolume(vol)) => {
                                 stream.set_total_volume(vol);
@@ -97,8 +123,17 @@ impl Player {
                             }
 
                             Err(mpsc::TryRecvError::Empty) => {
+                                let mut plays = stream.take_plays();
+                                if !plays.is_empty() {
+                                    stream_plays.lock().unwrap().append(&mut plays);
+                                }
                                 *total_progress.lock().unwrap() = stream.get_position();
                                 *current.lock().unwrap() = stream.get_current_playing();
+                                *tracks.lock().unwrap() = stream
+                                    .get_positions()
+                                    .into_iter()
+                                    .zip(stream.get_current_files())
+                                    .collect();
                                 thread::sleep(Duration::from_millis(30));
                             }
                             Err(mpsc::TryRecvError::Disconnected) => {
@@ -115,7 +150,10 @@ impl Player {
             cmd_tx,
             paused: true,
             progress,
-            current_playing
+            current_playing,
+            current_tracks,
+            errors,
+            plays,
         }
     }
 
@@ -173,6 +211,25 @@ impl Player {
     pub fn get_current_playing(&self) -> Vec<usize> {
         self.current_playing.lock().unwrap().clone()
     }
+
+    /// Position from 0 to 1 and filename of current track of every thread.
+    pub fn get_current_tracks(&self) -> Vec<(f32, String)> {
+        self.current_tracks.lock().unwrap().clone()
+    }
+
+    /// Messages about files failed to play.
+    pub fn get_errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+
+    pub fn clear_errors(&mut self) {
+        self.errors.lock().unwrap().clear();
+    }
+
+    /// Take records of finished playing.
+    pub fn take_plays(&mut self) -> Vec<PlayRecord> {
+        std::mem::take(&mut *self.plays.lock().unwrap())
+    }
 }
 
 impl Default for Player {
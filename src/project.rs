@@ -14,41 +14,435 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
-use std::path::Path;
+//! Self-contained project file: a zip archive with `project.yaml`, scene
+//! backgrounds, optionally embedded audio and a manifest with the format
+//! version. The archive is unpacked to a temporary directory while the
+//! project is open, except embedded audio, which is played from the archive.
 
-use crate::{audio::playlist::Playlist, scene::Scene, storage::Storage};
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
 
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use walkdir::WalkDir;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, result::ZipError, write::SimpleFileOptions};
 
-pub struct Project {
-    storages: Vec<Storage>,
-    root_scene: Scene,
-    playlists: Vec<Playlist>
+use crate::storage::{relink::Relinker, zipstorage};
+
+pub const PROJECT_EXTENSION: &str = "cyberbard";
+/// Version of the archive layout, projects of newer versions are not opened.
+pub const FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.yaml";
+const PROJECT_FILE: &str = "project.yaml";
+const BACKGROUNDS_DIR: &str = "backgrounds";
+const AUDIO_DIR: &str = "audio";
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    #[serde(default)]
+    pub embedded_audio: bool,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            embedded_audio: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(io::Error),
+    Zip(ZipError),
+    /// Archive without manifest.
+    NotAProject,
+    /// Project is saved by a newer version of the player.
+    NewerVersion(u32),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Io(e) => write!(f, "{}", e),
+            ProjectError::Zip(e) => write!(f, "{}", e),
+            ProjectError::NotAProject => write!(f, "not a project file"),
+            ProjectError::NewerVersion(version) => {
+                write!(
+                    f,
+                    "project format {} is newer than {}",
+                    version, FORMAT_VERSION
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+impl From<io::Error> for ProjectError {
+    fn from(e: io::Error) -> Self {
+        ProjectError::Io(e)
+    }
 }
 
+impl From<ZipError> for ProjectError {
+    fn from(e: ZipError) -> Self {
+        ProjectError::Zip(e)
+    }
+}
+
+pub struct Project {
+    /// Unpacked project files.
+    dir: TempDir,
+    manifest: Manifest,
+    /// Opened or saved project file.
+    archive: Option<PathBuf>,
+    /// Embedded audio entries of the project file, they are copied to the
+    /// file on saving. Audio embedded after saving is in `dir`.
+    audio: HashSet<String>,
+}
 
 impl Project {
-    pub fn new() -> Project {
-        // create tmp directory
-        todo!()
+    pub fn new() -> io::Result<Project> {
+        Ok(Project {
+            dir: tempfile::Builder::new().prefix("cyberbard-").tempdir()?,
+            manifest: Manifest::default(),
+            archive: None,
+            audio: HashSet::new(),
+        })
+    }
+
+    /// Unpack project file to a temporary directory. Embedded audio is
+    /// not unpacked, it could be large.
+    pub fn open(path: &Path) -> Result<Project, ProjectError> {
+        let mut project = Project::new()?;
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i)?;
+            let Some(relative) = entry.enclosed_name() else {
+                continue;
+            };
+            if relative.starts_with(AUDIO_DIR) {
+                if entry.is_file() {
+                    project.audio.insert(entry.name().to_string());
+                }
+                continue;
+            }
+            let target = project.dir().join(relative);
+            if entry.is_dir() {
+                fs::create_dir_all(&target)?;
+            } else {
+                fs::create_dir_all(target.parent().unwrap())?;
+                io::copy(&mut entry, &mut File::create(&target)?)?;
+            }
+        }
+        let manifest = fs::read_to_string(project.dir().join(MANIFEST_FILE))
+            .map_err(|_| ProjectError::NotAProject)?;
+        let manifest: Manifest =
+            serde_yaml::from_str(&manifest).map_err(|_| ProjectError::NotAProject)?;
+        if manifest.format_version > FORMAT_VERSION {
+            return Err(ProjectError::NewerVersion(manifest.format_version));
+        }
+        project.manifest = manifest;
+        project.archive = Some(path.to_path_buf());
+        Ok(project)
+    }
+
+    /// Pack project files and embedded audio of the previous project file
+    /// with the manifest. The file is replaced only when the archive is
+    /// written completely.
+    pub fn save(&mut self, path: &Path) -> Result<(), ProjectError> {
+        self.manifest.format_version = FORMAT_VERSION;
+        let manifest = serde_yaml::to_string(&self.manifest).map_err(io::Error::other)?;
+        fs::write(self.dir().join(MANIFEST_FILE), manifest)?;
+
+        let partial = path.with_extension(format!("{PROJECT_EXTENSION}.part"));
+        let mut zip = ZipWriter::new(File::create(&partial)?);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        // Audio is compressed already.
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut written = HashSet::new();
+        for entry in WalkDir::new(self.dir()).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::other)?;
+            let relative = entry.path().strip_prefix(self.dir()).unwrap();
+            let name = entry_name(relative);
+            if entry.file_type().is_dir() {
+                zip.add_directory(name, options)?;
+            } else {
+                let options = if relative.starts_with(AUDIO_DIR) {
+                    stored
+                } else {
+                    options
+                };
+                zip.start_file(name.clone(), options)?;
+                io::copy(&mut File::open(entry.path())?, &mut zip)?;
+                written.insert(name);
+            }
+        }
+        if let Some(archive) = &self.archive {
+            let mut old = ZipArchive::new(File::open(archive)?)?;
+            let mut audio: Vec<&String> = self.audio.difference(&written).collect();
+            audio.sort();
+            for name in audio {
+                zip.raw_copy_file(old.by_name(name)?)?;
+            }
+        }
+        zip.finish()?;
+        fs::rename(&partial, path)?;
+
+        // Audio is played from the saved file from now on.
+        self.audio.extend(
+            written
+                .into_iter()
+                .filter(|name| Path::new(name).starts_with(AUDIO_DIR)),
+        );
+        self.archive = Some(path.to_path_buf());
+        let audio_dir = self.dir().join(AUDIO_DIR);
+        if audio_dir.exists() {
+            fs::remove_dir_all(audio_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Remove unpacked project files.
+    pub fn close(self) -> io::Result<()> {
+        self.dir.close()
     }
 
-    pub fn open(path: &Path) -> Project {
-        // create tmp directory from archive
-        // deserialize project.yaml
-        todo!()
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
     }
 
-    pub fn save(&self, path: &Path) {
-        // serialize project.yaml
-        // archive tmp directory
-        todo!()
+    /// Opened or saved project file.
+    pub fn archive(&self) -> Option<PathBuf> {
+        self.archive.clone()
     }
 
-    pub fn close(&mut self) {
-        // close tmp directory
-        todo!()
+    pub fn get_manifest(&self) -> Manifest {
+        self.manifest.clone()
     }
 
-    // other methods from application.rs
+    /// Serialized application, empty for a new project.
+    pub fn get_yaml(&self) -> io::Result<String> {
+        match fs::read_to_string(self.dir().join(PROJECT_FILE)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    pub fn set_yaml(&mut self, yaml: &str) -> io::Result<()> {
+        fs::write(self.dir().join(PROJECT_FILE), yaml)
+    }
+
+    /// Copy background image to the project keeping its name, the name is
+    /// the scene caption. Return its path relative to the project directory.
+    pub fn add_background(&mut self, path: &Path) -> io::Result<PathBuf> {
+        if let Ok(relative) = path.strip_prefix(self.dir()) {
+            return Ok(relative.to_path_buf());
+        }
+        // The same image is stored once.
+        let data = fs::read(path)?;
+        let folder = hex::encode(Sha256::digest(&data));
+        let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+        let relative = Path::new(BACKGROUNDS_DIR).join(&folder[..8]).join(name);
+        let target = self.dir().join(&relative);
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::write(target, data)?;
+        Ok(relative)
+    }
+
+    /// Copy audio file to the project keeping its name, so missing tracks
+    /// could be relinked to it.
+    pub fn embed_audio(&mut self, path: &Path) -> io::Result<PathBuf> {
+        if let Ok(relative) = path.strip_prefix(self.dir()) {
+            return Ok(relative.to_path_buf());
+        }
+        // Files of the same name from different folders are kept apart.
+        let folder = hex::encode(Sha256::digest(path.to_string_lossy().as_bytes()));
+        let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+        let relative = Path::new(AUDIO_DIR).join(&folder[..8]).join(name);
+        let target = self.dir().join(&relative);
+        if !target.exists() && !self.audio.contains(&entry_name(&relative)) {
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::copy(path, &target)?;
+        }
+        self.manifest.embedded_audio = true;
+        Ok(relative)
+    }
+
+    /// Remove backgrounds and audio which are not in `used`, paths are
+    /// relative to the project directory.
+    pub fn remove_unused(&mut self, used: &HashSet<PathBuf>) -> io::Result<()> {
+        for folder in [BACKGROUNDS_DIR, AUDIO_DIR] {
+            let folder = self.dir().join(folder);
+            if !folder.exists() {
+                continue;
+            }
+            // Files go before their folder, so emptied folders are removed too.
+            for entry in WalkDir::new(&folder).contents_first(true) {
+                let entry = entry.map_err(io::Error::other)?;
+                let relative = entry.path().strip_prefix(self.dir()).unwrap();
+                if entry.file_type().is_dir() {
+                    if fs::read_dir(entry.path())?.next().is_none() {
+                        fs::remove_dir(entry.path())?;
+                    }
+                } else if !used.contains(relative) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+        self.audio.retain(|name| used.contains(Path::new(name)));
+        self.manifest.embedded_audio = used.iter().any(|p| p.starts_with(AUDIO_DIR));
+        Ok(())
+    }
+
+    /// Embedded audio of the project file for the source filename, it is
+    /// relative to the project directory.
+    pub fn embedded_audio(&self, filename: &str) -> Option<PathBuf> {
+        let (archive, name) = zipstorage::split_filename(filename)?;
+        (self.archive.as_ref() == Some(&archive) && self.audio.contains(&name))
+            .then(|| PathBuf::from(name))
+    }
+
+    /// Relinker of missing tracks to embedded audio of the project file.
+    pub fn audio_relinker(&self) -> Option<Relinker> {
+        let archive = self.archive.as_ref()?;
+        Some(Relinker::scan_archive(archive, AUDIO_DIR))
+    }
 }
 
+/// Name of archive entry for the path relative to the project directory.
+fn entry_name(relative: &Path) -> String {
+    let names: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    names.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn save_and_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("map.png");
+        fs::write(&image, b"image").unwrap();
+        let music = dir.path().join("Music");
+        fs::create_dir(&music).unwrap();
+        fs::write(music.join("tavern.ogg"), b"audio").unwrap();
+
+        let mut project = Project::new().unwrap();
+        assert_eq!(project.get_yaml().unwrap(), "");
+        project.set_yaml("root_map: {}\n").unwrap();
+        let background = project.add_background(&image).unwrap();
+        assert!(background.starts_with(BACKGROUNDS_DIR));
+        assert_eq!(background.file_name().unwrap(), "map.png");
+        let copy = project.dir().join(&background);
+        assert_eq!(project.add_background(&copy).unwrap(), background);
+        let audio = project.embed_audio(&music.join("tavern.ogg")).unwrap();
+        assert_eq!(audio.file_name().unwrap(), "tavern.ogg");
+
+        let path = dir.path().join(format!("Campaign.{PROJECT_EXTENSION}"));
+        project.save(&path).unwrap();
+        let temp = project.dir().to_path_buf();
+        project.close().unwrap();
+        assert!(!temp.exists());
+
+        // Audio is played from the project file without unpacking.
+        let mut project = Project::open(&path).unwrap();
+        assert_eq!(project.get_yaml().unwrap(), "root_map: {}\n");
+        assert_eq!(fs::read(project.dir().join(&background)).unwrap(), b"image");
+        assert!(!project.dir().join(AUDIO_DIR).exists());
+        let name = entry_name(&audio);
+        let filename = zipstorage::entry_filename(&path, &name);
+        assert_eq!(
+            project.embedded_audio(&filename),
+            Some(PathBuf::from(&name))
+        );
+        let mut zip = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let entry = zip.by_name(&name).unwrap();
+        assert_eq!(entry.compression(), CompressionMethod::Stored);
+        drop(entry);
+
+        // Embedded audio is copied to other file.
+        let copy = dir.path().join(format!("Copy.{PROJECT_EXTENSION}"));
+        project.save(&copy).unwrap();
+        assert_eq!(project.archive(), Some(copy.clone()));
+        let mut zip = ZipArchive::new(File::open(&copy).unwrap()).unwrap();
+        let mut data = vec![];
+        zip.by_name(&name).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"audio");
+        assert_eq!(
+            project.get_manifest(),
+            Manifest {
+                format_version: FORMAT_VERSION,
+                embedded_audio: true
+            }
+        );
+
+        // Newer format and foreign archives are not opened.
+        let newer = dir.path().join("newer.zip");
+        let mut zip = ZipWriter::new(File::create(&newer).unwrap());
+        zip.start_file(MANIFEST_FILE, SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(format!("format_version: {}\n", FORMAT_VERSION + 1).as_bytes())
+            .unwrap();
+        zip.finish().unwrap();
+        assert!(matches!(
+            Project::open(&newer),
+            Err(ProjectError::NewerVersion(_))
+        ));
+        let foreign = dir.path().join("foreign.zip");
+        ZipWriter::new(File::create(&foreign).unwrap())
+            .finish()
+            .unwrap();
+        assert!(matches!(
+            Project::open(&foreign),
+            Err(ProjectError::NotAProject)
+        ));
+    }
+
+    #[test]
+    fn remove_unused() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old.png");
+        fs::write(&old, b"old").unwrap();
+        let image = dir.path().join("map.png");
+        fs::write(&image, b"image").unwrap();
+        let music = dir.path().join("tavern.ogg");
+        fs::write(&music, b"audio").unwrap();
+
+        let mut project = Project::new().unwrap();
+        let old = project.add_background(&old).unwrap();
+        let background = project.add_background(&image).unwrap();
+        let audio = project.embed_audio(&music).unwrap();
+        project
+            .remove_unused(&HashSet::from([background.clone()]))
+            .unwrap();
+        assert!(project.dir().join(&background).is_file());
+        assert!(!project.dir().join(old.parent().unwrap()).exists());
+        assert!(!project.dir().join(&audio).exists());
+        assert!(!project.dir().join(AUDIO_DIR).exists());
+        assert!(!project.get_manifest().embedded_audio);
+
+        let path = dir.path().join(format!("Campaign.{PROJECT_EXTENSION}"));
+        project.save(&path).unwrap();
+        let mut zip = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let names: Vec<_> = zip.file_names().map(String::from).collect();
+        assert!(names.iter().all(|n| !n.starts_with(AUDIO_DIR)));
+        let name = background.to_string_lossy().replace('\\', "/");
+        assert_eq!(zip.by_name(&name).unwrap().size(), 5);
+    }
+}
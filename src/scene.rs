@@ -21,10 +21,11 @@ use std::{
 use egui::TextureHandle;
 use serde::{Deserialize, Serialize};
 
-use crate::audio::{Audio, playlist::Playlist};
+use crate::audio::{self, Audio};
 
 #[derive(Serialize, Deserialize)]
 pub struct Scene {
+    #[serde(with = "audio::shared::list")]
     audio: Vec<Audio>,
     maps: BTreeMap<Point, Rc<RefCell<Scene>>>,
 
@@ -79,14 +80,16 @@ impl Scene {
         }
     }
 
-    pub fn set_background(&mut self, path: PathBuf, handle: TextureHandle) {
-        self.background_path = Some(path);
+    /// Loaded image of the background path, the path is changed by
+    /// `Command::SetBackground` only.
+    pub fn set_background(&mut self, handle: TextureHandle) {
         self.background = Some(handle);
     }
 
-    pub fn remove_background(&mut self) {
+    /// Change background path, the image is loaded again on render.
+    pub fn reset_background(&mut self, path: Option<PathBuf>) {
         self.background = None;
-        self.background_path = None;
+        self.background_path = path;
     }
 
     pub fn get_background(&self) -> Option<TextureHandle> {
@@ -97,9 +100,44 @@ impl Scene {
         self.background_path.clone()
     }
 
-    pub fn push_new_audio(&mut self) {
-        let audio: Audio = Rc::new(RefCell::new(Box::new(Playlist::new())));
-        self.audio.push(audio);
+    /// Move background to other path, the loaded image is kept.
+    pub fn set_background_path(&mut self, path: PathBuf) {
+        self.background_path = Some(path);
+    }
+
+    /// Scene name for the play history: background image name.
+    pub fn caption(&self) -> String {
+        self.background_path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Scene or child scene which contains audio.
+    pub fn find_scene(scene: &Rc<RefCell<Scene>>, audio: &Audio) -> Option<Rc<RefCell<Scene>>> {
+        if scene.borrow().audio.iter().any(|a| Rc::ptr_eq(a, audio)) {
+            return Some(Rc::clone(scene));
+        }
+        scene
+            .borrow()
+            .maps
+            .values()
+            .find_map(|map| Scene::find_scene(map, audio))
+    }
+
+    /// Scene is the scene or one of its child scenes.
+    pub fn contains_scene(scene: &Rc<RefCell<Scene>>, child: &Rc<RefCell<Scene>>) -> bool {
+        Rc::ptr_eq(scene, child)
+            || scene
+                .borrow()
+                .maps
+                .values()
+                .any(|map| Scene::contains_scene(map, child))
+    }
+
+    pub fn insert_audio(&mut self, index: usize, audio: Audio) {
+        self.audio.insert(index.min(self.audio.len()), audio);
     }
 
     pub fn erase_audio(&mut self, index: usize) {
@@ -113,6 +151,24 @@ impl Scene {
     pub fn audio_count(&self) -> usize {
         self.audio.len()
     }
+
+    /// Call `f` for the scene and its child scenes.
+    pub fn walk_scenes(&mut self, f: &mut dyn FnMut(&mut Scene)) {
+        f(self);
+        for map in self.maps.values() {
+            map.borrow_mut().walk_scenes(f);
+        }
+    }
+
+    /// Call `f` for every audio of the scene and its child scenes.
+    pub fn walk_audio(&self, f: &mut dyn FnMut(&Audio)) {
+        for audio in &self.audio {
+            audio::walk(audio, f);
+        }
+        for map in self.maps.values() {
+            map.borrow().walk_audio(f);
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -0,0 +1,166 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Versioned project YAML. Projects are saved with the schema `version`,
+//! older projects are upgraded by a chain of migrations over the YAML tree
+//! before deserialization. Files without version are version 0.
+
+use std::fmt;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_yaml::{Mapping, Value};
+
+use crate::audio::shared;
+
+const VERSION_KEY: &str = "version";
+
+/// Migration of project mapping to the next version.
+type Migration = fn(&mut Mapping) -> Result<(), String>;
+
+/// Migration `i` upgrades version `i` to `i + 1`.
+const MIGRATIONS: [Migration; 2] = [single_storage_to_list, shared_audio_ids];
+
+/// Version of saved projects.
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Yaml(serde_yaml::Error),
+    /// Project is saved by a newer version of the player.
+    NewerVersion(u64),
+    /// Migration from the version failed.
+    Migration(u64, String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Yaml(e) => write!(f, "{}", e),
+            SchemaError::NewerVersion(version) => write!(
+                f,
+                "project is saved by a newer Cyberbard (project version {}, supported {}), \
+                 please update the player",
+                version, SCHEMA_VERSION
+            ),
+            SchemaError::Migration(version, message) => {
+                write!(
+                    f,
+                    "can't upgrade project of version {}: {}",
+                    version, message
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl From<serde_yaml::Error> for SchemaError {
+    fn from(e: serde_yaml::Error) -> Self {
+        SchemaError::Yaml(e)
+    }
+}
+
+/// Project YAML with the current schema version.
+pub fn to_yaml<T: Serialize>(project: &T) -> Result<String, SchemaError> {
+    let value = shared::write_shared(|| serde_yaml::to_value(project))?;
+    let Value::Mapping(fields) = value else {
+        return Ok(serde_yaml::to_string(&value)?);
+    };
+    // Version goes first to be seen when the file is opened in an editor.
+    let mut versioned = Mapping::new();
+    versioned.insert(VERSION_KEY.into(), SCHEMA_VERSION.into());
+    versioned.extend(fields);
+    Ok(serde_yaml::to_string(&versioned)?)
+}
+
+/// Project of any known version from YAML.
+pub fn from_yaml<T: DeserializeOwned>(text: &str) -> Result<T, SchemaError> {
+    let mut value: Value = serde_yaml::from_str(text)?;
+    if let Value::Mapping(project) = &mut value {
+        migrate(project)?;
+    }
+    Ok(shared::read_shared(|| serde_yaml::from_value(value))?)
+}
+
+/// Upgrade project mapping to the current version.
+fn migrate(project: &mut Mapping) -> Result<(), SchemaError> {
+    let version = match project.remove(VERSION_KEY) {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| SchemaError::Migration(0, format!("wrong version {:?}", version)))?,
+        None => 0,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(SchemaError::NewerVersion(version));
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(project).map_err(|e| SchemaError::Migration(from as u64, e))?;
+    }
+    Ok(())
+}
+
+/// Version 0 projects had one storage.
+fn single_storage_to_list(project: &mut Mapping) -> Result<(), String> {
+    let Some(storage) = project.remove("storage") else {
+        return Ok(());
+    };
+    let storages = project
+        .entry("storages".into())
+        .or_insert_with(|| Value::Sequence(vec![]));
+    match storages {
+        Value::Sequence(storages) => {
+            storages.insert(0, storage);
+            Ok(())
+        }
+        _ => Err("storages is not a list".to_string()),
+    }
+}
+
+/// Version 1 projects saved shared audio as copies, they are read as is.
+/// Newer projects refer to shared audio by id, see `audio::shared`.
+fn shared_audio_ids(_project: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::Application;
+
+    #[test]
+    fn old_and_newer_projects() {
+        let app: Application = from_yaml(include_str!("../fixtures/project_v0.yaml")).unwrap();
+        let storages = app.get_storages();
+        assert_eq!(storages.len(), 1);
+        assert_eq!(storages[0].borrow().get_caption(), "Tavern music");
+        assert_eq!(storages[0].borrow().get(0).unwrap().get_title(), "tavern");
+        let playlist = app.get_root_map().borrow().get_audio(0);
+        assert_eq!(playlist.borrow().get_title(), "Tavern");
+
+        let yaml = to_yaml(&app).unwrap();
+        assert!(yaml.starts_with(&format!("version: {SCHEMA_VERSION}\n")));
+        let app: Application = from_yaml(&yaml).unwrap();
+        assert_eq!(app.get_storages().len(), 1);
+
+        let version = format!("version: {SCHEMA_VERSION}");
+        let newer = yaml.replacen(&version, &format!("version: {}", SCHEMA_VERSION + 1), 1);
+        match from_yaml::<Application>(&newer) {
+            Err(SchemaError::NewerVersion(version)) => assert_eq!(version, SCHEMA_VERSION + 1),
+            _ => panic!("newer project is opened"),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Audio features analysis: tempo, loudness, brightness and rhythm.
+//! Features give suggested tags like `calm` or `dark`.
+
+use std::{
+    f32::consts::PI,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stream::Opener;
+
+/// Samples per analysis frame.
+const HOP: usize = 512;
+/// Only the track start is analyzed.
+const MAX_SECONDS: usize = 120;
+const SILENCE_DB: f32 = -60.0;
+/// Beat regularity of rhythmic tracks.
+const RHYTHMIC: f32 = 0.4;
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 180.0;
+
+/// Suggested tags and their categories.
+pub const SUGGESTED_TAGS: [(&str, &str); 9] = [
+    ("calm", "intensity"),
+    ("intense", "intensity"),
+    ("dark", "mood"),
+    ("bright", "mood"),
+    ("ambient", "tempo"),
+    ("rhythmic", "tempo"),
+    ("slow", "tempo"),
+    ("fast", "tempo"),
+    ("loop", "tempo"),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Features {
+    /// Beats per minute, 0 if there is no steady beat.
+    pub bpm: f32,
+    /// Average level in dBFS.
+    pub loudness: f32,
+    /// Rough spectral centroid in Hz.
+    pub brightness: f32,
+    /// Beat regularity from 0 (ambient) to 1 (strict rhythm).
+    pub rhythm: f32,
+    /// Steady level without silent ends, could be played in a loop.
+    pub loop_like: bool,
+}
+
+impl Features {
+    /// Tags describing the features, see `SUGGESTED_TAGS`.
+    pub fn suggested_tags(&self) -> Vec<String> {
+        let rhythmic = self.rhythm >= RHYTHMIC;
+        let mut tags = vec![];
+        if self.loudness < -26.0 || (!rhythmic && self.loudness < -20.0) {
+            tags.push("calm");
+        } else if self.loudness > -10.0 || (rhythmic && self.loudness > -16.0 && self.bpm >= 110.0)
+        {
+            tags.push("intense");
+        }
+        if self.brightness < 800.0 {
+            tags.push("dark");
+        } else if self.brightness > 3000.0 {
+            tags.push("bright");
+        }
+        if rhythmic {
+            tags.push("rhythmic");
+            if self.bpm < 90.0 {
+                tags.push("slow");
+            } else if self.bpm > 140.0 {
+                tags.push("fast");
+            }
+        } else {
+            tags.push("ambient");
+        }
+        if self.loop_like {
+            tags.push("loop");
+        }
+        tags.into_iter().map(|t| t.to_string()).collect()
+    }
+}
+
+/// Category of suggested tag.
+pub fn suggested_category(tag: &str) -> Option<&'static str> {
+    SUGGESTED_TAGS
+        .iter()
+        .find(|(text, _)| *text == tag)
+        .map(|(_, category)| *category)
+}
+
+fn to_db(level: f32) -> f32 {
+    if level > 0.0 {
+        (20.0 * level.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+/// Features of mono samples.
+pub fn analyze(samples: &[f32], sample_rate: u32) -> Features {
+    if samples.len() < 2 * HOP || sample_rate == 0 {
+        return Features {
+            loudness: SILENCE_DB,
+            ..Default::default()
+        };
+    }
+    let rate = sample_rate as f32;
+
+    let energy = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    let loudness = to_db(energy.sqrt());
+
+    // Sine of frequency f gives difference to signal level ratio 2·sin(πf/rate).
+    let diff_energy = samples
+        .windows(2)
+        .map(|w| (w[1] - w[0]).powi(2))
+        .sum::<f32>()
+        / (samples.len() - 1) as f32;
+    let brightness = if energy > 0.0 {
+        let ratio = ((diff_energy / energy).sqrt() / 2.0).min(1.0);
+        rate / PI * ratio.asin()
+    } else {
+        0.0
+    };
+
+    let levels: Vec<f32> = samples
+        .chunks_exact(HOP)
+        .map(|frame| to_db((frame.iter().map(|s| s * s).sum::<f32>() / HOP as f32).sqrt()))
+        .collect();
+    let (bpm, rhythm) = tempo(&levels, rate / HOP as f32);
+
+    Features {
+        bpm,
+        loudness,
+        brightness,
+        rhythm,
+        loop_like: is_loop_like(&levels, rate / HOP as f32),
+    }
+}
+
+/// Tempo and beat regularity by autocorrelation of level rises.
+fn tempo(levels: &[f32], frame_rate: f32) -> (f32, f32) {
+    let rises: Vec<f32> = levels.windows(2).map(|w| (w[1] - w[0]).max(0.0)).collect();
+    // Smoothing keeps beats between frames correlated.
+    let mut onsets: Vec<f32> = (0..rises.len())
+        .map(|i| {
+            let prev = if i > 0 { rises[i - 1] } else { 0.0 };
+            let next = rises.get(i + 1).copied().unwrap_or(0.0);
+            0.25 * prev + 0.5 * rises[i] + 0.25 * next
+        })
+        .collect();
+    let mean = onsets.iter().sum::<f32>() / onsets.len().max(1) as f32;
+    onsets.iter_mut().for_each(|o| *o -= mean);
+
+    let correlation = |lag: f32| {
+        let whole = lag as usize;
+        let part = lag - whole as f32;
+        let count = onsets.len().saturating_sub(whole + 1);
+        if count == 0 {
+            return 0.0;
+        }
+        let sum: f32 = (0..count)
+            .map(|i| onsets[i] * (onsets[i + whole] * (1.0 - part) + onsets[i + whole + 1] * part))
+            .sum();
+        sum / count as f32
+    };
+    let variance = correlation(0.0);
+    if variance < 0.01 {
+        return (0.0, 0.0);
+    }
+
+    // Tempos near 120 BPM are preferred to avoid half and double tempo.
+    let mut best = (0.0, 0.0, f32::MIN);
+    let mut bpm = MIN_BPM;
+    while bpm <= MAX_BPM {
+        let value = correlation(60.0 * frame_rate / bpm);
+        let weight = (-0.5 * (bpm / 120.0).log2().powi(2)).exp();
+        if value * weight > best.2 {
+            best = (bpm, value, value * weight);
+        }
+        bpm += 0.5;
+    }
+    let rhythm = (best.1 / variance).clamp(0.0, 1.0);
+    if rhythm < RHYTHMIC {
+        (0.0, rhythm)
+    } else {
+        (best.0, rhythm)
+    }
+}
+
+/// Level is steady and both ends are not silent.
+fn is_loop_like(levels: &[f32], frame_rate: f32) -> bool {
+    let edge = ((frame_rate / 2.0) as usize).clamp(1, levels.len() / 4 + 1);
+    let average = |l: &[f32]| l.iter().sum::<f32>() / l.len().max(1) as f32;
+    let start = average(&levels[..edge]);
+    let end = average(&levels[levels.len() - edge..]);
+    let mean = average(levels);
+    let deviation = average(
+        &levels
+            .iter()
+            .map(|l| (l - mean).powi(2))
+            .collect::<Vec<f32>>(),
+    )
+    .sqrt();
+    start > SILENCE_DB + 20.0
+        && end > SILENCE_DB + 20.0
+        && (start - end).abs() < 3.0
+        && deviation < 6.0
+}
+
+/// Decode track start and analyze it.
+pub fn analyze_opener(opener: &mut dyn Opener) -> Result<Features, String> {
+    let source = opener.source().map_err(|e| e.to_string())?;
+    let channels = source.channels().max(1) as usize;
+    let sample_rate = source.sample_rate();
+    let limit = MAX_SECONDS * sample_rate as usize * channels;
+
+    let mut samples = Vec::with_capacity(limit / channels);
+    let mut sum = 0.0;
+    for (i, sample) in source.take(limit).enumerate() {
+        sum += sample;
+        if i % channels == channels - 1 {
+            samples.push(sum / channels as f32);
+            sum = 0.0;
+        }
+    }
+    Ok(analyze(&samples, sample_rate))
+}
+
+/// Key of source and its analysis result.
+type Analyzed<T> = (String, Result<T, String>);
+
+enum AnalysisMessage<T> {
+    Progress(usize, usize),
+    Analyzed(Analyzed<T>),
+}
+
+/// Background analysis of storage sources, e.g. features or waveforms.
+pub struct Analyzer<T> {
+    rx: Receiver<AnalysisMessage<T>>,
+    progress: (usize, usize),
+}
+
+impl<T: Send + 'static> Analyzer<T> {
+    /// Analyze sources given by key and opener.
+    pub fn new(
+        jobs: Vec<(String, Box<dyn Opener + Send>)>,
+        analyze: fn(&mut dyn Opener) -> Result<T, String>,
+    ) -> Analyzer<T> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let total = jobs.len();
+            for (i, (key, mut opener)) in jobs.into_iter().enumerate() {
+                let _ = tx.send(AnalysisMessage::Progress(i, total));
+                let result = analyze(opener.as_mut());
+                if tx.send(AnalysisMessage::Analyzed((key, result))).is_err() {
+                    return;
+                }
+            }
+        });
+        Analyzer {
+            rx,
+            progress: (0, 0),
+        }
+    }
+
+    /// Count of analyzed and total sources.
+    pub fn progress(&self) -> (usize, usize) {
+        self.progress
+    }
+
+    /// Analyzed sources since the last poll, and true if analysis is finished.
+    pub fn poll(&mut self) -> (Vec<Analyzed<T>>, bool) {
+        let mut analyzed = vec![];
+        loop {
+            match self.rx.try_recv() {
+                Ok(AnalysisMessage::Progress(done, total)) => self.progress = (done, total),
+                Ok(AnalysisMessage::Analyzed(result)) => analyzed.push(result),
+                Err(TryRecvError::Empty) => return (analyzed, false),
+                Err(TryRecvError::Disconnected) => return (analyzed, true),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 22050;
+
+    /// Pseudo random samples in -1..1.
+    fn noise(count: usize) -> Vec<f32> {
+        let mut state: u32 = 12345;
+        (0..count)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 8) as f32 / (1 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn features_and_tags() {
+        let seconds = 20 * RATE as usize;
+
+        let hum: Vec<f32> = (0..seconds)
+            .map(|i| 0.03 * (2.0 * PI * 110.0 * i as f32 / RATE as f32).sin())
+            .collect();
+        let features = analyze(&hum, RATE);
+        assert!((features.brightness - 110.0).abs() < 5.0);
+        assert!(features.loop_like);
+        assert_eq!(
+            features.suggested_tags(),
+            vec!["calm", "dark", "ambient", "loop"]
+        );
+
+        let wind: Vec<f32> = noise(seconds).iter().map(|s| 0.8 * s).collect();
+        let features = analyze(&wind, RATE);
+        assert_eq!(
+            features.suggested_tags(),
+            vec!["intense", "bright", "ambient", "loop"]
+        );
+
+        // Noise bursts at 120 BPM.
+        let burst = noise(400);
+        let mut drums = vec![0.0; seconds];
+        for beat in (0..seconds - 400).step_by(RATE as usize / 2) {
+            drums[beat..beat + 400].copy_from_slice(&burst);
+        }
+        let features = analyze(&drums, RATE);
+        assert!((features.bpm - 120.0).abs() < 3.0, "{features:?}");
+        assert!(features.suggested_tags().contains(&"rhythmic".to_string()));
+        assert!(!features.loop_like);
+    }
+}
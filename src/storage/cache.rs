@@ -0,0 +1,93 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::metadata::Metadata;
+
+const CACHE_FILE: &str = "metadata.yaml";
+
+/// Cached information about one audio file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub mtime: u64,
+    pub size: u64,
+    pub id: String,
+    pub title: Option<String>,
+    pub metadata: Metadata,
+}
+
+/// Per-file metadata cache stored in the storage cache directory, so storage
+/// reopening reads only changed files. Keys are paths relative to the storage
+/// root.
+#[derive(Default, Serialize, Deserialize)]
+pub struct MetadataCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl MetadataCache {
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(CACHE_FILE)
+    }
+
+    /// Load cache of the storage root. Broken or missing cache is empty.
+    pub fn load(root: &Path) -> MetadataCache {
+        fs::read_to_string(Self::path(root))
+            .ok()
+            .and_then(|s| serde_yaml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let s = serde_yaml::to_string(self).map_err(io::Error::other)?;
+        fs::create_dir_all(root)?;
+        fs::write(Self::path(root), s)
+    }
+
+    /// Cached entry if file is not changed since caching.
+    pub fn get(&self, key: &str, mtime: u64, size: u64) -> Option<&CacheEntry> {
+        self.entries
+            .get(key)
+            .filter(|e| e.mtime == mtime && e.size == size)
+    }
+
+    pub fn insert(&mut self, key: String, entry: CacheEntry) {
+        self.entries.insert(key, entry);
+    }
+
+    /// Forget files not in keys.
+    pub fn retain(&mut self, keys: &[String]) {
+        let keys: std::collections::HashSet<&String> = keys.iter().collect();
+        self.entries.retain(|k, _| keys.contains(k));
+    }
+}
+
+/// Modification time of file in seconds.
+pub fn mtime(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
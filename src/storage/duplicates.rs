@@ -0,0 +1,191 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Search of the same tracks in a storage: copies of a file and the same
+//! track in other format or from other pack.
+
+use std::collections::HashMap;
+
+use crate::storage::{index::osa_distance, source::Source};
+
+/// Max duration difference of similar tracks in seconds.
+const DURATION_TOLERANCE: f32 = 1.5;
+/// Min title similarity of similar tracks, 1 is the same title.
+const TITLE_SIMILARITY: f32 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateKind {
+    /// Same content fingerprint.
+    Exact,
+    /// Close duration and similar title.
+    Similar,
+}
+
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    /// Source indexes, at least two.
+    pub sources: Vec<usize>,
+}
+
+/// Groups of duplicated visible sources. Exact copies are grouped first,
+/// then groups and the rest of sources are joined by similarity.
+pub fn find_duplicates(sources: &[Source]) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = vec![];
+    let mut by_id: HashMap<String, usize> = HashMap::new();
+    for (i, source) in sources.iter().enumerate() {
+        if source.is_hidden() {
+            continue;
+        }
+        let id = source.get_id();
+        if id.is_empty() {
+            groups.push(single(i));
+            continue;
+        }
+        match by_id.get(&id) {
+            Some(&group) => {
+                groups[group].kind = DuplicateKind::Exact;
+                groups[group].sources.push(i);
+            }
+            None => {
+                by_id.insert(id, groups.len());
+                groups.push(single(i));
+            }
+        }
+    }
+
+    // Compare groups by the first source, only neighbours by duration.
+    let titles: Vec<String> = groups
+        .iter()
+        .map(|g| normalize_title(&sources[g.sources[0]].get_title()))
+        .collect();
+    let duration = |g: usize| sources[groups[g].sources[0]].metadata().duration;
+    let mut order: Vec<usize> = (0..groups.len()).filter(|&g| duration(g) > 0.0).collect();
+    order.sort_by(|&a, &b| duration(a).total_cmp(&duration(b)));
+
+    let mut parent: Vec<usize> = (0..groups.len()).collect();
+    for (n, &a) in order.iter().enumerate() {
+        for &b in &order[n + 1..] {
+            if duration(b) - duration(a) > DURATION_TOLERANCE {
+                break;
+            }
+            if title_similarity(&titles[a], &titles[b]) >= TITLE_SIMILARITY {
+                let (root_a, root_b) = (root(&mut parent, a), root(&mut parent, b));
+                parent[root_b] = root_a;
+            }
+        }
+    }
+
+    let mut joined: Vec<Option<DuplicateGroup>> = vec![None; groups.len()];
+    for (g, group) in groups.into_iter().enumerate() {
+        let r = root(&mut parent, g);
+        match &mut joined[r] {
+            Some(target) => {
+                target.kind = DuplicateKind::Similar;
+                target.sources.extend(group.sources);
+            }
+            None => joined[r] = Some(group),
+        }
+    }
+    joined
+        .into_iter()
+        .flatten()
+        .filter(|g| g.sources.len() > 1)
+        .map(|mut g| {
+            g.sources.sort();
+            g
+        })
+        .collect()
+}
+
+fn single(source: usize) -> DuplicateGroup {
+    DuplicateGroup {
+        kind: DuplicateKind::Similar,
+        sources: vec![source],
+    }
+}
+
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Lowercase words of title without punctuation and numbering like `01 - `.
+fn normalize_title(title: &str) -> String {
+    let words: Vec<String> = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_string())
+        .collect();
+    let skip = words
+        .iter()
+        .take_while(|w| w.chars().all(|c| c.is_ascii_digit()))
+        .count()
+        .min(words.len().saturating_sub(1));
+    words[skip..].join(" ")
+}
+
+/// Similarity of normalized titles from 0 to 1.
+fn title_similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return 0.0;
+    }
+    1.0 - osa_distance(&a, &b) as f32 / len as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::metadata::Metadata;
+
+    fn source(title: &str, id: &str, duration: f32) -> Source {
+        let mut source = Source::new(format!("{title}.ogg"), title.to_string());
+        source.set_id(id.to_string(), 10);
+        source.set_metadata(Metadata {
+            duration,
+            ..Default::default()
+        });
+        source
+    }
+
+    #[test]
+    fn exact_and_similar() {
+        let mut sources = vec![
+            source("Tavern", "a", 60.0),
+            source("Tavern", "a", 60.0),
+            source("Forest Night", "b", 120.0),
+            source("03 - forest night", "c", 121.0),
+            source("Forest Day", "d", 120.5),
+            source("Battle", "e", 300.0),
+            source("Tavern", "a", 60.0),
+        ];
+        sources[6].set_hidden(true);
+
+        let groups = find_duplicates(&sources);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].kind, DuplicateKind::Exact);
+        assert_eq!(groups[0].sources, vec![0, 1]);
+        assert_eq!(groups[1].kind, DuplicateKind::Similar);
+        assert_eq!(groups[1].sources, vec![2, 3]);
+    }
+}
@@ -0,0 +1,83 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+/// Bytes read from the begin and the end of a file.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Stable source identifier.
+/// Hash of file size, first and last 64 KiB of the file, so it does not
+/// depend on file name or location and is cheap for large files.
+pub fn fingerprint(path: &Path) -> io::Result<String> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    fingerprint_reader(file, size)
+}
+
+/// Fingerprint of data with known size, e.g. remote file.
+pub fn fingerprint_reader<R: Read + Seek>(mut file: R, size: u64) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(size.to_le_bytes());
+
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE as usize);
+    (&mut file).take(CHUNK_SIZE).read_to_end(&mut buffer)?;
+    hasher.update(&buffer);
+
+    if size > 2 * CHUNK_SIZE {
+        buffer.clear();
+        file.seek(SeekFrom::End(-(CHUNK_SIZE as i64)))?;
+        file.take(CHUNK_SIZE).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    } else if size > CHUNK_SIZE {
+        buffer.clear();
+        file.read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+
+    Ok(hex::encode(&hasher.finalize()[..16]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_content_same_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.ogg");
+        let b = dir.path().join("other name.ogg");
+        let c = dir.path().join("c.ogg");
+
+        let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&a, &data).unwrap();
+        std::fs::write(&b, &data).unwrap();
+
+        let mut changed = data.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        std::fs::write(&c, &changed).unwrap();
+
+        assert_eq!(fingerprint(&a).unwrap(), fingerprint(&b).unwrap());
+        assert_ne!(fingerprint(&a).unwrap(), fingerprint(&c).unwrap());
+        assert_eq!(fingerprint(&a).unwrap().len(), 32);
+    }
+}
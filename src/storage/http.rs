@@ -0,0 +1,663 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::SystemTime,
+};
+
+use url::Url;
+
+use crate::storage::localstorage::decode;
+
+/// Bytes fetched by one range request.
+const BLOCK_SIZE: u64 = 256 * 1024;
+/// Blocks requested by a reader and not read yet.
+const PREFETCH_BLOCKS: usize = 2;
+/// Bytes written to the part file of a download at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+lazy_static::lazy_static! {
+    /// Cache files being downloaded, a file is downloaded once at a time.
+    static ref DOWNLOADS: Mutex<HashMap<PathBuf, Arc<Download>>> = Mutex::new(HashMap::new());
+}
+
+/// Played file in the disk cache. Least recently played files of the
+/// cache directory are removed when it is larger than `limit` bytes.
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub limit: u64,
+}
+
+/// Remote file with request parameters.
+#[derive(Clone)]
+pub struct HttpFile {
+    agent: ureq::Agent,
+    url: Url,
+    authorization: Option<String>,
+}
+
+impl HttpFile {
+    pub fn new(agent: ureq::Agent, url: Url, authorization: Option<String>) -> HttpFile {
+        HttpFile {
+            agent,
+            url,
+            authorization,
+        }
+    }
+
+    fn request(&self, method: &str) -> ureq::Request {
+        let request = self.agent.request_url(method, &self.url);
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    pub fn reader(&self, size: u64) -> HttpReader {
+        HttpReader {
+            file: self.clone(),
+            size,
+            pos: 0,
+            block: vec![],
+            block_start: 0,
+            prefetch: None,
+        }
+    }
+
+    /// File size from HEAD request.
+    pub fn size(&self) -> io::Result<u64> {
+        let response = self
+            .request("HEAD")
+            .call()
+            .map_err(|e| io::Error::other(error_text(e)))?;
+        response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| io::Error::other("unknown file size"))
+    }
+
+    /// Read `len` bytes from `start`.
+    fn get_range(&self, start: u64, len: u64) -> io::Result<Vec<u8>> {
+        let response = self
+            .request("GET")
+            .set("Range", &format!("bytes={}-{}", start, start + len - 1))
+            .call()
+            .map_err(|e| io::Error::other(error_text(e)))?;
+        let partial = response.status() == 206;
+        let mut reader = response.into_reader();
+        if !partial {
+            // Server ignores ranges and sends the whole file.
+            io::copy(&mut reader.by_ref().take(start), &mut io::sink())?;
+        }
+        let mut data = Vec::with_capacity(len as usize);
+        reader.take(len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Fetch whole file through temporary file, so broken downloads are not
+    /// used. Players of the file read it from the part file.
+    fn download(&self, path: &Path, download: &Download) -> io::Result<()> {
+        let response = self
+            .request("GET")
+            .call()
+            .map_err(|e| io::Error::other(error_text(e)))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut part = tempfile::Builder::new().suffix(".part").tempfile_in(dir)?;
+        download.update(|state| state.part = Some(part.path().to_path_buf()));
+        let mut reader = response.into_reader();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let count = reader.read(&mut chunk)?;
+            if count == 0 {
+                break;
+            }
+            part.write_all(&chunk[..count])?;
+            download.update(|state| state.written += count as u64);
+        }
+        part.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Download in background unless the file is being downloaded already.
+    /// The cache is cleaned up after downloading.
+    fn download_in_background(&self, cache: CachedFile) -> Arc<Download> {
+        let mut downloads = DOWNLOADS.lock().unwrap();
+        if let Some(download) = downloads.get(&cache.path) {
+            return Arc::clone(download);
+        }
+        let download = Arc::new(Download::default());
+        downloads.insert(cache.path.clone(), Arc::clone(&download));
+        let file = self.clone();
+        let shared = Arc::clone(&download);
+        thread::spawn(move || {
+            let result = file.download(&cache.path, &shared);
+            if let Err(e) = &result {
+                eprintln!("Error caching {}: {}", cache.path.display(), e);
+            }
+            shared.update(|state| state.finished = Some(result.is_ok()));
+            DOWNLOADS.lock().unwrap().remove(&cache.path);
+            if let Some(dir) = cache.path.parent()
+                && let Err(e) = evict(dir, cache.limit)
+            {
+                eprintln!("Error cleaning up cache {}: {}", dir.display(), e);
+            }
+        });
+        download
+    }
+
+    /// Decoder of the file, `filename` gives format hint. Size could be 0 if
+    /// unknown. If `cache` is given, downloaded file is played from it, else
+    /// the file is downloaded to it and played while downloading.
+    pub fn open(
+        &self,
+        size: u64,
+        filename: &str,
+        cache: Option<CachedFile>,
+    ) -> Result<Box<dyn rodio::Source + Send>, Box<dyn std::error::Error>> {
+        let Some(cache) = cache else {
+            let size = if size == 0 { self.size()? } else { size };
+            let mut reader = self.reader(size);
+            reader.prefetch = Some(Prefetcher::new(self.clone()));
+            return Ok(Box::new(decode(reader, size, filename)?));
+        };
+        if cache.path.is_file() {
+            let file = File::open(&cache.path)?;
+            // Modification time is the last play time for the cache cleanup.
+            let _ = file.set_modified(SystemTime::now());
+            let len = file.metadata()?.len();
+            return Ok(Box::new(decode(BufReader::new(file), len, filename)?));
+        }
+        let size = if size == 0 { self.size()? } else { size };
+        let reader = DownloadReader {
+            path: cache.path.clone(),
+            download: self.download_in_background(cache),
+            file: None,
+            remote: self.reader(size),
+            pos: 0,
+        };
+        Ok(Box::new(decode(reader, size, filename)?))
+    }
+}
+
+/// Worker requesting blocks of a reader in background one by one.
+struct Prefetcher {
+    requests: Sender<(u64, u64)>,
+    /// Mutex makes the reader `Sync` for the decoder.
+    blocks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    /// Starts of requested blocks not received yet.
+    requested: VecDeque<u64>,
+}
+
+impl Prefetcher {
+    fn new(file: HttpFile) -> Prefetcher {
+        let (requests, rx) = mpsc::channel::<(u64, u64)>();
+        let (tx, blocks) = mpsc::channel();
+        // The worker stops with its reader.
+        thread::spawn(move || {
+            for (start, len) in rx {
+                if tx.send(file.get_range(start, len)).is_err() {
+                    return;
+                }
+            }
+        });
+        Prefetcher {
+            requests,
+            blocks: Mutex::new(blocks),
+            requested: VecDeque::new(),
+        }
+    }
+
+    fn request(&mut self, start: u64, len: u64) {
+        if self.requested.len() < PREFETCH_BLOCKS
+            && !self.requested.contains(&start)
+            && self.requests.send((start, len)).is_ok()
+        {
+            self.requested.push_back(start);
+        }
+    }
+
+    /// Block starting at `start` if it is requested. Blocks requested
+    /// before it are dropped, e.g. after seeking.
+    fn take(&mut self, start: u64) -> Option<io::Result<Vec<u8>>> {
+        if !self.requested.contains(&start) {
+            return None;
+        }
+        let blocks = self.blocks.get_mut().ok()?;
+        while let Some(requested) = self.requested.pop_front() {
+            let block = blocks.recv().ok()?;
+            if requested == start {
+                return Some(block);
+            }
+        }
+        None
+    }
+}
+
+/// Seekable remote file, read by blocks with HTTP range requests.
+pub struct HttpReader {
+    file: HttpFile,
+    size: u64,
+    pos: u64,
+    block: Vec<u8>,
+    block_start: u64,
+    /// Fetch the next block while the current one is read, for playback.
+    prefetch: Option<Prefetcher>,
+}
+
+impl HttpReader {
+    /// Block starting at the position, from prefetch if it is requested.
+    fn fetch(&mut self, start: u64) -> io::Result<Vec<u8>> {
+        let len = BLOCK_SIZE.min(self.size - start);
+        match self.prefetch.as_mut().and_then(|p| p.take(start)) {
+            Some(block) => block,
+            None => self.file.get_range(start, len),
+        }
+    }
+
+    /// Request the block after the current one in background.
+    fn prefetch_next(&mut self) {
+        let start = self.block_start + self.block.len() as u64;
+        if let Some(prefetch) = self.prefetch.as_mut()
+            && start < self.size
+        {
+            prefetch.request(start, BLOCK_SIZE.min(self.size - start));
+        }
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_end = self.block_start + self.block.len() as u64;
+        if self.pos < self.block_start || self.pos >= block_end {
+            self.block = self.fetch(self.pos)?;
+            self.block_start = self.pos;
+            if self.block.is_empty() {
+                return Ok(0);
+            }
+            self.prefetch_next();
+        }
+        let offset = (self.pos - self.block_start) as usize;
+        let count = buf.len().min(self.block.len() - offset);
+        buf[..count].copy_from_slice(&self.block[offset..offset + count]);
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the file start",
+            )),
+        }
+    }
+}
+
+/// State of a download shared with players of the file.
+#[derive(Default)]
+struct DownloadState {
+    /// Temporary file, it is renamed to the cache file when finished.
+    part: Option<PathBuf>,
+    /// Bytes written to the part file.
+    written: u64,
+    /// Is downloaded successfully, None while downloading.
+    finished: Option<bool>,
+}
+
+#[derive(Default)]
+struct Download {
+    state: Mutex<DownloadState>,
+    changed: Condvar,
+}
+
+impl Download {
+    fn update(&self, f: impl FnOnce(&mut DownloadState)) {
+        f(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    /// Wait until the byte at `pos` is downloaded. Return the file to read
+    /// it from and count of bytes in it, or None if the position is far
+    /// ahead or downloading is failed, so it should be requested.
+    fn wait_for(&self, pos: u64, cache: &Path) -> Option<(PathBuf, u64)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.finished {
+                Some(true) => return Some((cache.to_path_buf(), u64::MAX)),
+                Some(false) => return None,
+                None => (),
+            }
+            if let Some(part) = &state.part
+                && pos < state.written
+            {
+                return Some((part.clone(), state.written));
+            }
+            if pos >= state.written + BLOCK_SIZE {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// Remote file played while it is downloaded to the disk cache. Data is
+/// read from the downloaded part, positions far ahead of it are requested.
+struct DownloadReader {
+    path: PathBuf,
+    download: Arc<Download>,
+    /// Part file or downloaded file.
+    file: Option<File>,
+    remote: HttpReader,
+    pos: u64,
+}
+
+impl Read for DownloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.remote.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let Some((path, available)) = self.download.wait_for(self.pos, &self.path) else {
+            self.remote.seek(SeekFrom::Start(self.pos))?;
+            let count = self.remote.read(buf)?;
+            self.pos += count as u64;
+            return Ok(count);
+        };
+        if self.file.is_none() {
+            self.file = Some(File::open(path)?);
+        }
+        let file = self.file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(self.pos))?;
+        let available = (available - self.pos).try_into().unwrap_or(usize::MAX);
+        let len = buf.len().min(available);
+        let count = file.read(&mut buf[..len])?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for DownloadReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.remote.seek(SeekFrom::Start(self.pos))?;
+        self.pos = self.remote.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Remove least recently played files of the cache directory until it is
+/// not larger than `limit` bytes. Part files of downloads are kept.
+fn evict(dir: &Path, limit: u64) -> io::Result<()> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && path.extension().is_none_or(|e| e != "part") {
+            let played = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((played, metadata.len(), path));
+        }
+    }
+    files.sort();
+    let mut total: u64 = files.iter().map(|f| f.1).sum();
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+        fs::remove_file(path)?;
+        total -= len;
+    }
+    Ok(())
+}
+
+/// Readable request error. Query is hidden, it could contain credentials.
+pub fn error_text(error: ureq::Error) -> String {
+    match error {
+        ureq::Error::Status(401, _) => "wrong user name or password".to_string(),
+        ureq::Error::Status(code, response) => {
+            let url = response.get_url();
+            let url = url.split('?').next().unwrap_or(url);
+            format!("{}: HTTP {} {}", url, code, response.status_text())
+        }
+        ureq::Error::Transport(transport) => {
+            let mut text = transport.kind().to_string();
+            if let Some(message) = transport.message() {
+                text = format!("{text}: {message}");
+            }
+            match transport.url() {
+                Some(url) => {
+                    let mut url = url.clone();
+                    url.set_query(None);
+                    format!("{url}: {text}")
+                }
+                None => text,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn prefetch_and_download_once() {
+        let data: Vec<u8> = (0..700_000).map(|i| (i % 251) as u8).collect();
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let ranges = Arc::new(AtomicUsize::new(0));
+        let address = {
+            let (data, downloads, ranges) =
+                (data.clone(), Arc::clone(&downloads), Arc::clone(&ranges));
+            mock::serve(move |request| {
+                match request.range {
+                    Some(_) if request.path == "/wind.ogg" => {
+                        ranges.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Some(_) => (),
+                    None => {
+                        thread::sleep(Duration::from_millis(200));
+                        downloads.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                mock::ranged(&data, request.range)
+            })
+        };
+        let url = Url::parse(&format!("http://{address}/rain.ogg")).unwrap();
+        let file = HttpFile::new(ureq::Agent::new(), url, None);
+
+        let mut reader = file.reader(data.len() as u64);
+        reader.prefetch = Some(Prefetcher::new(file.clone()));
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        let mut buffer = vec![0; 5];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data[10..15]);
+
+        // The file is played while it is downloaded, without other requests.
+        let url = Url::parse(&format!("http://{address}/wind.ogg")).unwrap();
+        let file = HttpFile::new(ureq::Agent::new(), url, None);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audio").join("wind.ogg");
+        let cache = || CachedFile {
+            path: path.clone(),
+            limit: u64::MAX,
+        };
+        let download = file.download_in_background(cache());
+        let other = file.download_in_background(cache());
+        assert!(Arc::ptr_eq(&download, &other));
+        let mut reader = DownloadReader {
+            path: path.clone(),
+            download,
+            file: None,
+            remote: file.reader(data.len() as u64),
+            pos: 0,
+        };
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        while DOWNLOADS.lock().unwrap().contains_key(&path) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        assert_eq!(ranges.load(Ordering::SeqCst), 0);
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn evict_least_recently_played() {
+        let dir = tempfile::tempdir().unwrap();
+        for (i, name) in ["old.ogg", "played.ogg", "new.ogg"].iter().enumerate() {
+            let path = dir.path().join(name);
+            fs::write(&path, vec![0; 100]).unwrap();
+            let played = SystemTime::UNIX_EPOCH + Duration::from_secs(1000 + i as u64);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(played)
+                .unwrap();
+        }
+        fs::write(dir.path().join("download.part"), vec![0; 100]).unwrap();
+
+        evict(dir.path(), 250).unwrap();
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["download.part", "new.ogg", "played.ogg"]);
+    }
+}
+
+/// Stand-in HTTP server for tests of remote storages.
+#[cfg(test)]
+pub mod mock {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
+    use url::Url;
+
+    pub struct Request {
+        pub method: String,
+        /// Path with query.
+        pub path: String,
+        /// Inclusive byte range.
+        pub range: Option<(usize, usize)>,
+    }
+
+    impl Request {
+        pub fn url(&self) -> Url {
+            Url::parse(&format!("http://host{}", self.path)).unwrap()
+        }
+    }
+
+    /// Requested range of the data or the whole data.
+    pub fn ranged(data: &[u8], range: Option<(usize, usize)>) -> (&'static str, Vec<u8>) {
+        match range {
+            Some((start, end)) => (
+                "206 Partial Content",
+                data[start..=end.min(data.len() - 1)].to_vec(),
+            ),
+            None => ("200 OK", data.to_vec()),
+        }
+    }
+
+    /// Serve requests with status and body returned by `handler`.
+    pub fn serve(
+        handler: impl Fn(&Request) -> (&'static str, Vec<u8>) + Send + 'static,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let mut request = Request {
+                    method: parts.next().unwrap().to_string(),
+                    path: parts.next().unwrap().to_string(),
+                    range: None,
+                };
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("range: bytes=") {
+                        let (start, end) = value.split_once('-').unwrap();
+                        request.range = Some((start.parse().unwrap(), end.parse().unwrap()));
+                    }
+                    if let Some(value) = line.strip_prefix("content-length: ") {
+                        content_length = value.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, body) = handler(&request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        address
+    }
+}
@@ -0,0 +1,301 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+//! Search index over storage sources.
+//! Keeps lowercase fields and a trigram index to match words with typos.
+
+use std::collections::HashMap;
+
+use crate::storage::{source::Source, tag::Tag};
+
+const TITLE_WEIGHT: f32 = 3.0;
+const TAG_WEIGHT: f32 = 2.0;
+const METADATA_WEIGHT: f32 = 1.0;
+
+/// Lowercase searchable fields of one source.
+pub struct Entry {
+    pub title: String,
+    pub tags: Vec<String>,
+    /// Groups of tags, see `Tag::group`.
+    pub categories: Vec<String>,
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    pub comment: String,
+    pub duration: f32,
+    /// Words of all fields with field weight.
+    words: Vec<(String, f32)>,
+}
+
+impl Entry {
+    pub fn new(source: &Source, tags: &[Tag]) -> Entry {
+        let metadata = source.metadata();
+        let source_tags: Vec<&Tag> = source
+            .tags()
+            .into_iter()
+            .filter_map(|i| tags.get(i))
+            .collect();
+        let mut entry = Entry {
+            title: source.get_title().to_lowercase(),
+            tags: source_tags
+                .iter()
+                .map(|t| t.get_text().to_lowercase())
+                .collect(),
+            categories: source_tags
+                .iter()
+                .map(|t| t.group())
+                .filter(|c| !c.is_empty())
+                .collect(),
+            artist: metadata.artist.to_lowercase(),
+            album: metadata.album.to_lowercase(),
+            genre: metadata.genre.to_lowercase(),
+            comment: metadata.comment.to_lowercase(),
+            duration: metadata.duration,
+            words: vec![],
+        };
+
+        let mut words = vec![];
+        push_words(&mut words, &entry.title, TITLE_WEIGHT);
+        for tag in &entry.tags {
+            push_words(&mut words, tag, TAG_WEIGHT);
+            // Whole tag too, so "forest-fire" is found as typed.
+            if tag.contains(|c: char| !c.is_alphanumeric()) {
+                words.push((tag.clone(), TAG_WEIGHT));
+            }
+        }
+        for field in [&entry.artist, &entry.album, &entry.genre, &entry.comment] {
+            push_words(&mut words, field, METADATA_WEIGHT);
+        }
+        entry.words = words;
+        entry
+    }
+
+    /// Score of lowercase word match, None if not matched.
+    /// Exact word is better than prefix, prefix is better than substring,
+    /// substring is better than word with typos. Title matches are weighted
+    /// more than tags and tags more than metadata.
+    pub fn word_score(&self, word: &str) -> Option<f32> {
+        let typos = allowed_typos(word);
+        let mut best: Option<f32> = None;
+        for (field_word, weight) in &self.words {
+            let score = if field_word == word {
+                1.0
+            } else if field_word.starts_with(word) {
+                0.9
+            } else if field_word.contains(word) {
+                0.7
+            } else if typos > 0 {
+                match fuzzy_distance(word, field_word) {
+                    Some(d) if d <= typos => 0.5 - 0.1 * d as f32,
+                    _ => continue,
+                }
+            } else {
+                continue;
+            };
+            let score = score * weight;
+            if best.is_none_or(|b| score > b) {
+                best = Some(score);
+            }
+        }
+
+        if best.is_none() {
+            // Substring over word borders, like "night amb".
+            for (field, weight) in [(&self.title, TITLE_WEIGHT), (&self.artist, METADATA_WEIGHT)] {
+                if field.contains(word) {
+                    best = Some(0.6 * weight);
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Index of all storage sources.
+pub struct SearchIndex {
+    entries: Vec<Entry>,
+    trigrams: HashMap<[char; 3], Vec<usize>>,
+}
+
+impl SearchIndex {
+    pub fn new(sources: &[Source], tags: &[Tag]) -> SearchIndex {
+        let entries: Vec<Entry> = sources.iter().map(|s| Entry::new(s, tags)).collect();
+        let mut trigrams: HashMap<[char; 3], Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            for (word, _) in &entry.words {
+                for trigram in word_trigrams(word) {
+                    let postings = trigrams.entry(trigram).or_default();
+                    if postings.last() != Some(&i) {
+                        postings.push(i);
+                    }
+                }
+            }
+        }
+        SearchIndex { entries, trigrams }
+    }
+
+    pub fn entry(&self, index: usize) -> &Entry {
+        &self.entries[index]
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Sources which could match the word, sorted.
+    /// None if the word is too short to use the index.
+    pub fn candidates(&self, word: &str) -> Option<Vec<usize>> {
+        if word.chars().count() < 3 {
+            return None;
+        }
+        let mut candidates: Vec<usize> = word_trigrams(word)
+            .iter()
+            .filter_map(|t| self.trigrams.get(t))
+            .flatten()
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        Some(candidates)
+    }
+}
+
+fn push_words(words: &mut Vec<(String, f32)>, text: &str, weight: f32) {
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if !word.is_empty() {
+            words.push((word.to_string(), weight));
+        }
+    }
+}
+
+/// Trigrams of word padded with spaces, so short words with typos
+/// still share a trigram with the original.
+fn word_trigrams(word: &str) -> Vec<[char; 3]> {
+    let chars: Vec<char> = "  "
+        .chars()
+        .chain(word.chars())
+        .chain(" ".chars())
+        .collect();
+    chars.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+fn allowed_typos(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Typos count between word and whole field word or its prefix.
+fn fuzzy_distance(word: &str, field_word: &str) -> Option<usize> {
+    let word: Vec<char> = word.chars().collect();
+    let field_word: Vec<char> = field_word.chars().collect();
+    if field_word.len() + 2 < word.len() {
+        return None;
+    }
+    let mut distance = osa_distance(&word, &field_word);
+    // Prefixes a char shorter or longer to allow insertions and deletions.
+    for len in word.len().saturating_sub(1)..=word.len() + 1 {
+        if len < field_word.len() {
+            distance = distance.min(osa_distance(&word, &field_word[..len]));
+        }
+    }
+    Some(distance)
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent chars.
+pub fn osa_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut d = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d = d.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = d;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::metadata::Metadata;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(osa_distance(&chars("tavren"), &chars("tavern")), 1);
+        assert_eq!(osa_distance(&chars("taven"), &chars("tavern")), 1);
+        assert_eq!(osa_distance(&chars("forst"), &chars("forest")), 1);
+        assert_eq!(osa_distance(&chars("abc"), &chars("")), 3);
+        assert_eq!(fuzzy_distance("drgon", "dragonborn"), Some(1));
+    }
+
+    #[test]
+    fn fuzzy_ranked() {
+        let mut tags = vec![Tag::new("tavern".to_string())];
+        tags.push(Tag::new("city".to_string()));
+
+        let mut in_title = Source::new("1.ogg".to_string(), "Tavern brawl".to_string());
+        in_title.attach_tag(1);
+        let mut in_tag = Source::new("2.ogg".to_string(), "Drinking song".to_string());
+        in_tag.attach_tag(0);
+        let mut in_metadata = Source::new("3.ogg".to_string(), "Lute".to_string());
+        in_metadata.set_metadata(Metadata {
+            album: "Tavern songs".to_string(),
+            ..Default::default()
+        });
+        let other = Source::new("4.ogg".to_string(), "Dragon attack".to_string());
+
+        let sources = vec![in_metadata, other, in_tag, in_title];
+        let index = SearchIndex::new(&sources, &tags);
+
+        let candidates = index.candidates("tavren").unwrap();
+        assert!(!candidates.contains(&1));
+
+        let mut scored: Vec<(usize, f32)> = candidates
+            .into_iter()
+            .filter_map(|i| index.entry(i).word_score("tavren").map(|s| (i, s)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let order: Vec<usize> = scored.iter().map(|s| s.0).collect();
+        assert_eq!(order, vec![3, 2, 0]);
+
+        // Exact match is better than typo.
+        let exact = index.entry(3).word_score("tavern").unwrap();
+        let typo = index.entry(3).word_score("tavren").unwrap();
+        assert!(exact > typo);
+
+        assert!(index.entry(1).word_score("tavren").is_none());
+        assert!(index.candidates("ta").is_none());
+    }
+}
@@ -14,15 +14,19 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
+use std::io::{BufReader, Read, Seek};
 use std::path::Path;
 use std::path::PathBuf;
 
-use id3::TagLike;
 use walkdir::WalkDir;
 
-use crate::storage::is_music_file;
-use crate::storage::tag::Tag;
-use rodio::Source;
+use crate::storage::cache::{self, CacheEntry, MetadataCache};
+use crate::storage::fingerprint::fingerprint;
+use crate::storage::metadata::{Metadata, read_metadata};
+use crate::storage::pathtags::PathTagger;
+use crate::storage::scanner::CANCELLED;
+use crate::storage::{ImportOptions, extension, is_music_file, undecodable_format};
+use rodio::{Source, decoder::DecoderError};
 use crate::stream::Opener;
 
 pub struct LocalOpener {
@@ -31,76 +35,207 @@ pub struct LocalOpener {
 }
 
 impl LocalOpener {
-    pub fn new(filename: String) -> LocalOpener {
-        LocalOpener {
-            filename,
-            duration: 0.0,
-        }
+    /// Duration is updated on source opening, could be 0 if unknown.
+    pub fn new(filename: String, duration: f32) -> LocalOpener {
+        LocalOpener { filename, duration }
     }
 }
 
 impl Opener for LocalOpener {
     fn source(&mut self) -> Result<Box<dyn rodio::Source + Send>, Box<dyn std::error::Error>> {
+        if let Some(format) = undecodable_format(&self.filename) {
+            return Err(format!("{format} playback is not supported").into());
+        }
+
         let file = std::fs::File::open(&self.filename)?;
-        let decoder = rodio::Decoder::try_from(file)?;
-        self.duration = decoder.total_duration().unwrap_or_default().as_secs_f32();
+        let len = file.metadata()?.len();
+        let decoder = decode(BufReader::new(file), len, &self.filename)?;
+        if let Some(duration) = decoder.total_duration() {
+            self.duration = duration.as_secs_f32();
+        }
         Ok(Box::new(decoder))
     }
 
     fn total_duration(&self) -> f32 {
         self.duration
     }
+
+    fn name(&self) -> String {
+        self.filename.clone()
+    }
 }
 
-pub fn load_local_sources(storage_path: &PathBuf) -> (Vec<super::source::Source>, Vec<Tag>) {
-    let mut sources = Vec::new();
-    let mut tags = Vec::new();
-
-    for dir_entry in WalkDir::new(storage_path).into_iter().flatten() {
-        let filename = dir_entry.path().to_string_lossy().to_string();
-        if is_music_file(&filename) {
-            let mut title: String = Path::new(&filename)
-                .file_stem()
-                .unwrap()
-                .to_string_lossy()
-                .chars()
-                .take(50)
-                .collect();
-
-            if let Ok(tag) = id3::Tag::read_from_path(dir_entry.path()) {
-                // if let Some(artist) = tag.artist() {
-                //     println!("artist: {}", artist);
-                // }
-                if let Some(t) = tag.title()
-                    && !t.trim().is_empty()
-                {
-                    title = t.trim().to_string();
-                }
-            }
-            let tag = dir_entry
-                .path()
-                .parent()
-                .unwrap()
-                .components()
-                .next_back()
-                .unwrap()
-                .as_os_str()
-                .to_string_lossy()
-                .to_string();
-
-            let mut new_source = super::source::Source::new(filename, title);
-            if let Some(i) = tags.iter().position(|p: &Tag| {p.get_text() == tag}) {
-                new_source.attach_tag(i);
-            } else {
-                let i = tags.len();
-                let tag = Tag::new(tag);
-                tags.push(tag);
-                new_source.attach_tag(i);
-            }
+/// Seekable decoder of audio data, filename gives format hint.
+pub fn decode<R>(data: R, len: u64, filename: &str) -> Result<rodio::Decoder<R>, String>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    let mut builder = rodio::Decoder::builder()
+        .with_data(data)
+        .with_byte_len(len)
+        .with_seekable(true);
+    let extension = extension(filename);
+    if !extension.is_empty() {
+        builder = builder.with_hint(&extension);
+    }
+    builder.build().map_err(|e| match e {
+        DecoderError::UnrecognizedFormat | DecoderError::NoStreams => {
+            "unsupported audio format or codec".to_string()
+        }
+        e => e.to_string(),
+    })
+}
+
+/// Title for files without title tag.
+pub fn default_title(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .chars()
+        .take(50)
+        .collect()
+}
+
+/// Audio file information read by storage scanning.
+pub struct ScannedFile {
+    pub filename: String,
+    pub title: String,
+    pub metadata: Metadata,
+    pub id: String,
+    pub size: u64,
+    /// Path relative to the storage root, empty for server songs.
+    pub path: PathBuf,
+    /// Tags given by the storage, like server genres.
+    pub storage_tags: Vec<String>,
+}
 
-            sources.push(new_source);
+impl ScannedFile {
+    /// All tags to attach on import.
+    pub fn tags(&self, options: &ImportOptions, tagger: &PathTagger) -> Vec<String> {
+        let mut tags = tagger.tags(&self.path);
+        tags.extend(self.storage_tags.iter().cloned());
+        if options.genre_tags {
+            tags.extend(self.metadata.genres());
         }
+        if options.album_tags && !self.metadata.album.is_empty() {
+            tags.push(self.metadata.album.clone());
+        }
+        tags
     }
+}
+
+/// Read all music files of the storage. Unchanged files are taken from
+/// the metadata cache in `cache_root`. `progress` gets count of scanned and
+/// total files and returns false if scanning is cancelled.
+pub fn scan_local_files(
+    root: &Path,
+    cache_root: &Path,
+    progress: &dyn Fn(usize, usize) -> bool,
+) -> Result<Vec<ScannedFile>, String> {
+    let paths: Vec<PathBuf> = WalkDir::new(root)
+        .into_iter()
+        .flatten()
+        .map(|e| e.into_path())
+        .filter(|p| p.is_file() && is_music_file(&p.to_string_lossy()))
+        .collect();
+
+    let mut cache = MetadataCache::load(cache_root);
+    let mut keys = Vec::with_capacity(paths.len());
+    let mut files = Vec::with_capacity(paths.len());
 
-   (sources, tags)
+    for (i, path) in paths.iter().enumerate() {
+        if !progress(i, paths.len()) {
+            return Err(CANCELLED.to_string());
+        }
+        let key = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string();
+        let (mtime, size) = match std::fs::metadata(path) {
+            Ok(m) => (cache::mtime(&m), m.len()),
+            Err(_) => (0, 0),
+        };
+
+        let entry = match cache.get(&key, mtime, size) {
+            Some(entry) => entry.clone(),
+            None => {
+                let (title, metadata) = read_metadata(path);
+                let entry = CacheEntry {
+                    mtime,
+                    size,
+                    id: fingerprint(path).unwrap_or_default(),
+                    title,
+                    metadata,
+                };
+                cache.insert(key.clone(), entry.clone());
+                entry
+            }
+        };
+
+        let title = entry.title.unwrap_or_else(|| default_title(path));
+
+        files.push(ScannedFile {
+            filename: path.to_string_lossy().to_string(),
+            title,
+            metadata: entry.metadata,
+            id: entry.id,
+            size,
+            path: PathBuf::from(&key),
+            storage_tags: vec![],
+        });
+        keys.push(key);
+    }
+    progress(paths.len(), paths.len());
+
+    cache.retain(&keys);
+    if let Err(e) = cache.save(cache_root) {
+        eprintln!("Error saving metadata cache: {}", e);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_uses_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_root = tempfile::tempdir().unwrap();
+        let forest = dir.path().join("Forest");
+        std::fs::create_dir(&forest).unwrap();
+        std::fs::write(forest.join("wind.ogg"), b"not really ogg").unwrap();
+        std::fs::write(forest.join("notes.txt"), b"text").unwrap();
+
+        let files = scan_local_files(dir.path(), cache_root.path(), &|_, _| true).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].title, "wind");
+        assert_eq!(files[0].path, Path::new("Forest").join("wind.ogg"));
+        assert!(MetadataCache::path(cache_root.path()).is_file());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Unchanged file is read from cache.
+        let mut cache = MetadataCache::load(cache_root.path());
+        let key = Path::new("Forest").join("wind.ogg").to_string_lossy().to_string();
+        let m = std::fs::metadata(forest.join("wind.ogg")).unwrap();
+        let mut entry = cache.get(&key, cache::mtime(&m), m.len()).unwrap().clone();
+        entry.title = Some("Cached wind".to_string());
+        cache.insert(key, entry);
+        cache.save(cache_root.path()).unwrap();
+
+        let files = scan_local_files(dir.path(), cache_root.path(), &|_, _| true).unwrap();
+        assert_eq!(files[0].title, "Cached wind");
+    }
+
+    #[test]
+    fn cancelled_scan_keeps_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_root = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("wind.ogg"), b"not really ogg").unwrap();
+
+        let files = scan_local_files(dir.path(), cache_root.path(), &|_, _| false);
+        assert_eq!(files.err().as_deref(), Some(CANCELLED));
+        assert!(!MetadataCache::path(cache_root.path()).exists());
+    }
 }
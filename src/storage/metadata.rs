@@ -0,0 +1,110 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+use std::{
+    io::{Read, Seek},
+    path::Path,
+};
+
+use lofty::{file::TaggedFile, prelude::*, probe::Probe};
+use serde::{Deserialize, Serialize};
+
+/// Audio file information from ID3, Vorbis comments, FLAC and other tags.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    pub comment: String,
+    /// Seconds, 0 if unknown.
+    pub duration: f32,
+}
+
+impl Metadata {
+    /// Genres of multi-genre tags like "Ambient; Folk".
+    pub fn genres(&self) -> Vec<String> {
+        self.genre
+            .split([';', '/', ','])
+            .map(|g| g.trim().to_string())
+            .filter(|g| !g.is_empty())
+            .collect()
+    }
+
+    /// Is any field contains lowercase pattern.
+    pub fn contains(&self, pattern: &str) -> bool {
+        [&self.artist, &self.album, &self.genre, &self.comment]
+            .iter()
+            .any(|f| f.to_lowercase().contains(pattern))
+    }
+}
+
+/// Read title and metadata of file. Title is None if the file has no title tag.
+pub fn read_metadata(path: &Path) -> (Option<String>, Metadata) {
+    tagged_metadata(lofty::read_from_path(path).ok())
+}
+
+/// Read title and metadata from data, e.g. remote file.
+pub fn read_metadata_from<R: Read + Seek>(reader: R) -> (Option<String>, Metadata) {
+    let file = Probe::new(reader)
+        .guess_file_type()
+        .ok()
+        .and_then(|probe| probe.read().ok());
+    tagged_metadata(file)
+}
+
+fn tagged_metadata(file: Option<TaggedFile>) -> (Option<String>, Metadata) {
+    let mut metadata = Metadata::default();
+    let Some(file) = file else {
+        return (None, metadata);
+    };
+
+    metadata.duration = file.properties().duration().as_secs_f32();
+
+    let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) else {
+        return (None, metadata);
+    };
+
+    let text = |value: Option<std::borrow::Cow<'_, str>>| {
+        value.map(|v| v.trim().to_string()).unwrap_or_default()
+    };
+    metadata.artist = text(tag.artist());
+    metadata.album = text(tag.album());
+    metadata.genre = text(tag.genre());
+    metadata.comment = text(tag.comment());
+
+    let title = text(tag.title());
+    if title.is_empty() {
+        (None, metadata)
+    } else {
+        (Some(title), metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_genres() {
+        let metadata = Metadata {
+            genre: "Ambient; Folk/Celtic, ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(metadata.genres(), vec!["Ambient", "Folk", "Celtic"]);
+        assert!(Metadata::default().genres().is_empty());
+    }
+}
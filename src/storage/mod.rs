@@ -14,51 +14,314 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
+pub mod analysis;
+pub mod cache;
+pub mod duplicates;
+pub mod fingerprint;
+pub mod http;
+pub mod index;
 pub mod localstorage;
+pub mod metadata;
+pub mod pathtags;
+pub mod portable;
+pub mod query;
+pub mod relink;
+pub mod scanner;
 pub mod source;
+pub mod subsonic;
 pub mod tag;
+pub mod tagexport;
+pub mod waveform;
+pub mod webdav;
+pub mod zipstorage;
 
-use std::path::{Path, PathBuf};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use id3::TagLike;
 use serde::{Deserialize, Serialize};
-use walkdir::WalkDir;
 
+use analysis::{Analyzer, Features};
+use duplicates::DuplicateGroup;
+use index::SearchIndex;
+use query::Query;
+use localstorage::ScannedFile;
+use pathtags::{PathRules, PathTagger};
+use relink::Relinker;
+use scanner::Scanner;
 use source::Source;
+use subsonic::SubsonicClient;
 use tag::Tag;
+use tagexport::{Format, TagRecord};
+use waveform::Waveform;
+use webdav::WebDavClient;
 
-use crate::{colors, storage::localstorage::load_local_sources};
-
-type TagIndexes = Vec<usize>;
+use crate::colors;
 
 #[derive(Deserialize, Serialize)]
 pub enum StorageCredentials {
     Local(PathBuf),
+    /// HTTP/WebDAV folder, password is not saved to project.
+    WebDav { url: String, user: String },
+    /// Subsonic compatible server, e.g. Navidrome.
+    Subsonic { url: String, user: String },
+    /// Zip archives, e.g. sound packs.
+    Zip(Vec<PathBuf>),
 }
 
-struct LocalStorageCredentials {
-    path: PathBuf,
+impl StorageCredentials {
+    /// Storage caption by location, e.g. folder name.
+    pub fn default_caption(&self) -> String {
+        match self {
+            StorageCredentials::Local(path) => path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.to_string_lossy().to_string()),
+            StorageCredentials::WebDav { url, .. } => url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .map(|name| percent_encoding::percent_decode_str(name).decode_utf8_lossy())
+                .unwrap_or_default()
+                .to_string(),
+            StorageCredentials::Zip(archives) => archives
+                .first()
+                .and_then(|archive| archive.file_stem())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            StorageCredentials::Subsonic { url, .. } => url::Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.to_string()))
+                .unwrap_or_else(|| url.clone()),
+        }
+    }
+
+    /// Source filename relative to the storage root with `/` separators.
+    pub fn relative_path(&self, filename: &str) -> String {
+        let relative = match self {
+            StorageCredentials::Local(root) => Path::new(filename)
+                .strip_prefix(root)
+                .ok()
+                .map(|path| {
+                    path.components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                }),
+            StorageCredentials::Zip(_) => {
+                zipstorage::split_filename(filename).map(|(archive, entry)| {
+                    let archive = archive.file_name().unwrap_or_default().to_string_lossy();
+                    format!("{archive}/{entry}")
+                })
+            }
+            StorageCredentials::WebDav { url, .. } => filename
+                .strip_prefix(url.trim_end_matches('/'))
+                .map(|path| {
+                    let path = path.trim_start_matches('/');
+                    percent_encoding::percent_decode_str(path)
+                        .decode_utf8_lossy()
+                        .to_string()
+                }),
+            StorageCredentials::Subsonic { .. } => filename
+                .strip_prefix(subsonic::SCHEME)
+                .and_then(|rest| rest.split_once('/'))
+                .map(|(_, path)| path.to_string()),
+        };
+        relative.unwrap_or_else(|| filename.to_string())
+    }
+}
+
+/// Settings of sources import.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Attach genres from file tags as storage tags.
+    pub genre_tags: bool,
+    /// Attach album from file tags as storage tag.
+    pub album_tags: bool,
+    /// Tags from folders and filenames.
+    pub path: PathRules,
+}
+
+/// Storage tags with tags, suggestions and hidden flags of sources by
+/// filename, to undo changes of many sources at once.
+#[derive(Clone, PartialEq)]
+pub struct TagState {
+    tags: Vec<Tag>,
+    sources: HashMap<String, SourceTags>,
+}
+
+#[derive(Clone, PartialEq)]
+struct SourceTags {
+    tags: Vec<String>,
+    suggestions: Vec<String>,
+    hidden: bool,
 }
 
 /// Storage of audio sources, that read audio files from local disk.
 /// Open stream from .mp3, .ogg and so on files.
 #[derive(Deserialize, Serialize)]
 pub struct Storage {
+    /// Unique id, sources and tracks refer to the storage by it.
+    #[serde(default = "new_storage_id")]
+    id: String,
     title: String,
     credentials: Option<StorageCredentials>,
     sources: Vec<Source>,
     tags: Vec<Tag>,
+    #[serde(default)]
+    import: ImportOptions,
+    /// Keep played files of remote storage on disk.
+    #[serde(default)]
+    disk_cache: bool,
+    /// Size limit of played files on disk in MiB.
+    #[serde(default = "default_disk_cache_limit")]
+    disk_cache_limit: u64,
+
+    #[serde(skip)]
+    password: String,
+    /// Project file, the metadata cache is kept next to it.
+    #[serde(skip)]
+    project: Option<PathBuf>,
+    /// Error of last scanning, e.g. server is not available.
+    #[serde(skip)]
+    scan_error: Option<String>,
+    /// Built on first search after changes.
+    #[serde(skip)]
+    index: RefCell<Option<SearchIndex>>,
+    #[serde(skip)]
+    scanner: Option<Scanner>,
+    #[serde(skip)]
+    analyzer: Option<Analyzer<Features>>,
+    /// Waveforms by source key, see `waveform_key`. Empty waveform is
+    /// kept for files failed to decode, they are not decoded again.
+    #[serde(skip)]
+    waveforms: HashMap<String, Waveform>,
+    #[serde(skip)]
+    waveform_generator: Option<Analyzer<Waveform>>,
+    /// Count of waveforms generated after the last cache saving.
+    #[serde(skip)]
+    unsaved_waveforms: usize,
+    /// Changed on every change of sources or tags.
+    #[serde(skip)]
+    revision: Cell<u64>,
 }
 
 impl Storage {
     pub fn new() -> Storage {
-        let storage = Storage {
+        Storage {
+            id: new_storage_id(),
             title: "New storage".into(),
             credentials: None,
             sources: vec![],
             tags: vec![],
-        };
-        storage
+            import: ImportOptions::default(),
+            disk_cache: false,
+            disk_cache_limit: default_disk_cache_limit(),
+            password: String::new(),
+            project: None,
+            scan_error: None,
+            index: RefCell::new(None),
+            scanner: None,
+            analyzer: None,
+            waveforms: HashMap::new(),
+            waveform_generator: None,
+            unsaved_waveforms: 0,
+            revision: Cell::new(0),
+        }
+    }
+
+    /// Fill data not stored in old projects.
+    pub fn restore(&mut self) {
+        for source in self.sources.iter_mut() {
+            if source.get_storage().is_empty() {
+                source.set_storage(self.id.clone());
+            }
+        }
+        let _ = self.connect();
+        self.generate_waveforms();
+    }
+
+    pub fn is_remote(&self) -> bool {
+        matches!(
+            self.credentials,
+            Some(StorageCredentials::WebDav { .. } | StorageCredentials::Subsonic { .. })
+        )
+    }
+
+    pub fn get_password(&self) -> String {
+        self.password.clone()
+    }
+
+    /// Password of remote storage. Storage should be rescanned to use it.
+    pub fn set_password(&mut self, password: String) {
+        self.password = password;
+    }
+
+    pub fn get_disk_cache(&self) -> bool {
+        self.disk_cache
+    }
+
+    pub fn set_disk_cache(&mut self, disk_cache: bool) {
+        self.disk_cache = disk_cache;
+        let _ = self.connect();
+    }
+
+    pub fn get_disk_cache_limit(&self) -> u64 {
+        self.disk_cache_limit
+    }
+
+    pub fn set_disk_cache_limit(&mut self, limit: u64) {
+        self.disk_cache_limit = limit;
+        let _ = self.connect();
+    }
+
+    pub fn get_scan_error(&self) -> Option<String> {
+        self.scan_error.clone()
+    }
+
+    /// Register client of remote storage for its sources playback.
+    /// Return scanner of remote storage.
+    fn connect(&self) -> Result<Option<Scanner>, String> {
+        let cache = cache_dir(&self.id);
+        let limit = self
+            .disk_cache
+            .then_some(self.disk_cache_limit * 1024 * 1024);
+        match &self.credentials {
+            Some(StorageCredentials::WebDav { url, user }) => {
+                let client = WebDavClient::new(url, user, &self.password, cache, limit)?;
+                webdav::register(&self.id, client.clone());
+                Ok(Some(Scanner::webdav(client, self.metadata_dir())))
+            }
+            Some(StorageCredentials::Subsonic { url, user }) => {
+                let client = SubsonicClient::new(url, user, &self.password, cache, limit)?;
+                subsonic::register(&self.id, client.clone());
+                Ok(Some(Scanner::subsonic(client)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Project file of the storage, `None` if the project is not saved.
+    pub fn set_project(&mut self, project: Option<PathBuf>) {
+        self.project = project;
+    }
+
+    /// Directory of the metadata cache, it is next to the project file.
+    /// Storages of unsaved projects keep it in the user cache directory.
+    fn metadata_dir(&self) -> PathBuf {
+        match &self.project {
+            Some(project) => project.with_extension(METADATA_EXTENSION).join(&self.id),
+            None => cache_dir(&self.id),
+        }
     }
 
     pub fn get(&self, index: usize) -> Option<Source> {
@@ -69,18 +332,56 @@ impl Storage {
         self.sources.len()
     }
 
+    /// Indexes of sources which files are not found.
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.sources.len())
+            .filter(|&i| self.sources[i].is_missing())
+            .collect()
+    }
+
+    /// Repair paths of missing sources. Return count of relinked sources.
+    pub fn relink(&mut self, relinker: &mut Relinker) -> usize {
+        let mut count = 0;
+        for source in self.sources.iter_mut().filter(|s| s.is_missing()) {
+            if let Some(path) = relinker.find(source) {
+                source.set_filename(path.to_string_lossy().to_string());
+                count += 1;
+            }
+        }
+        count
+    }
+
     pub fn attach_tag(&mut self, source_index: usize, tag: String) {
+        self.invalidate_index();
         if source_index >= self.sources.len() {
             return;
         }
 
-        if let Some(i) = self.tags.iter().position(|p: &Tag| p.get_text() == tag) {
-            self.sources[source_index].attach_tag(i);
-        } else {
-            let i = self.tags.len();
-            let tag = Tag::new(tag);
-            self.tags.push(tag);
-            self.sources[source_index].attach_tag(i);
+        let i = self.tag_index(tag);
+        self.sources[source_index].attach_tag(i);
+    }
+
+    /// Attach tag to several sources at once.
+    pub fn attach_tag_to(&mut self, source_indexes: &[usize], tag: String) {
+        self.invalidate_index();
+        let i = self.tag_index(tag);
+        for &index in source_indexes {
+            if let Some(source) = self.sources.get_mut(index) {
+                source.attach_tag(i);
+            }
+        }
+    }
+
+    /// Remove tag from several sources at once, the tag itself is kept.
+    pub fn unattach_tag_from(&mut self, source_indexes: &[usize], tag: String) {
+        self.invalidate_index();
+        let Some(i) = self.tags.iter().position(|t| t.get_text() == tag) else {
+            return;
+        };
+        for &index in source_indexes {
+            if let Some(source) = self.sources.get_mut(index) {
+                source.unattach_tag(i);
+            }
         }
     }
 
@@ -92,46 +393,661 @@ impl Storage {
         self.title = new_caption
     }
 
+    pub fn get_import_options(&self) -> ImportOptions {
+        self.import.clone()
+    }
+
+    pub fn set_import_options(&mut self, options: ImportOptions) {
+        self.import = options;
+    }
+
+    /// Set new storage location and start scanning it.
     pub fn setup_storage(&mut self, cred: StorageCredentials) {
+        self.invalidate_index();
+        self.sources.clear();
+        self.tags.clear();
         self.credentials = Some(cred);
-        (self.sources, self.tags) = match &self.credentials.as_ref().unwrap() {
-            StorageCredentials::Local(path_buf) => load_local_sources(&path_buf),
+        self.rescan();
+    }
+
+    /// Start background scanning. Tags of known sources are kept.
+    pub fn rescan(&mut self) {
+        if let Some(scanner) = self.scanner.take() {
+            scanner.stop();
+        }
+        self.scan_error = None;
+        self.scanner = match &self.credentials {
+            Some(StorageCredentials::Local(path)) => {
+                Some(Scanner::local(path.clone(), self.metadata_dir()))
+            }
+            Some(StorageCredentials::Zip(archives)) => {
+                Some(Scanner::zip(archives.clone(), self.metadata_dir()))
+            }
+            _ => self.connect().unwrap_or_else(|e| {
+                self.scan_error = Some(e);
+                None
+            }),
+        };
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scanner.is_some()
+    }
+
+    /// Count of scanned and total files if scanning.
+    pub fn scan_progress(&self) -> Option<(usize, usize)> {
+        self.scanner.as_ref().map(|s| s.progress())
+    }
+
+    /// Update scanning state. Return true if scanning is just finished.
+    pub fn poll_scan(&mut self) -> bool {
+        let Some(result) = self.scanner.as_mut().and_then(|s| s.poll()) else {
+            return false;
+        };
+        self.scanner = None;
+        match result {
+            Ok(files) => {
+                self.merge_scanned(files);
+                self.generate_waveforms();
+            }
+            Err(e) => self.scan_error = Some(e),
+        }
+        true
+    }
+
+    /// Start background generation of waveforms not cached yet. Files of
+    /// remote storages are not downloaded for it.
+    pub fn generate_waveforms(&mut self) {
+        if self.waveform_generator.is_some() {
+            return;
+        }
+        if self.waveforms.is_empty() {
+            self.waveforms = waveform::load_cache(&cache_dir(&self.id));
+        }
+        let jobs: Vec<_> = self
+            .sources
+            .iter()
+            .filter(|s| !self.waveforms.contains_key(&waveform_key(s)))
+            .filter(|s| !is_remote(&s.get_filename()) && !s.is_missing())
+            .map(|s| (waveform_key(s), s.opener()))
+            .collect();
+        if !jobs.is_empty() {
+            self.waveform_generator = Some(Analyzer::new(jobs, waveform::generate));
+        }
+    }
+
+    /// Count of generated and total waveforms if generating.
+    pub fn waveform_progress(&self) -> Option<(usize, usize)> {
+        self.waveform_generator.as_ref().map(|g| g.progress())
+    }
+
+    /// Keep generated waveforms, the cache is saved by parts, so long
+    /// generation is not lost on exit. Return true if some are generated.
+    pub fn poll_waveforms(&mut self) -> bool {
+        let Some(generator) = self.waveform_generator.as_mut() else {
+            return false;
+        };
+        let (generated, finished) = generator.poll();
+        let is_generated = !generated.is_empty();
+        self.unsaved_waveforms += generated.len();
+        for (key, waveform) in generated {
+            let waveform = waveform.unwrap_or_else(|e| {
+                eprintln!("Error making waveform of {}: {}", key, e);
+                Waveform::default()
+            });
+            self.waveforms.insert(key, waveform);
+        }
+        if finished {
+            self.waveform_generator = None;
+        }
+        if self.unsaved_waveforms > 0 && (finished || self.unsaved_waveforms >= WAVEFORMS_PER_SAVE)
+        {
+            self.unsaved_waveforms = 0;
+            if let Err(e) = waveform::save_cache(&cache_dir(&self.id), &self.waveforms) {
+                eprintln!("Error saving waveforms: {}", e);
+            }
+        }
+        is_generated
+    }
+
+    pub fn get_waveform(&self, index: usize) -> Option<Waveform> {
+        let source = self.sources.get(index)?;
+        let waveform = self.waveforms.get(&waveform_key(source))?;
+        (!waveform.is_empty()).then(|| waveform.clone())
+    }
+
+    /// Start background analysis of sources not analyzed yet.
+    pub fn analyze(&mut self) {
+        let jobs: Vec<_> = self
+            .sources
+            .iter()
+            .filter(|s| s.get_features().is_none() && !s.is_missing())
+            .map(|s| (s.get_filename(), s.opener()))
+            .collect();
+        if !jobs.is_empty() {
+            self.analyzer = Some(Analyzer::new(jobs, analysis::analyze_opener));
+        }
+    }
+
+    pub fn is_analyzing(&self) -> bool {
+        self.analyzer.is_some()
+    }
+
+    /// Count of analyzed and total sources if analyzing.
+    pub fn analysis_progress(&self) -> Option<(usize, usize)> {
+        self.analyzer.as_ref().map(|a| a.progress())
+    }
+
+    /// Save analyzed features and suggest tags. Return true if analysis is
+    /// just finished.
+    pub fn poll_analysis(&mut self) -> bool {
+        let Some(analyzer) = self.analyzer.as_mut() else {
+            return false;
+        };
+        let (analyzed, finished) = analyzer.poll();
+        for (filename, features) in analyzed {
+            let features = match features {
+                Ok(features) => features,
+                Err(e) => {
+                    eprintln!("Error analyzing {}: {}", filename, e);
+                    continue;
+                }
+            };
+            let Some(i) = self.sources.iter().position(|s| s.get_filename() == filename) else {
+                continue;
+            };
+            let attached: Vec<String> = self.get_tags(i).iter().map(|t| t.get_text()).collect();
+            let suggestions = features
+                .suggested_tags()
+                .into_iter()
+                .filter(|t| !attached.contains(t))
+                .collect();
+            self.sources[i].set_features(features, suggestions);
+        }
+        if finished {
+            self.analyzer = None;
+        }
+        finished
+    }
+
+    /// Indexes of sources with suggested tags.
+    pub fn suggested_sources(&self) -> Vec<usize> {
+        (0..self.sources.len())
+            .filter(|&i| !self.sources[i].get_suggestions().is_empty())
+            .collect()
+    }
+
+    pub fn accept_suggestion(&mut self, source_index: usize, tag: String) {
+        if source_index >= self.sources.len() {
+            return;
+        }
+        self.sources[source_index].remove_suggestion(&tag);
+        let category = analysis::suggested_category(&tag);
+        self.attach_tag(source_index, tag.clone());
+        if let Some(tag) = self.tags.iter_mut().find(|t| t.get_text() == tag)
+            && tag.get_category().is_empty()
+        {
+            tag.set_category(category.unwrap_or_default().to_string());
         }
     }
 
-    pub fn unattach_tag(&mut self, index: usize, tag: String) {
-        let tag_index = self.tags.iter().position(|t| t.get_text() == tag);
+    /// Attach all suggested tags of sources.
+    pub fn accept_suggestions(&mut self, source_indexes: &[usize]) {
+        for &i in source_indexes {
+            for tag in self.sources.get(i).map(|s| s.get_suggestions()).unwrap_or_default() {
+                self.accept_suggestion(i, tag);
+            }
+        }
+    }
+
+    pub fn reject_suggestions(&mut self, source_indexes: &[usize]) {
+        for &i in source_indexes {
+            if let Some(source) = self.sources.get_mut(i) {
+                for tag in source.get_suggestions() {
+                    source.remove_suggestion(&tag);
+                }
+            }
+        }
+    }
+
+    /// Groups of duplicated sources, see `storage::duplicates`.
+    pub fn find_duplicates(&self) -> Vec<DuplicateGroup> {
+        duplicates::find_duplicates(&self.sources)
+    }
+
+    /// Attach tags of duplicates to the kept source, optionally hide them.
+    pub fn merge_duplicates(&mut self, keep: usize, others: &[usize], hide: bool) {
+        self.invalidate_index();
+        if keep >= self.sources.len() {
+            return;
+        }
+        let count = self.sources.len();
+        for &i in others.iter().filter(|&&i| i != keep && i < count) {
+            for tag in self.sources[i].tags() {
+                self.sources[keep].attach_tag(tag);
+            }
+            if hide {
+                self.sources[i].set_hidden(true);
+            }
+        }
+    }
+
+    /// Filenames of sources. Commands keep sources by them, as indexes
+    /// change on rescan.
+    pub fn source_filenames(&self, source_indexes: &[usize]) -> Vec<String> {
+        source_indexes
+            .iter()
+            .filter_map(|&i| self.sources.get(i))
+            .map(|s| s.get_filename())
+            .collect()
+    }
+
+    /// Indexes of sources with the filenames.
+    pub fn source_indexes(&self, filenames: &[String]) -> Vec<usize> {
+        (0..self.sources.len())
+            .filter(|&i| filenames.contains(&self.sources[i].get_filename()))
+            .collect()
+    }
+
+    pub fn get_tag_state(&self) -> TagState {
+        let sources = self
+            .sources
+            .iter()
+            .map(|source| {
+                let tags = SourceTags {
+                    tags: self.tag_texts(source),
+                    suggestions: source.get_suggestions(),
+                    hidden: source.is_hidden(),
+                };
+                (source.get_filename(), tags)
+            })
+            .collect();
+        TagState {
+            tags: self.tags.clone(),
+            sources,
+        }
+    }
+
+    /// Restore tags of storage and sources. Sources not in the state, e.g.
+    /// found by a later scan, keep their tags.
+    pub fn set_tag_state(&mut self, state: TagState) {
+        self.invalidate_index();
+        let texts: Vec<Vec<String>> = self.sources.iter().map(|s| self.tag_texts(s)).collect();
+        self.tags = state.tags;
+        for (i, texts) in texts.into_iter().enumerate() {
+            let saved = state.sources.get(&self.sources[i].get_filename());
+            let texts = saved.map(|s| s.tags.clone()).unwrap_or(texts);
+            let tags = texts.into_iter().map(|t| self.tag_index(t)).collect();
+            let source = &mut self.sources[i];
+            source.set_tags(tags);
+            if let Some(saved) = saved {
+                source.set_suggestions(saved.suggestions.clone());
+                source.set_hidden(saved.hidden);
+            }
+        }
+    }
+
+    fn tag_texts(&self, source: &Source) -> Vec<String> {
+        source
+            .tags()
+            .iter()
+            .filter_map(|&i| self.tags.get(i))
+            .map(|t| t.get_text())
+            .collect()
+    }
+
+    pub fn hidden_count(&self) -> usize {
+        self.sources.iter().filter(|s| s.is_hidden()).count()
+    }
+
+    pub fn unhide_all(&mut self) {
+        self.invalidate_index();
+        for source in &mut self.sources {
+            source.set_hidden(false);
+        }
+    }
 
-        if let Some(i) = tag_index {
-            self.sources[index].unattach_tag(i);
+    /// Source filename relative to the storage root.
+    pub fn relative_path(&self, filename: &str) -> String {
+        match &self.credentials {
+            Some(credentials) => credentials.relative_path(filename),
+            None => filename.to_string(),
         }
     }
 
-    pub fn rename_tag(&mut self, old_name: String, new_name: String) {
+    /// Is the file a source of the storage or under its folder.
+    pub fn owns_file(&self, filename: &str) -> bool {
+        if self.sources.iter().any(|s| s.get_filename() == filename) {
+            return true;
+        }
+        match &self.credentials {
+            Some(StorageCredentials::Local(root)) => Path::new(filename).starts_with(root),
+            Some(StorageCredentials::Zip(archives)) => zipstorage::split_filename(filename)
+                .is_some_and(|(archive, _)| archives.contains(&archive)),
+            _ => false,
+        }
+    }
+
+    /// Filename of source saved to the project in the directory: relative
+    /// to the storage root or archives relative to the directory.
+    pub fn portable_filename(&self, filename: &str, dir: &Path) -> String {
+        match &self.credentials {
+            Some(StorageCredentials::Local(root)) => portable::portable_filename(filename, root),
+            Some(StorageCredentials::Zip(_)) => zipstorage::split_filename(filename)
+                .and_then(|(archive, entry)| {
+                    let archive = portable::relative_to(&archive, dir)?;
+                    Some(zipstorage::entry_filename(&archive, &entry))
+                })
+                .unwrap_or_else(|| filename.to_string()),
+            _ => filename.to_string(),
+        }
+    }
+
+    /// Absolute filename of source loaded from the project in the directory.
+    pub fn absolute_filename(&self, filename: &str, dir: &Path) -> String {
+        match &self.credentials {
+            Some(StorageCredentials::Local(root)) => portable::absolute_filename(filename, root),
+            Some(StorageCredentials::Zip(_)) => match zipstorage::split_filename(filename) {
+                Some((archive, entry)) if archive.is_relative() => {
+                    zipstorage::entry_filename(&portable::join(dir, &archive), &entry)
+                }
+                _ => filename.to_string(),
+            },
+            _ => filename.to_string(),
+        }
+    }
+
+    /// Make paths relative for saving the project to the directory.
+    pub fn make_portable(&mut self, dir: &Path) {
+        for i in 0..self.sources.len() {
+            let filename = self.portable_filename(&self.sources[i].get_filename(), dir);
+            self.sources[i].set_filename(filename);
+        }
+        match &mut self.credentials {
+            Some(StorageCredentials::Local(root)) => {
+                if let Some(relative) = portable::relative_to(root, dir) {
+                    *root = relative;
+                }
+            }
+            Some(StorageCredentials::Zip(archives)) => {
+                for archive in archives.iter_mut() {
+                    if let Some(relative) = portable::relative_to(archive, dir) {
+                        *archive = relative;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Make paths absolute after the project in the directory is loaded
+    /// or saved.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        match &mut self.credentials {
+            Some(StorageCredentials::Local(root)) if root.is_relative() => {
+                *root = portable::join(dir, root);
+            }
+            Some(StorageCredentials::Zip(archives)) => {
+                for archive in archives.iter_mut().filter(|a| a.is_relative()) {
+                    *archive = portable::join(dir, archive);
+                }
+            }
+            _ => (),
+        }
+        for i in 0..self.sources.len() {
+            let filename = self.absolute_filename(&self.sources[i].get_filename(), dir);
+            self.sources[i].set_filename(filename);
+        }
+    }
+
+    /// Root of local storage if its folder is not found.
+    pub fn get_missing_root(&self) -> Option<PathBuf> {
+        match &self.credentials {
+            Some(StorageCredentials::Local(root)) if !root.is_dir() => Some(root.clone()),
+            _ => None,
+        }
+    }
+
+    /// Move local storage with all its sources to other folder and rescan
+    /// it. Return the old root.
+    pub fn remap_root(&mut self, new_root: PathBuf) -> Option<PathBuf> {
+        let Some(StorageCredentials::Local(old_root)) = &self.credentials else {
+            return None;
+        };
+        let old_root = old_root.clone();
+        for source in &mut self.sources {
+            let filename = portable::remap_filename(&source.get_filename(), &old_root, &new_root);
+            source.set_filename(filename);
+        }
+        self.credentials = Some(StorageCredentials::Local(new_root));
+        self.invalidate_index();
+        self.rescan();
+        Some(old_root)
+    }
+
+    /// Tags of visible sources with colors.
+    pub fn tag_records(&self) -> Vec<TagRecord> {
+        let visible: Vec<usize> = (0..self.sources.len())
+            .filter(|&i| !self.sources[i].is_hidden())
+            .collect();
+        self.tag_records_of(&visible)
+    }
+
+    /// Tags of given sources with colors.
+    pub fn tag_records_of(&self, source_indexes: &[usize]) -> Vec<TagRecord> {
+        source_indexes
+            .iter()
+            .filter_map(|&i| self.sources.get(i).map(|source| (i, source)))
+            .map(|(i, source)| {
+                let tags = self.get_tags(i);
+                TagRecord {
+                    path: self.relative_path(&source.get_filename()),
+                    id: source.get_id(),
+                    title: source.get_title(),
+                    tags: tags.iter().map(|t| t.get_text()).collect(),
+                    colors: tags.iter().map(|t| t.get_color()).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Attach tags of records to sources with the same id or relative path.
+    /// Colors are set to new tags only. Return counts of matched and not
+    /// matched records.
+    pub fn apply_tag_records(&mut self, records: &[TagRecord], replace: bool) -> (usize, usize) {
+        self.invalidate_index();
+        let by_id: HashMap<String, usize> = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.get_id().is_empty())
+            .map(|(i, s)| (s.get_id(), i))
+            .collect();
+        let by_path: HashMap<String, usize> = self
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (self.relative_path(&s.get_filename()), i))
+            .collect();
+
+        let mut matched = 0;
+        for record in records {
+            let source = by_id
+                .get(&record.id)
+                .or_else(|| by_path.get(&record.path.replace('\\', "/")));
+            let Some(&i) = source else {
+                continue;
+            };
+            matched += 1;
+            if replace {
+                for tag in self.sources[i].tags() {
+                    self.sources[i].unattach_tag(tag);
+                }
+            }
+            for (n, text) in record.tags.iter().enumerate() {
+                let is_new = !self.tags.iter().any(|t| t.get_text() == *text);
+                let tag = self.tag_index(text.clone());
+                if is_new && let Some(color) = record.colors.get(n).filter(|c| !c.is_empty()) {
+                    self.tags[tag].set_color(color.clone());
+                }
+                self.sources[i].attach_tag(tag);
+            }
+        }
+        (matched, records.len() - matched)
+    }
+
+    /// Save tags of sources to CSV or JSON file.
+    pub fn export_tags(&self, path: &Path) -> Result<(), String> {
+        tagexport::save_records(path, &self.tag_records())
+    }
+
+    /// Load tags of sources from CSV or JSON file, see `apply_tag_records`.
+    pub fn import_tags(&mut self, path: &Path, replace: bool) -> Result<(usize, usize), String> {
+        let format = Format::from_path(path).ok_or("unknown file format")?;
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let records = tagexport::read_records(&text, format)?;
+        Ok(self.apply_tag_records(&records, replace))
+    }
+
+    fn merge_scanned(&mut self, files: Vec<ScannedFile>) {
+        self.invalidate_index();
+        let old = std::mem::take(&mut self.sources);
+        let by_id: HashMap<String, usize> = old
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.get_id().is_empty())
+            .map(|(i, s)| (s.get_id(), i))
+            .collect();
+        let by_filename: HashMap<String, usize> = old
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.get_filename(), i))
+            .collect();
+
+        let mut found = vec![false; old.len()];
+        let tagger = PathTagger::new(&self.import.path);
+        for file in files {
+            let tags = file.tags(&self.import, &tagger);
+            let title = tagger.title(file.title, &file.path);
+            let mut source = Source::new(file.filename, title);
+            source.set_storage(self.id.clone());
+            source.set_metadata(file.metadata);
+            source.set_id(file.id, file.size);
+
+            // Filename goes first, copies of a file have the same id.
+            let previous = by_filename
+                .get(&source.get_filename())
+                .or_else(|| by_id.get(&source.get_id()));
+            if let Some(&i) = previous {
+                found[i] = true;
+                source.set_hidden(old[i].is_hidden());
+                for tag in old[i].tags() {
+                    source.attach_tag(tag);
+                }
+                if let Some(features) = old[i].get_features() {
+                    source.set_features(features, old[i].get_suggestions());
+                }
+            }
+            for tag in tags {
+                source.attach_tag(self.tag_index(tag));
+            }
+            self.sources.push(source);
+        }
+
+        // Vanished sources are kept with their tags to relink them later.
+        for (mut source, found) in old.into_iter().zip(found) {
+            if !found {
+                source.set_vanished();
+                self.sources.push(source);
+            }
+        }
+    }
+
+    /// Index of tag with text. New tag is created if not exists.
+    fn tag_index(&mut self, text: String) -> usize {
+        let tag = Tag::new(text);
+        match self.tags.iter().position(|t| t.get_text() == tag.get_text()) {
+            Some(i) => i,
+            None => {
+                self.tags.push(tag);
+                self.tags.len() - 1
+            }
+        }
+    }
+
+    /// Rename tag and its children. Return the new text of the tag, None if
+    /// the name is not allowed.
+    pub fn rename_tag(&mut self, old_name: String, new_name: String) -> Option<String> {
+        self.invalidate_index();
         // Not allowed set empty name or existing name.
         if new_name.trim().is_empty() || self.tags.iter().any(|t| t.get_text() == new_name) {
-            return;
+            return None;
         }
 
-        for tag in &mut self.tags {
-            if tag.get_text() == old_name {
-                tag.set_text(new_name);
-                break;
+        let mut renamed = None;
+        let names: Vec<String> = self.tags.iter().map(|t| t.get_text()).collect();
+        for (tag, name) in self.tags.iter_mut().zip(&names) {
+            if *name == old_name {
+                tag.set_text(new_name.clone());
+                renamed = Some(tag.get_text());
+            } else if tag::is_tag_or_child(name, &old_name) {
+                // Move child to the renamed parent.
+                let text = format!("{}{}", new_name, &name[old_name.len()..]);
+                if !names.contains(&text) {
+                    tag.set_text(text);
+                }
             }
         }
+        renamed
     }
 
-    pub fn remove_tag(&mut self, tag_text: String) {
-        if let Some(tag_index) = self.tags.iter().position(|t| t.get_text() == tag_text) {
-            for source in &mut self.sources {
-                source.remove_tag_and_shift_indexes(tag_index);
+    /// Remove tag. Return its index, the tag and sources it was attached to.
+    pub fn remove_tag(&mut self, tag_text: String) -> Option<(usize, Tag, Vec<usize>)> {
+        self.invalidate_index();
+        let tag_index = self.tags.iter().position(|t| t.get_text() == tag_text)?;
+        let sources = self.sources_with_tag(&tag_text);
+        for source in &mut self.sources {
+            source.remove_tag_and_shift_indexes(tag_index);
+        }
+        Some((tag_index, self.tags.remove(tag_index), sources))
+    }
+
+    /// Insert tag at the index and attach it to the sources. False if the
+    /// tag exists.
+    pub fn insert_tag(&mut self, tag_index: usize, tag: Tag, source_indexes: &[usize]) -> bool {
+        if self.tags.iter().any(|t| t.get_text() == tag.get_text()) {
+            return false;
+        }
+        self.invalidate_index();
+        let tag_index = tag_index.min(self.tags.len());
+        for source in &mut self.sources {
+            source.insert_tag_and_shift_indexes(tag_index);
+        }
+        self.tags.insert(tag_index, tag);
+        for &index in source_indexes {
+            if let Some(source) = self.sources.get_mut(index) {
+                source.attach_tag(tag_index);
             }
         }
-        self.tags.retain(|t| t.get_text() != tag_text);
+        true
+    }
+
+    pub fn tag_count(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn find_tag(&self, text: &str) -> Option<Tag> {
+        self.tags.iter().find(|t| t.get_text() == text).cloned()
     }
 
-    pub fn add_tag(&mut self) {
-        self.tags.push(Tag::random());
+    /// Indexes of sources with the tag.
+    pub fn sources_with_tag(&self, text: &str) -> Vec<usize> {
+        let Some(tag_index) = self.tags.iter().position(|t| t.get_text() == text) else {
+            return vec![];
+        };
+        (0..self.sources.len())
+            .filter(|&i| self.sources[i].has_tag(tag_index))
+            .collect()
     }
 
     pub fn set_tag_color(&mut self, title: String, color: String) {
@@ -143,6 +1059,26 @@ impl Storage {
         }
     }
 
+    pub fn set_tag_category(&mut self, title: String, category: String) {
+        self.invalidate_index();
+        for tag in &mut self.tags {
+            if tag.get_text() == title {
+                tag.set_category(category);
+                break;
+            }
+        }
+    }
+
+    /// Chosen categories of tags and suggested ones, sorted.
+    pub fn tag_categories(&self) -> Vec<String> {
+        let mut categories: Vec<String> = tag::CATEGORIES.iter().map(|c| c.to_string()).collect();
+        categories.extend(self.tags.iter().map(|t| t.get_category()));
+        categories.retain(|c| !c.is_empty());
+        categories.sort();
+        categories.dedup();
+        categories
+    }
+
     pub fn reverse_colors(&mut self) {
         for tag in &mut self.tags {
             let color = tag.get_color();
@@ -151,21 +1087,37 @@ impl Storage {
         }
     }
 
-    pub fn find(&self, substr: String) -> Vec<usize> {
-        let pattern = substr.to_lowercase();
-        let mut matched = Vec::with_capacity(self.sources.len());
-        for i in 0..self.sources.len() {
-            let title = &self.sources[i].get_title();
-            if title.to_lowercase().contains(&pattern)
-                || self.sources[i]
-                    .tags()
-                    .iter()
-                    .any(|i| self.tags[*i].get_text().to_lowercase().contains(&pattern))
-            {
-                matched.push(i);
-            }
-        }
-        matched
+    /// Indexes of sources matched the query, see `storage::query`.
+    /// Sorted by relevance.
+    /// Returns pairs of source index and relevance.
+    pub fn find(&self, query: &Query) -> Vec<(usize, f32)> {
+        let mut index = self.index.borrow_mut();
+        let index = index.get_or_insert_with(|| SearchIndex::new(&self.sources, &self.tags));
+
+        // Narrow search by the most selective word.
+        let candidates = query
+            .required_words()
+            .into_iter()
+            .filter_map(|w| index.candidates(w))
+            .min_by_key(|c| c.len())
+            .unwrap_or_else(|| (0..index.len()).collect());
+
+        let mut found: Vec<(usize, f32)> = candidates
+            .into_iter()
+            .filter(|&i| !self.sources[i].is_hidden())
+            .filter_map(|i| query.score(index.entry(i)).map(|score| (i, score)))
+            .collect();
+        found.sort_by(|a, b| b.1.total_cmp(&a.1));
+        found
+    }
+
+    fn invalidate_index(&self) {
+        self.index.replace(None);
+        self.revision.set(self.revision.get().wrapping_add(1));
+    }
+
+    pub fn get_revision(&self) -> u64 {
+        self.revision.get()
     }
 
     pub fn get_tags(&self, index: usize) -> Vec<&Tag> {
@@ -193,9 +1145,292 @@ impl Storage {
     }
 }
 
+/// Sources of storages matched the query, sorted by relevance.
+/// Hidden or missing sources and wrong queries give nothing.
+pub fn find_sources(storages: &[Rc<RefCell<Storage>>], query: &str) -> Vec<Source> {
+    let Ok(query) = Query::parse(query) else {
+        return vec![];
+    };
+    let mut found = vec![];
+    for storage in storages {
+        let Ok(storage) = storage.try_borrow() else {
+            continue;
+        };
+        for (i, score) in storage.find(&query) {
+            if let Some(source) = storage.get(i).filter(|s| !s.is_missing()) {
+                found.push((source, score));
+            }
+        }
+    }
+    found.sort_by(|a, b| b.1.total_cmp(&a.1));
+    found.into_iter().map(|(source, _)| source).collect()
+}
+
+/// Waveform of source with the filename from storages.
+pub fn find_waveform(storages: &[Rc<RefCell<Storage>>], filename: &str) -> Option<Waveform> {
+    storages.iter().find_map(|storage| {
+        let storage = storage.try_borrow().ok()?;
+        let source = storage.sources.iter().find(|s| s.get_filename() == filename)?;
+        let waveform = storage.waveforms.get(&waveform_key(source))?;
+        (!waveform.is_empty()).then(|| waveform.clone())
+    })
+}
+
+/// Waveforms are kept by content id, so moved files keep them.
+fn waveform_key(source: &Source) -> String {
+    match source.get_id() {
+        id if id.is_empty() => source.get_filename(),
+        id => id,
+    }
+}
+
+fn default_disk_cache_limit() -> u64 {
+    2048
+}
+
+fn new_storage_id() -> String {
+    let bytes: [u8; 8] = rand::random();
+    hex::encode(bytes)
+}
+
+/// Extensions of indexed audio files.
+const MUSIC_EXTENSIONS: [&str; 12] = [
+    "mp3", "flac", "wav", "ogg", "oga", "opus", "m4a", "mp4", "aac", "aiff", "aif", "wv",
+];
+
+/// Waveforms generated between saves of the waveforms cache.
+const WAVEFORMS_PER_SAVE: usize = 20;
+
+/// Extension of the metadata cache directory next to the project file.
+const METADATA_EXTENSION: &str = "cache";
+
+/// Formats indexed by storage, but not supported by the decoder.
+const UNDECODABLE_EXTENSIONS: [(&str, &str); 2] = [("opus", "Opus"), ("wv", "WavPack")];
+
+/// Schemes of remote storage files. Zip entries are local files.
+const REMOTE_SCHEMES: [&str; 3] = ["http://", "https://", subsonic::SCHEME];
+
+/// Is source filename an address of remote storage file.
+pub fn is_remote(filename: &str) -> bool {
+    REMOTE_SCHEMES.iter().any(|scheme| {
+        filename
+            .get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// Directory of storage metadata and played files of remote storage
+/// in the user cache directory.
+pub fn cache_dir(storage_id: &str) -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("cyberbard")
+        .join(storage_id)
+}
+
+fn extension(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
 fn is_music_file(filename: &str) -> bool {
-    let filename = filename.to_lowercase();
-    [".mp3", ".flac", ".wav", ".ogg"]
+    MUSIC_EXTENSIONS.contains(&extension(filename).as_str())
+}
+
+/// Name of recognized audio format, which could not be played.
+pub fn undecodable_format(filename: &str) -> Option<&'static str> {
+    let extension = extension(filename);
+    UNDECODABLE_EXTENSIONS
         .iter()
-        .any(|x| filename.ends_with(x))
+        .find(|(e, _)| *e == extension)
+        .map(|(_, name)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::localstorage::LocalOpener, stream::Opener};
+
+    #[test]
+    fn music_files() {
+        for name in ["a.MP3", "b.flac", "c.opus", "d.m4a", "e.aiff", "f.wv", "/x.y/g.ogg"] {
+            assert!(is_music_file(name), "{name}");
+        }
+        for name in ["a.txt", "mp3", "b.yaml", "c.mp3.part"] {
+            assert!(!is_music_file(name), "{name}");
+        }
+
+        assert_eq!(undecodable_format("ambience.opus"), Some("Opus"));
+        assert_eq!(undecodable_format("rain.WV"), Some("WavPack"));
+        assert_eq!(undecodable_format("rain.m4a"), None);
+        // Indexed, but playing gives the error of the file.
+        let mut opener = LocalOpener::new("/music/ambience.opus".to_string(), 0.0);
+        let error = opener.source().err().unwrap().to_string();
+        assert_eq!(error, "Opus playback is not supported");
+
+        assert!(is_remote("HTTPS://host/music/rain.ogg"));
+        assert!(is_remote("subsonic://s1/rain.ogg"));
+        assert!(!is_remote("zip:///music/pack.zip!/rain.ogg"));
+        assert!(!is_remote("/music/rain://.ogg"));
+    }
+
+    #[test]
+    fn share_tags() {
+        let root = PathBuf::from("/music");
+        let scanned = |name: &str, id: &str| ScannedFile {
+            filename: root.join(name).to_string_lossy().to_string(),
+            title: name.to_string(),
+            metadata: Default::default(),
+            id: id.to_string(),
+            size: 1,
+            path: PathBuf::from(name),
+            storage_tags: vec![],
+        };
+
+        let mut first = Storage::new();
+        first.credentials = Some(StorageCredentials::Local(root.clone()));
+        first.merge_scanned(vec![scanned("Forest/owl.ogg", "a"), scanned("rain.ogg", "b")]);
+        first.attach_tag(0, "night".to_string());
+        first.set_tag_color("night".to_string(), "#102030".to_string());
+        let records = first.tag_records();
+        assert_eq!(records[0].path, "Forest/owl.ogg");
+        assert_eq!(records[0].tags, vec!["Forest", "night"]);
+
+        // The same files, one is moved.
+        let mut second = Storage::new();
+        second.credentials = Some(StorageCredentials::Local(root.clone()));
+        second.merge_scanned(vec![scanned("rain.ogg", ""), scanned("Birds/owl.ogg", "a")]);
+        second.attach_tag(0, "weather".to_string());
+        assert_eq!(second.apply_tag_records(&records, true), (2, 0));
+
+        let texts = |storage: &Storage, i: usize| -> Vec<String> {
+            storage.get_tags(i).iter().map(|t| t.get_text()).collect()
+        };
+        assert_eq!(texts(&second, 0), Vec::<String>::new());
+        assert_eq!(texts(&second, 1), vec!["Forest", "night"]);
+        let night = second.tags.iter().find(|t| t.get_text() == "night").unwrap();
+        assert_eq!(night.get_color(), "#102030");
+    }
+
+    #[test]
+    fn keep_vanished() {
+        let scanned = |name: &str| ScannedFile {
+            filename: format!("https://host/music/{name}"),
+            title: name.to_string(),
+            metadata: Default::default(),
+            id: name.to_string(),
+            size: 1,
+            path: PathBuf::from(name),
+            storage_tags: vec![],
+        };
+        let mut storage = Storage::new();
+        storage.merge_scanned(vec![scanned("owl.ogg"), scanned("rain.ogg")]);
+        storage.attach_tag(0, "night".to_string());
+        assert!(storage.missing().is_empty());
+
+        storage.merge_scanned(vec![scanned("rain.ogg")]);
+        storage.merge_scanned(vec![scanned("rain.ogg")]);
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.missing(), vec![1]);
+        assert_eq!(storage.sources_with_tag("night"), vec![1]);
+
+        storage.merge_scanned(vec![scanned("rain.ogg"), scanned("owl.ogg")]);
+        assert!(storage.missing().is_empty());
+        assert_eq!(storage.sources_with_tag("night"), vec![1]);
+    }
+
+    #[test]
+    fn metadata_cache_next_to_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let music = dir.path().join("music");
+        std::fs::create_dir(&music).unwrap();
+        std::fs::write(music.join("wind.ogg"), b"not really ogg").unwrap();
+        let project = dir.path().join("campaign.cyberbard");
+
+        let mut storage = Storage::new();
+        storage.set_project(Some(project.clone()));
+        storage.setup_storage(StorageCredentials::Local(music));
+        // Rescan replaces the running scanner.
+        storage.rescan();
+        while !storage.poll_scan() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(storage.len(), 1);
+        let cache = dir.path().join("campaign.cache").join(storage.get_id());
+        assert!(cache::MetadataCache::path(&cache).is_file());
+    }
+
+    #[test]
+    fn bulk_tags() {
+        let mut storage = Storage::new();
+        storage.sources = ["a", "b", "c"]
+            .map(|name| Source::new(name.to_string(), name.to_string()))
+            .to_vec();
+        storage.attach_tag_to(&[0, 2, 5], "battle".to_string());
+        let found = |storage: &Storage| -> Vec<usize> {
+            let query = Query::parse("tag:battle").unwrap();
+            let mut found: Vec<usize> = storage.find(&query).iter().map(|f| f.0).collect();
+            found.sort();
+            found
+        };
+        assert_eq!(found(&storage), vec![0, 2]);
+
+        storage.unattach_tag_from(&[0, 1], "battle".to_string());
+        assert_eq!(found(&storage), vec![2]);
+        let records = storage.tag_records_of(&[2, 1]);
+        assert_eq!(records[0].tags, vec!["battle"]);
+        assert!(records[1].tags.is_empty());
+    }
+
+    #[test]
+    fn found_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let scanned = |name: &str| ScannedFile {
+            filename: dir.path().join(name).to_string_lossy().to_string(),
+            title: name.to_string(),
+            metadata: Default::default(),
+            id: name.to_string(),
+            size: 1,
+            path: PathBuf::from(name),
+            storage_tags: vec![],
+        };
+        for folder in ["Tavern", "Forest"] {
+            std::fs::create_dir(dir.path().join(folder)).unwrap();
+        }
+        for name in ["Tavern/lute.ogg", "Tavern/song.ogg", "Forest/owl.ogg"] {
+            std::fs::write(dir.path().join(name), b"not really ogg").unwrap();
+        }
+        let storage = Rc::new(RefCell::new(Storage::new()));
+        storage.borrow_mut().merge_scanned(vec![
+            scanned("Tavern/lute.ogg"),
+            scanned("Tavern/song.ogg"),
+            scanned("Forest/owl.ogg"),
+        ]);
+        storage.borrow_mut().attach_tag(1, "vocals".to_string());
+        let revision = storage.borrow().get_revision();
+
+        assert!(find_sources(&[], "tag:tavern").is_empty());
+        let storages = [storage];
+        let titles = |query: &str| -> Vec<String> {
+            find_sources(&storages, query)
+                .iter()
+                .map(|s| s.get_title())
+                .collect()
+        };
+        assert_eq!(titles("tag:tavern -tag:vocals"), vec!["Tavern/lute.ogg"]);
+        assert!(titles("tag:(").is_empty());
+
+        storages[0]
+            .borrow_mut()
+            .attach_tag(1, "instrumental".to_string());
+        assert_ne!(storages[0].borrow().get_revision(), revision);
+
+        // Vanished files are not played.
+        storages[0]
+            .borrow_mut()
+            .merge_scanned(vec![scanned("Tavern/song.ogg"), scanned("Forest/owl.ogg")]);
+        assert_eq!(titles("tag:tavern"), vec!["Tavern/song.ogg"]);
+    }
 }
@@ -0,0 +1,205 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Rules to make tags from paths of imported files.
+
+use std::path::Path;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{localstorage::default_title, tag::SEPARATOR};
+
+/// Folders of file path to make tags from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum FolderTags {
+    None,
+    /// Nearest folder.
+    #[default]
+    Parent,
+    /// Every folder below the storage root.
+    All,
+    /// One hierarchical tag like `Fantasy/Combat/Boss`.
+    Nested,
+}
+
+/// Regex replacement of path tags, like `^\d+ - ` to nothing.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Rewrite {
+    pub pattern: String,
+    pub replace: String,
+}
+
+impl Rewrite {
+    pub fn regex(&self) -> Result<Regex, String> {
+        Regex::new(&self.pattern).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PathRules {
+    pub folders: FolderTags,
+    /// Only folders up to this depth below the storage root, 0 for any depth.
+    pub max_depth: usize,
+    /// Folder names that never become tags, case insensitive.
+    pub ignored: Vec<String>,
+    /// Applied in order to every path tag.
+    pub rewrites: Vec<Rewrite>,
+    /// Filenames like `[tag1][tag2] title.ogg` give tags.
+    pub filename_tags: bool,
+}
+
+/// Compiled path rules. Rewrites with wrong patterns are skipped.
+pub struct PathTagger {
+    rules: PathRules,
+    rewrites: Vec<(Regex, String)>,
+}
+
+impl PathTagger {
+    pub fn new(rules: &PathRules) -> PathTagger {
+        PathTagger {
+            rules: rules.clone(),
+            rewrites: rules
+                .rewrites
+                .iter()
+                .filter_map(|r| r.regex().ok().map(|regex| (regex, r.replace.clone())))
+                .collect(),
+        }
+    }
+
+    /// Tags of file path relative to the storage root.
+    pub fn tags(&self, path: &Path) -> Vec<String> {
+        let folders: Vec<String> = path
+            .parent()
+            .map(|p| {
+                p.components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let folders: Vec<String> = folders
+            .into_iter()
+            .take(match self.rules.max_depth {
+                0 => usize::MAX,
+                depth => depth,
+            })
+            .filter(|f| {
+                !self
+                    .rules
+                    .ignored
+                    .iter()
+                    .any(|i| i.trim().eq_ignore_ascii_case(f))
+            })
+            .filter_map(|f| self.rewrite(&f))
+            .collect();
+
+        let mut tags = match self.rules.folders {
+            FolderTags::None => vec![],
+            FolderTags::Parent => folders.last().cloned().into_iter().collect(),
+            FolderTags::All => folders,
+            FolderTags::Nested if folders.is_empty() => vec![],
+            FolderTags::Nested => vec![folders.join(&SEPARATOR.to_string())],
+        };
+
+        if self.rules.filename_tags {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let (filename_tags, _) = split_filename_tags(&stem);
+            tags.extend(filename_tags.iter().filter_map(|t| self.rewrite(t)));
+        }
+        tags
+    }
+
+    /// Title without tags of filename.
+    pub fn title(&self, title: String, path: &Path) -> String {
+        if !self.rules.filename_tags || title != default_title(path) {
+            return title;
+        }
+        match split_filename_tags(&title) {
+            (tags, rest) if !tags.is_empty() && !rest.is_empty() => rest.to_string(),
+            _ => title,
+        }
+    }
+
+    fn rewrite(&self, tag: &str) -> Option<String> {
+        let mut tag = tag.to_string();
+        for (regex, replace) in &self.rewrites {
+            tag = regex.replace_all(&tag, replace.as_str()).to_string();
+        }
+        let tag = tag.trim();
+        (!tag.is_empty()).then(|| tag.to_string())
+    }
+}
+
+/// Tags in brackets at the filename start and the rest of the name.
+fn split_filename_tags(stem: &str) -> (Vec<String>, &str) {
+    let mut tags = vec![];
+    let mut rest = stem.trim_start();
+    while let Some(tail) = rest.strip_prefix('[') {
+        let Some((tag, tail)) = tail.split_once(']') else {
+            break;
+        };
+        if !tag.trim().is_empty() {
+            tags.push(tag.trim().to_string());
+        }
+        rest = tail.trim_start();
+    }
+    (tags, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_rules() {
+        let path = Path::new("Fantasy/01 - Combat/CD1/Boss/[epic][ Drums ] Final fight.ogg");
+        let mut rules = PathRules::default();
+        assert_eq!(PathTagger::new(&rules).tags(path), vec!["Boss"]);
+
+        rules.folders = FolderTags::All;
+        rules.ignored = vec!["cd1".to_string()];
+        rules.rewrites = vec![
+            Rewrite {
+                pattern: r"^\d+\s*-\s*".to_string(),
+                replace: String::new(),
+            },
+            Rewrite {
+                pattern: "(".to_string(),
+                replace: String::new(),
+            },
+        ];
+        rules.filename_tags = true;
+        let tagger = PathTagger::new(&rules);
+        assert_eq!(
+            tagger.tags(path),
+            vec!["Fantasy", "Combat", "Boss", "epic", "Drums"]
+        );
+        assert_eq!(
+            tagger.title("[epic][ Drums ] Final fight".to_string(), path),
+            "Final fight"
+        );
+        assert_eq!(
+            tagger.title("From metadata".to_string(), path),
+            "From metadata"
+        );
+
+        rules.folders = FolderTags::Nested;
+        rules.max_depth = 2;
+        rules.filename_tags = false;
+        assert_eq!(PathTagger::new(&rules).tags(path), vec!["Fantasy/Combat"]);
+    }
+}
@@ -0,0 +1,142 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Portable paths of saved projects: sources are saved relative to the
+//! storage root, storage roots and backgrounds relative to the project
+//! file. Relative paths use `/` separators on every system.
+
+use std::path::{Component, Path, PathBuf};
+
+/// Path relative to base with `..` if needed. None if any path is relative
+/// or they are on different disks.
+pub fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() || !base.is_absolute() {
+        return None;
+    }
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    if path.first() != base.first() {
+        return None;
+    }
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<String> = vec!["..".to_string(); base.len() - common];
+    parts.extend(
+        path[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    );
+    if parts.is_empty() {
+        return Some(PathBuf::from("."));
+    }
+    Some(PathBuf::from(parts.join("/")))
+}
+
+/// Relative path in the directory, `..` are resolved without file system
+/// access.
+pub fn join(dir: &Path, relative: &Path) -> PathBuf {
+    let mut path = dir.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => (),
+            component => path.push(component),
+        }
+    }
+    path
+}
+
+/// Filename relative to the root, other filenames are kept.
+pub fn portable_filename(filename: &str, root: &Path) -> String {
+    match Path::new(filename).strip_prefix(root) {
+        Ok(relative) if root.is_absolute() => relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        _ => filename.to_string(),
+    }
+}
+
+/// Absolute filename of relative one, other filenames are kept.
+pub fn absolute_filename(filename: &str, root: &Path) -> String {
+    if is_relative(filename) {
+        join(root, Path::new(filename))
+            .to_string_lossy()
+            .to_string()
+    } else {
+        filename.to_string()
+    }
+}
+
+/// Filename of the old root moved to the new one.
+pub fn remap_filename(filename: &str, old_root: &Path, new_root: &Path) -> String {
+    absolute_filename(&portable_filename(filename, old_root), new_root)
+}
+
+/// Relative path, not an URL.
+fn is_relative(filename: &str) -> bool {
+    !filename.is_empty() && !filename.contains("://") && Path::new(filename).is_relative()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Storage, StorageCredentials, source::Source};
+
+    #[test]
+    fn relative_paths() {
+        let project = Path::new("/home/gm/Campaign");
+        let music = Path::new("/home/gm/Music/Ambient");
+        assert_eq!(
+            relative_to(music, project),
+            Some(PathBuf::from("../Music/Ambient"))
+        );
+        assert_eq!(relative_to(project, project), Some(PathBuf::from(".")));
+        assert_eq!(join(project, Path::new("../Music/Ambient")), music);
+        assert_eq!(relative_to(Path::new("Music"), project), None);
+
+        let filename = "/home/gm/Music/Ambient/Forest/owl.ogg";
+        let portable = portable_filename(filename, music);
+        assert_eq!(portable, "Forest/owl.ogg");
+        assert_eq!(absolute_filename(&portable, music), filename);
+        assert_eq!(portable_filename("/other/owl.ogg", music), "/other/owl.ogg");
+        assert_eq!(
+            absolute_filename("https://dav/owl.ogg", music),
+            "https://dav/owl.ogg"
+        );
+        assert_eq!(
+            remap_filename(filename, music, Path::new("/mnt/usb/Ambient")),
+            "/mnt/usb/Ambient/Forest/owl.ogg"
+        );
+
+        let mut storage = Storage::new();
+        storage.credentials = Some(StorageCredentials::Local(music.to_path_buf()));
+        storage
+            .sources
+            .push(Source::new(filename.to_string(), "owl".to_string()));
+        storage.make_portable(project);
+        assert_eq!(storage.sources[0].get_filename(), "Forest/owl.ogg");
+        assert!(matches!(
+            &storage.credentials,
+            Some(StorageCredentials::Local(root)) if root == Path::new("../Music/Ambient")
+        ));
+        storage.resolve_paths(project);
+        assert_eq!(storage.sources[0].get_filename(), filename);
+        assert_eq!(storage.get_missing_root(), Some(music.to_path_buf()));
+    }
+}
@@ -0,0 +1,559 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+//! Storage search query language.
+//!
+//! Words are matched as substrings of title, tags and metadata.
+//! Also supported:
+//! - `"exact title"` — whole title, case insensitive;
+//! - `tag:forest`, `tag:"dark forest"` — tag with exactly this text,
+//!   `tag:location` also matches its children like `location/forest`;
+//! - `cat:mood` — tag of the category;
+//! - `title:`, `artist:`, `album:`, `genre:`, `comment:` — substring of the field;
+//! - `dur:>120`, `dur:<=60`, `dur:90` — duration in seconds;
+//! - `AND` (or just space), `OR`, `NOT` (or `-` attached to the term, like
+//!   `-word`) and parentheses. Detached `-` is a word, e.g. "01 - Intro".
+
+use std::{fmt, ops::Range};
+
+use crate::storage::{index::Entry, tag::is_tag_or_child};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub message: String,
+    /// Byte range of the query string with the error.
+    pub span: Range<usize>,
+}
+
+impl QueryError {
+    fn new(message: impl Into<String>, span: Range<usize>) -> QueryError {
+        QueryError {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Matches every source.
+    All,
+    Word(String),
+    ExactTitle(String),
+    Tag(String),
+    Category(String),
+    Field(Field, String),
+    Duration(Compare, f32),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: text.len(),
+        };
+        if parser.tokens.is_empty() {
+            return Ok(Query::All);
+        }
+        let query = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(QueryError::new("unexpected ')'", token.span.clone())),
+        }
+    }
+
+    /// Relevance of matched source, None if the source is not matched.
+    /// Words are matched fuzzy, see `Entry::word_score`.
+    pub fn score(&self, entry: &Entry) -> Option<f32> {
+        match self {
+            Query::All => Some(0.0),
+            Query::Word(word) => entry.word_score(word),
+            Query::ExactTitle(title) => (entry.title == *title).then_some(4.0),
+            Query::Tag(text) => entry
+                .tags
+                .iter()
+                .any(|t| is_tag_or_child(t, text))
+                .then_some(2.0),
+            Query::Category(category) => entry.categories.contains(category).then_some(1.5),
+            Query::Field(field, text) => {
+                let value = match field {
+                    Field::Title => &entry.title,
+                    Field::Artist => &entry.artist,
+                    Field::Album => &entry.album,
+                    Field::Genre => &entry.genre,
+                    Field::Comment => &entry.comment,
+                };
+                value.contains(text).then_some(1.0)
+            }
+            Query::Duration(compare, seconds) => {
+                let duration = entry.duration;
+                let matched = match compare {
+                    Compare::Less => duration < *seconds,
+                    Compare::LessOrEqual => duration <= *seconds,
+                    Compare::Equal => (duration - seconds).abs() < 1.0,
+                    Compare::GreaterOrEqual => duration >= *seconds,
+                    Compare::Greater => duration > *seconds,
+                };
+                matched.then_some(0.0)
+            }
+            Query::Not(query) => match query.score(entry) {
+                Some(_) => None,
+                None => Some(0.0),
+            },
+            Query::And(a, b) => Some(a.score(entry)? + b.score(entry)?),
+            Query::Or(a, b) => match (a.score(entry), b.score(entry)) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+
+    /// Words every matched source must contain. Used to narrow search by index.
+    pub fn required_words(&self) -> Vec<&str> {
+        match self {
+            Query::Word(word) => vec![word.as_str()],
+            Query::And(a, b) => {
+                let mut words = a.required_words();
+                words.extend(b.required_words());
+                words
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    Minus,
+    And,
+    Or,
+    Not,
+    Word(String),
+    Quoted(String),
+    /// `field:value`, value span is used for errors.
+    Field(String, String, Range<usize>),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    span: Range<usize>,
+}
+
+/// Search fields, other words with ':' are searched as is, e.g. "Act 1:".
+const FIELDS: [&str; 8] = [
+    "tag", "cat", "title", "artist", "album", "genre", "comment", "dur",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(start, ch)) = chars.peek() {
+        match ch {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                let kind = if ch == '(' {
+                    TokenKind::Open
+                } else {
+                    TokenKind::Close
+                };
+                tokens.push(Token {
+                    kind,
+                    span: start..start + 1,
+                });
+            }
+            '-' if text[start + 1..].starts_with(|c: char| !c.is_whitespace() && c != ')') => {
+                chars.next();
+                tokens.push(Token {
+                    kind: TokenKind::Minus,
+                    span: start..start + 1,
+                });
+            }
+            '"' => {
+                let (value, end) = read_quoted(text, &mut chars)?;
+                tokens.push(Token {
+                    kind: TokenKind::Quoted(value),
+                    span: start..end,
+                });
+            }
+            _ => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let word = &text[start..end];
+
+                let field = word
+                    .split_once(':')
+                    .filter(|(field, _)| FIELDS.contains(&field.to_lowercase().as_str()));
+                let kind = if let Some((field, value)) = field {
+                    if value.is_empty() && chars.peek().is_some_and(|&(_, c)| c == '"') {
+                        let value_start = end;
+                        let (value, value_end) = read_quoted(text, &mut chars)?;
+                        end = value_end;
+                        TokenKind::Field(field.to_lowercase(), value, value_start..value_end)
+                    } else {
+                        TokenKind::Field(
+                            field.to_lowercase(),
+                            value.to_string(),
+                            start + field.len() + 1..end,
+                        )
+                    }
+                } else {
+                    match word {
+                        "AND" => TokenKind::And,
+                        "OR" => TokenKind::Or,
+                        "NOT" => TokenKind::Not,
+                        _ => TokenKind::Word(word.to_string()),
+                    }
+                };
+                tokens.push(Token {
+                    kind,
+                    span: start..end,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read string in quotes. Return the string and end position.
+fn read_quoted(
+    text: &str,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+) -> Result<(String, usize), QueryError> {
+    let (start, _) = chars.next().unwrap();
+    for (i, c) in chars.by_ref() {
+        if c == '"' {
+            return Ok((text[start + 1..i].to_string(), i + 1));
+        }
+    }
+    Err(QueryError::new("unclosed quote", start..text.len()))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.parse_and()?;
+        while self.peek().is_some_and(|t| t.kind == TokenKind::Or) {
+            self.next();
+            let right = self.parse_and()?;
+            query = Query::Or(Box::new(query), Box::new(right));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.parse_unary()?;
+        loop {
+            match self.peek().map(|t| &t.kind) {
+                None | Some(TokenKind::Or) | Some(TokenKind::Close) => break,
+                Some(TokenKind::And) => {
+                    self.next();
+                }
+                _ => (),
+            }
+            let right = self.parse_unary()?;
+            query = Query::And(Box::new(query), Box::new(right));
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, QueryError> {
+        match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Not) | Some(TokenKind::Minus) => {
+                self.next();
+                Ok(Query::Not(Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, QueryError> {
+        let Some(token) = self.next() else {
+            return Err(QueryError::new("expected search term", self.end..self.end));
+        };
+
+        match token.kind {
+            TokenKind::Open => {
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::Close,
+                        ..
+                    }) => Ok(query),
+                    _ => Err(QueryError::new("unclosed '('", token.span)),
+                }
+            }
+            TokenKind::Word(word) => Ok(Query::Word(word.to_lowercase())),
+            TokenKind::Quoted(title) => Ok(Query::ExactTitle(title.to_lowercase())),
+            TokenKind::Field(field, value, value_span) => {
+                parse_field(&field, &value, token.span, value_span)
+            }
+            TokenKind::Close
+            | TokenKind::And
+            | TokenKind::Or
+            | TokenKind::Minus
+            | TokenKind::Not => Err(QueryError::new("expected search term", token.span)),
+        }
+    }
+}
+
+fn parse_field(
+    field: &str,
+    value: &str,
+    span: Range<usize>,
+    value_span: Range<usize>,
+) -> Result<Query, QueryError> {
+    if value.is_empty() && field != "dur" {
+        return Err(QueryError::new("expected value", span));
+    }
+    let value_lower = value.to_lowercase();
+    match field {
+        "tag" => Ok(Query::Tag(value_lower)),
+        "cat" => Ok(Query::Category(value_lower)),
+        "title" => Ok(Query::Field(Field::Title, value_lower)),
+        "artist" => Ok(Query::Field(Field::Artist, value_lower)),
+        "album" => Ok(Query::Field(Field::Album, value_lower)),
+        "genre" => Ok(Query::Field(Field::Genre, value_lower)),
+        "comment" => Ok(Query::Field(Field::Comment, value_lower)),
+        "dur" => {
+            let (compare, number) = if let Some(n) = value.strip_prefix(">=") {
+                (Compare::GreaterOrEqual, n)
+            } else if let Some(n) = value.strip_prefix("<=") {
+                (Compare::LessOrEqual, n)
+            } else if let Some(n) = value.strip_prefix('>') {
+                (Compare::Greater, n)
+            } else if let Some(n) = value.strip_prefix('<') {
+                (Compare::Less, n)
+            } else {
+                (Compare::Equal, value.strip_prefix('=').unwrap_or(value))
+            };
+            match number.parse::<f32>() {
+                Ok(seconds) if seconds >= 0.0 => Ok(Query::Duration(compare, seconds)),
+                _ => Err(QueryError::new("expected duration in seconds", value_span)),
+            }
+        }
+        _ => Err(QueryError::new(
+            format!("unknown field '{field}'"),
+            span.start..span.start + field.len(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{metadata::Metadata, source::Source, tag::Tag};
+
+    fn storage() -> (Vec<Source>, Vec<Tag>) {
+        let tags = vec![
+            Tag::new("forest".to_string()),
+            Tag::new("forest-fire".to_string()),
+            Tag::new("night".to_string()),
+            Tag::new("combat".to_string()),
+        ];
+
+        let mut sources = vec![];
+        for (title, source_tags, duration) in [
+            ("Quiet woods", vec![0, 2], 200.0),
+            ("Burning trees", vec![1], 90.0),
+            ("Night ambush", vec![0, 2, 3], 150.0),
+            ("Tavern", vec![], 60.0),
+        ] {
+            let mut source = Source::new(format!("{title}.ogg"), title.to_string());
+            for tag in source_tags {
+                source.attach_tag(tag);
+            }
+            source.set_metadata(Metadata {
+                duration,
+                artist: "Bard".to_string(),
+                ..Default::default()
+            });
+            sources.push(source);
+        }
+        (sources, tags)
+    }
+
+    fn find(text: &str) -> Vec<String> {
+        let (sources, tags) = storage();
+        let query = Query::parse(text).unwrap();
+        sources
+            .iter()
+            .filter(|s| query.score(&Entry::new(s, &tags)).is_some())
+            .map(|s| s.get_title())
+            .collect()
+    }
+
+    #[test]
+    fn words_and_tags() {
+        assert_eq!(find("").len(), 4);
+        assert_eq!(
+            find("forest"),
+            vec!["Quiet woods", "Burning trees", "Night ambush"]
+        );
+        assert_eq!(find("tag:forest"), vec!["Quiet woods", "Night ambush"]);
+        assert_eq!(find("tag:FOREST -tag:combat"), vec!["Quiet woods"]);
+        assert_eq!(find("\"tavern\""), vec!["Tavern"]);
+        assert!(find("\"tav\"").is_empty());
+        assert_eq!(find("artist:bard").len(), 4);
+        assert_eq!(find("Night: ambush"), vec!["Night ambush"]);
+        assert_eq!(find("forest-fire"), vec!["Burning trees"]);
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(find("tag:night tag:combat"), vec!["Night ambush"]);
+        assert_eq!(find("tag:night AND tag:combat"), vec!["Night ambush"]);
+        assert_eq!(find("tavern OR burning"), vec!["Burning trees", "Tavern"]);
+        assert_eq!(find("NOT (tag:forest OR tag:forest-fire)"), vec!["Tavern"]);
+        assert_eq!(
+            find("(tavern OR tag:night) -ambush"),
+            vec!["Quiet woods", "Tavern"]
+        );
+        assert_eq!(
+            find("tag:forest tag:night -tag:combat dur:>120"),
+            vec!["Quiet woods"]
+        );
+    }
+
+    #[test]
+    fn hierarchical_tags() {
+        let mut tags = vec![
+            Tag::new("location/forest/night".to_string()),
+            Tag::new("location/forest-fire".to_string()),
+            Tag::new("calm".to_string()),
+        ];
+        tags[2].set_category("mood".to_string());
+        let mut source = Source::new("owls.ogg".to_string(), "Owls".to_string());
+        source.attach_tag(0);
+        source.attach_tag(2);
+        let entry = Entry::new(&source, &tags);
+
+        let matched = |text: &str| Query::parse(text).unwrap().score(&entry).is_some();
+        assert!(matched("tag:location"));
+        assert!(matched("tag:Location/Forest"));
+        assert!(matched("tag:location/forest/night"));
+        assert!(!matched("tag:location/forest-fire"));
+        assert!(!matched("tag:forest"));
+        assert!(matched("cat:mood cat:location"));
+        assert!(!matched("cat:intensity"));
+    }
+
+    #[test]
+    fn detached_minus() {
+        let source = Source::new("intro.ogg".to_string(), "01 - Intro".to_string());
+        let entry = Entry::new(&source, &[]);
+
+        let matched = |text: &str| Query::parse(text).unwrap().score(&entry).is_some();
+        assert!(matched("01 - Intro"));
+        assert!(matched("intro -"));
+        assert!(!matched("01 -intro"));
+        assert_eq!(
+            Query::parse("- intro").unwrap(),
+            Query::And(
+                Box::new(Query::Word("-".to_string())),
+                Box::new(Query::Word("intro".to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(find("dur:>120"), vec!["Quiet woods", "Night ambush"]);
+        assert_eq!(find("dur:<=90"), vec!["Burning trees", "Tavern"]);
+        assert_eq!(find("dur:60"), vec!["Tavern"]);
+    }
+
+    #[test]
+    fn errors() {
+        let err = Query::parse("tag:forest \"unclosed").unwrap_err();
+        assert_eq!(err.span, 11..20);
+
+        let err = Query::parse("(forest OR night").unwrap_err();
+        assert_eq!(err.span, 0..1);
+
+        let err = Query::parse("forest)").unwrap_err();
+        assert_eq!(err.span, 6..7);
+
+        assert_eq!(
+            Query::parse("mood:calm").unwrap(),
+            Query::Word("mood:calm".to_string())
+        );
+
+        let err = Query::parse("dur:>long").unwrap_err();
+        assert_eq!(err.span, 4..9);
+
+        let err = Query::parse("forest OR").unwrap_err();
+        assert_eq!(err.span, 9..9);
+    }
+}
@@ -0,0 +1,211 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+use zip::{ZipArchive, result::ZipError};
+
+use crate::storage::{
+    fingerprint::fingerprint,
+    is_music_file,
+    source::Source,
+    zipstorage::{self, entry_filename},
+};
+
+struct Candidate {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    id: Option<String>,
+}
+
+/// Search moved or renamed files in a directory.
+/// Matches sources by fingerprint, then by file name and size. Sources of
+/// old projects without fingerprint and size are matched by a unique name.
+pub struct Relinker {
+    candidates: Vec<Candidate>,
+}
+
+impl Relinker {
+    pub fn scan(dir: &Path) -> Relinker {
+        let mut candidates = vec![];
+        for entry in WalkDir::new(dir).into_iter().flatten() {
+            let path = entry.path();
+            if !path.is_file() || !is_music_file(&path.to_string_lossy()) {
+                continue;
+            }
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            candidates.push(Candidate {
+                name: file_name(path),
+                path: path.to_path_buf(),
+                size,
+                id: None,
+            });
+        }
+        Relinker { candidates }
+    }
+
+    /// Search files in the folder of zip archive, e.g. audio embedded to
+    /// the project file. Found paths are filenames of archive entries.
+    pub fn scan_archive(archive: &Path, folder: &str) -> Relinker {
+        let mut candidates = vec![];
+        let zip = File::open(archive)
+            .map_err(ZipError::from)
+            .and_then(ZipArchive::new);
+        let mut zip = match zip {
+            Ok(zip) => zip,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", archive.display(), e);
+                return Relinker { candidates };
+            }
+        };
+        for i in 0..zip.len() {
+            let Ok(entry) = zip.by_index_raw(i) else {
+                continue;
+            };
+            let in_folder = entry.enclosed_name().is_some_and(|p| p.starts_with(folder));
+            if !entry.is_file() || !in_folder || !is_music_file(entry.name()) {
+                continue;
+            }
+            let filename = entry_filename(archive, entry.name());
+            candidates.push(Candidate {
+                name: file_name(Path::new(entry.name())),
+                path: PathBuf::from(filename),
+                size: entry.size(),
+                id: None,
+            });
+        }
+        Relinker { candidates }
+    }
+
+    /// Return new location of the source file if found.
+    pub fn find(&mut self, source: &Source) -> Option<PathBuf> {
+        let id = source.get_id();
+        if !id.is_empty() {
+            // Fingerprint includes file size, so hash only same sized files.
+            let size = source.get_size();
+            for candidate in self.candidates.iter_mut() {
+                if size != 0 && candidate.size != size {
+                    continue;
+                }
+                if candidate.id.is_none() {
+                    let filename = candidate.path.to_string_lossy();
+                    candidate.id = match zipstorage::split_filename(&filename) {
+                        Some(_) => zipstorage::fingerprint_entry(&filename),
+                        None => fingerprint(&candidate.path),
+                    }
+                    .ok();
+                }
+                if candidate.id.as_deref() == Some(id.as_str()) {
+                    return Some(candidate.path.clone());
+                }
+            }
+        }
+
+        let name = file_name(Path::new(&source.get_filename()));
+        let size = source.get_size();
+        if size == 0 {
+            // Other file of the same name may be found by mistake.
+            let mut named = self.candidates.iter().filter(|c| c.name == name);
+            return match (named.next(), named.next()) {
+                (Some(candidate), None) => Some(candidate.path.clone()),
+                _ => None,
+            };
+        }
+        self.candidates
+            .iter()
+            .find(|c| c.name == name && c.size == size)
+            .map(|c| c.path.clone())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn source(path: &Path) -> Source {
+        let mut source = Source::new(path.to_string_lossy().to_string(), "rain".to_string());
+        let size = fs::metadata(path).unwrap().len();
+        source.set_id(fingerprint(path).unwrap(), size);
+        source
+    }
+
+    #[test]
+    fn relink_by_id_or_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old");
+        let new = dir.path().join("new");
+        fs::create_dir_all(old.join("forest")).unwrap();
+        fs::create_dir_all(new.join("other")).unwrap();
+        fs::write(old.join("rain.ogg"), b"rain on the roof").unwrap();
+        fs::write(old.join("forest/wind.ogg"), b"wind").unwrap();
+        let rain = source(&old.join("rain.ogg"));
+        let wind = source(&old.join("forest/wind.ogg"));
+        let mut unknown = Source::new(
+            old.join("forest/birds.ogg").to_string_lossy().to_string(),
+            "birds".to_string(),
+        );
+        fs::rename(old.join("rain.ogg"), new.join("storm.ogg")).unwrap();
+        // Same name and other size is another file.
+        fs::write(new.join("wind.ogg"), b"strong wind").unwrap();
+        fs::write(new.join("other/birds.ogg"), b"birds").unwrap();
+        fs::write(new.join("birds.ogg"), b"other birds").unwrap();
+        fs::remove_dir_all(&old).unwrap();
+
+        let mut relinker = Relinker::scan(&new);
+        assert_eq!(relinker.find(&rain), Some(new.join("storm.ogg")));
+        assert_eq!(relinker.find(&wind), None);
+        assert_eq!(relinker.find(&unknown), None);
+
+        fs::write(new.join("other/wind.ogg"), b"wind").unwrap();
+        unknown.set_id(String::new(), 5);
+        let mut relinker = Relinker::scan(&new);
+        assert_eq!(relinker.find(&wind), Some(new.join("other/wind.ogg")));
+        assert_eq!(relinker.find(&unknown), Some(new.join("other/birds.ogg")));
+    }
+
+    #[test]
+    fn relink_old_source_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("forest")).unwrap();
+        fs::write(dir.path().join("forest/Wind.ogg"), b"wind").unwrap();
+        fs::write(dir.path().join("rain.ogg"), b"rain").unwrap();
+        fs::write(dir.path().join("forest/rain.ogg"), b"forest rain").unwrap();
+        // Sources of old projects have no id and size.
+        let wind = Source::new("/old/music/wind.ogg".to_string(), "wind".to_string());
+        let rain = Source::new("/old/music/rain.ogg".to_string(), "rain".to_string());
+
+        let mut relinker = Relinker::scan(dir.path());
+        assert_eq!(
+            relinker.find(&wind),
+            Some(dir.path().join("forest/Wind.ogg"))
+        );
+        // Several files of the name, the right one is not known.
+        assert_eq!(relinker.find(&rain), None);
+    }
+}
@@ -0,0 +1,126 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::storage::{
+    localstorage::{ScannedFile, scan_local_files},
+    subsonic::SubsonicClient,
+    webdav::WebDavClient,
+    zipstorage::scan_zip_files,
+};
+
+/// Error of scanning stopped by `Scanner::stop`.
+pub const CANCELLED: &str = "Scanning is cancelled";
+
+enum ScanMessage {
+    Progress(usize, usize),
+    Finished(Result<Vec<ScannedFile>, String>),
+}
+
+/// Background storage scanning.
+pub struct Scanner {
+    rx: Receiver<ScanMessage>,
+    progress: (usize, usize),
+    cancelled: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scanner {
+    pub fn local(root: PathBuf, cache_root: PathBuf) -> Scanner {
+        Scanner::spawn(move |progress| scan_local_files(&root, &cache_root, progress))
+    }
+
+    pub fn webdav(client: WebDavClient, cache_root: PathBuf) -> Scanner {
+        Scanner::spawn(move |progress| client.scan(&cache_root, progress))
+    }
+
+    pub fn subsonic(client: SubsonicClient) -> Scanner {
+        Scanner::spawn(move |progress| client.scan(progress))
+    }
+
+    pub fn zip(archives: Vec<PathBuf>, cache_root: PathBuf) -> Scanner {
+        Scanner::spawn(move |progress| scan_zip_files(&archives, &cache_root, progress))
+    }
+
+    fn spawn<F>(scan: F) -> Scanner
+    where
+        F: FnOnce(&dyn Fn(usize, usize) -> bool) -> Result<Vec<ScannedFile>, String>
+            + Send
+            + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let is_cancelled = Arc::clone(&cancelled);
+        let thread = thread::spawn(move || {
+            let progress_tx = tx.clone();
+            let files = scan(&|done, total| {
+                let _ = progress_tx.send(ScanMessage::Progress(done, total));
+                !is_cancelled.load(Ordering::Relaxed)
+            });
+            let _ = tx.send(ScanMessage::Finished(files));
+        });
+        Scanner {
+            rx,
+            progress: (0, 0),
+            cancelled,
+            thread: Some(thread),
+        }
+    }
+
+    /// Cancel scanning and wait for the scanning thread, so it does not
+    /// write the metadata cache after a new scanner.
+    pub fn stop(mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Count of scanned and total files.
+    pub fn progress(&self) -> (usize, usize) {
+        self.progress
+    }
+
+    /// Handle scanner messages. Return files when scanning is finished.
+    pub fn poll(&mut self) -> Option<Result<Vec<ScannedFile>, String>> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(ScanMessage::Progress(done, total)) => self.progress = (done, total),
+                Ok(ScanMessage::Finished(files)) => return Some(files),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    return Some(Err("Storage scanning thread is stopped".to_string()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Scanner {
+    /// Dropped scanner is cancelled without waiting, e.g. of removed storage.
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
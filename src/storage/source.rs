@@ -14,28 +14,180 @@
 //   You should have received a copy of the GNU General Public License
 //   along with this program.  If not, see <https://www.gnu.org/licenses/>
 
-use crate::{storage::localstorage::LocalOpener, stream::Stream};
+use std::path::Path;
+
+use crate::{
+    storage::{
+        analysis::Features,
+        is_remote,
+        localstorage::LocalOpener,
+        metadata::Metadata,
+        subsonic::{self, SubsonicOpener},
+        webdav::WebDavOpener,
+        zipstorage::{self, ZipOpener},
+    },
+    stream::{Opener, Stream},
+};
 
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Source {
+    /// Content fingerprint, see `storage::fingerprint`.
+    /// Empty for sources from old projects.
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    size: u64,
+    /// Id of storage the source is from.
+    #[serde(default)]
+    storage: String,
     filename: String,
     title: String,
     tags: Vec<usize>,
+    #[serde(default)]
+    metadata: Metadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    features: Option<Features>,
+    /// Suggested tags waiting for accepting or rejecting.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    suggestions: Vec<String>,
+    /// Hidden duplicate, not shown in search results.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hidden: bool,
+    /// Not found by the last storage scan.
+    #[serde(skip)]
+    vanished: bool,
 }
 
 impl Source {
     pub fn new(filename: String, title: String) -> Source {
         Source {
+            id: String::new(),
+            size: 0,
+            storage: String::new(),
             filename,
             title,
             tags: Vec::new(),
+            metadata: Metadata::default(),
+            features: None,
+            suggestions: Vec::new(),
+            hidden: false,
+            vanished: false,
+        }
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn set_id(&mut self, id: String, size: u64) {
+        self.id = id;
+        self.size = size;
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_storage(&self) -> String {
+        self.storage.clone()
+    }
+
+    pub fn set_storage(&mut self, storage_id: String) {
+        self.storage = storage_id;
+    }
+
+    pub fn get_filename(&self) -> String {
+        self.filename.clone()
+    }
+
+    pub fn set_filename(&mut self, filename: String) {
+        self.filename = filename;
+        self.vanished = false;
+    }
+
+    pub fn set_vanished(&mut self) {
+        self.vanished = true;
+    }
+
+    /// Is local file or archive not found. Remote files are checked on
+    /// playback and by storage scan.
+    pub fn is_missing(&self) -> bool {
+        if self.vanished {
+            return true;
+        }
+        if let Some((archive, _)) = zipstorage::split_filename(&self.filename) {
+            return !archive.is_file();
+        }
+        !is_remote(&self.filename) && !Path::new(&self.filename).is_file()
+    }
+
+    /// Is other the same file. Compares ids if both known, else filenames.
+    pub fn is_same(&self, other: &Source) -> bool {
+        if !self.id.is_empty() && !other.id.is_empty() {
+            self.id == other.id
+        } else {
+            self.filename == other.filename
+        }
+    }
+
+    /// Opener of the storage the file is from.
+    pub fn opener(&self) -> Box<dyn Opener + Send> {
+        let filename = self.filename.clone();
+        let duration = self.metadata.duration;
+        let storage = self.storage.clone();
+        if filename.starts_with(subsonic::SCHEME) {
+            Box::new(SubsonicOpener::new(storage, filename, self.size, duration))
+        } else if filename.starts_with(zipstorage::SCHEME) {
+            Box::new(ZipOpener::new(filename, duration))
+        } else if is_remote(&filename) {
+            Box::new(WebDavOpener::new(storage, filename, self.size, duration))
+        } else {
+            Box::new(LocalOpener::new(filename, duration))
         }
     }
 
     pub fn get_stream(&self) -> Stream {
-        Stream::from_source(Box::new(LocalOpener::new(self.filename.clone())), 100.0)
+        Stream::from_source(self.opener(), 100.0)
+    }
+
+    pub fn get_features(&self) -> Option<Features> {
+        self.features
+    }
+
+    /// Set analyzed features and suggest tags not attached yet.
+    pub fn set_features(&mut self, features: Features, suggestions: Vec<String>) {
+        self.features = Some(features);
+        self.suggestions = suggestions;
+    }
+
+    pub fn get_suggestions(&self) -> Vec<String> {
+        self.suggestions.clone()
+    }
+
+    pub fn set_suggestions(&mut self, suggestions: Vec<String>) {
+        self.suggestions = suggestions;
+    }
+
+    pub fn remove_suggestion(&mut self, tag: &str) {
+        self.suggestions.retain(|s| s != tag);
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
     }
 
     pub fn get_title(&self) -> String {
@@ -64,10 +216,25 @@ impl Source {
         }
     }
 
+    /// Increment all tags from the index, when a tag is inserted there.
+    pub fn insert_tag_and_shift_indexes(&mut self, tag_index: usize) {
+        for tag in self.tags.iter_mut() {
+            if *tag >= tag_index {
+                *tag += 1;
+            }
+        }
+    }
+
     pub fn tags(&self) -> Vec<usize> {
         self.tags.clone()
     }
 
+    pub fn set_tags(&mut self, mut tag_indexes: Vec<usize>) {
+        tag_indexes.sort_unstable();
+        tag_indexes.dedup();
+        self.tags = tag_indexes;
+    }
+
     pub fn has_tag(&self, tag_index: usize) -> bool {
         self.tags.binary_search(&tag_index).is_ok()
     }
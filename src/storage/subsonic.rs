@@ -0,0 +1,490 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use rodio::Source;
+use serde::Deserialize;
+use url::Url;
+
+use crate::storage::extension;
+use crate::storage::http::{CachedFile, HttpFile, error_text};
+use crate::storage::localstorage::ScannedFile;
+use crate::storage::metadata::Metadata;
+use crate::storage::scanner::CANCELLED;
+use crate::stream::Opener;
+
+/// Filename prefix of songs, filename is `subsonic://<song id>/<path>`.
+pub const SCHEME: &str = "subsonic://";
+
+const API_VERSION: &str = "1.16.1";
+const CLIENT_NAME: &str = "cyberbard";
+/// Albums per request.
+const PAGE_SIZE: usize = 500;
+/// Tag of songs starred by user or from starred albums and artists.
+const STARRED_TAG: &str = "starred";
+
+lazy_static::lazy_static! {
+    /// Connected servers by storage id, openers of sources use them.
+    static ref CLIENTS: Mutex<HashMap<String, SubsonicClient>> = Mutex::new(HashMap::new());
+}
+
+/// Make storage client available to openers of its sources.
+pub fn register(storage_id: &str, client: SubsonicClient) {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .insert(storage_id.to_string(), client);
+}
+
+fn registered(storage_id: &str) -> Option<SubsonicClient> {
+    CLIENTS.lock().unwrap().get(storage_id).cloned()
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "subsonic-response")]
+    response: Response,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Response {
+    status: String,
+    error: Option<ApiError>,
+    album_list2: Option<AlbumList>,
+    album: Option<Album>,
+    starred2: Option<Starred>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ApiError {
+    code: i32,
+    message: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AlbumList {
+    album: Vec<Item>,
+}
+
+/// Album or artist reference.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Item {
+    id: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Album {
+    song: Vec<Song>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Starred {
+    artist: Vec<Item>,
+    album: Vec<Item>,
+    song: Vec<Song>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Song {
+    id: String,
+    title: String,
+    artist: String,
+    album: String,
+    genre: String,
+    /// OpenSubsonic list of all genres.
+    genres: Vec<Genre>,
+    comment: String,
+    duration: f32,
+    size: u64,
+    suffix: String,
+    path: String,
+    album_id: String,
+    artist_id: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Genre {
+    name: String,
+}
+
+impl Song {
+    fn genres(&self) -> Vec<String> {
+        if self.genres.is_empty() {
+            Metadata {
+                genre: self.genre.clone(),
+                ..Default::default()
+            }
+            .genres()
+        } else {
+            self.genres.iter().map(|g| g.name.clone()).collect()
+        }
+    }
+
+    /// Source filename, path part gives name and format of the song.
+    fn filename(&self) -> String {
+        let name = if self.path.is_empty() {
+            format!("{}.{}", self.title, self.suffix)
+        } else {
+            self.path.clone()
+        };
+        format!(
+            "{SCHEME}{}/{}",
+            utf8_percent_encode(&self.id, NON_ALPHANUMERIC),
+            name
+        )
+    }
+}
+
+/// Song id from source filename.
+fn song_id(filename: &str) -> Option<String> {
+    let rest = filename.strip_prefix(SCHEME)?;
+    let id = rest.split('/').next()?;
+    Some(percent_decode_str(id).decode_utf8_lossy().to_string())
+}
+
+/// Subsonic compatible server, e.g. Navidrome.
+#[derive(Clone)]
+pub struct SubsonicClient {
+    root: Url,
+    user: String,
+    password: String,
+    /// Directory of played files.
+    cache: PathBuf,
+    /// Size limit of played files kept on disk, they are not kept if None.
+    cache_limit: Option<u64>,
+    agent: ureq::Agent,
+}
+
+impl SubsonicClient {
+    pub fn new(
+        url: &str,
+        user: &str,
+        password: &str,
+        cache: PathBuf,
+        cache_limit: Option<u64>,
+    ) -> Result<SubsonicClient, String> {
+        let mut root = Url::parse(url).map_err(|e| format!("{url}: {e}"))?;
+        if !root.path().ends_with('/') {
+            let path = format!("{}/", root.path());
+            root.set_path(&path);
+        }
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(Duration::from_secs(30))
+            .build();
+        Ok(SubsonicClient {
+            root,
+            user: user.to_string(),
+            password: password.to_string(),
+            cache,
+            cache_limit,
+            agent,
+        })
+    }
+
+    /// Url of API method with token authentication.
+    fn endpoint(&self, method: &str, params: &[(&str, &str)]) -> Url {
+        let salt = hex::encode(rand::random::<[u8; 6]>());
+        let token = format!("{:x}", md5::compute(format!("{}{}", self.password, salt)));
+        let mut url = self.root.join("rest/").unwrap().join(method).unwrap();
+        url.query_pairs_mut()
+            .append_pair("u", &self.user)
+            .append_pair("t", &token)
+            .append_pair("s", &salt)
+            .append_pair("v", API_VERSION)
+            .append_pair("c", CLIENT_NAME)
+            .append_pair("f", "json")
+            .extend_pairs(params);
+        url
+    }
+
+    fn call(&self, method: &str, params: &[(&str, &str)]) -> Result<Response, String> {
+        let body = self
+            .agent
+            .request_url("GET", &self.endpoint(method, params))
+            .call()
+            .map_err(error_text)?
+            .into_string()
+            .map_err(|e| e.to_string())?;
+        let envelope: Envelope =
+            serde_json::from_str(&body).map_err(|e| format!("{method}: {e}"))?;
+        let response = envelope.response;
+        if response.status == "ok" {
+            return Ok(response);
+        }
+        match response.error {
+            Some(error) if error.code == 40 => Err("wrong user name or password".to_string()),
+            Some(error) => Err(format!("{method}: {}", error.message)),
+            None => Err(format!("{method}: request failed")),
+        }
+    }
+
+    /// Read songs of all albums. Genres and starred mark become tags.
+    pub fn scan(
+        &self,
+        progress: &dyn Fn(usize, usize) -> bool,
+    ) -> Result<Vec<ScannedFile>, String> {
+        let mut albums = vec![];
+        loop {
+            let offset = albums.len().to_string();
+            let size = PAGE_SIZE.to_string();
+            let page = self
+                .call(
+                    "getAlbumList2",
+                    &[
+                        ("type", "alphabeticalByName"),
+                        ("size", &size),
+                        ("offset", &offset),
+                    ],
+                )?
+                .album_list2
+                .unwrap_or_default()
+                .album;
+            let is_last = page.len() < PAGE_SIZE;
+            albums.extend(page);
+            if is_last {
+                break;
+            }
+        }
+
+        let starred = self.call("getStarred2", &[])?.starred2.unwrap_or_default();
+        let ids = |items: &[Item]| -> HashSet<String> {
+            items.iter().map(|item| item.id.clone()).collect()
+        };
+        let starred_artists = ids(&starred.artist);
+        let starred_albums = ids(&starred.album);
+        let starred_songs: HashSet<String> = starred.song.iter().map(|s| s.id.clone()).collect();
+
+        let mut files = vec![];
+        let mut seen = HashSet::new();
+        for (i, album) in albums.iter().enumerate() {
+            if !progress(i, albums.len()) {
+                return Err(CANCELLED.to_string());
+            }
+            let songs = self
+                .call("getAlbum", &[("id", &album.id)])?
+                .album
+                .unwrap_or_default()
+                .song;
+            for song in songs {
+                let is_starred = starred_songs.contains(&song.id)
+                    || starred_albums.contains(&song.album_id)
+                    || starred_artists.contains(&song.artist_id);
+                if seen.insert(song.id.clone()) {
+                    files.push(scanned_song(song, is_starred));
+                }
+            }
+        }
+        // Starred songs without album.
+        for song in starred.song {
+            if seen.insert(song.id.clone()) {
+                files.push(scanned_song(song, true));
+            }
+        }
+        progress(albums.len(), albums.len());
+        Ok(files)
+    }
+
+    /// Original file of song, so it could be seeked with range requests.
+    fn file(&self, song_id: &str) -> HttpFile {
+        let url = self.endpoint("stream", &[("id", song_id), ("format", "raw")]);
+        HttpFile::new(self.agent.clone(), url, None)
+    }
+
+    /// Local copy of played song.
+    fn cached_path(&self, song_id: &str, filename: &str) -> PathBuf {
+        let name = format!("{}.{}", hex::encode(song_id), extension(filename));
+        self.cache.join("audio").join(name)
+    }
+}
+
+fn scanned_song(song: Song, is_starred: bool) -> ScannedFile {
+    let mut tags = song.genres();
+    if is_starred {
+        tags.push(STARRED_TAG.to_string());
+    }
+    let filename = song.filename();
+    let title = if song.title.is_empty() {
+        song.id.clone()
+    } else {
+        song.title
+    };
+    ScannedFile {
+        filename,
+        title,
+        metadata: Metadata {
+            artist: song.artist,
+            album: song.album,
+            genre: song.genre,
+            comment: song.comment,
+            duration: song.duration,
+        },
+        // Content is not read on import, songs are identified by filename.
+        id: String::new(),
+        size: song.size,
+        path: PathBuf::new(),
+        storage_tags: tags,
+    }
+}
+
+/// Stream song of Subsonic server.
+pub struct SubsonicOpener {
+    storage: String,
+    filename: String,
+    size: u64,
+    duration: f32,
+}
+
+impl SubsonicOpener {
+    /// Size and duration could be 0 if unknown.
+    pub fn new(storage: String, filename: String, size: u64, duration: f32) -> SubsonicOpener {
+        SubsonicOpener {
+            storage,
+            filename,
+            size,
+            duration,
+        }
+    }
+}
+
+impl Opener for SubsonicOpener {
+    fn source(&mut self) -> Result<Box<dyn rodio::Source + Send>, Box<dyn std::error::Error>> {
+        let client = registered(&self.storage).ok_or("music server is not connected")?;
+        let id = song_id(&self.filename).ok_or("wrong song address")?;
+        let cache = client.cache_limit.map(|limit| CachedFile {
+            path: client.cached_path(&id, &self.filename),
+            limit,
+        });
+        let source = client.file(&id).open(self.size, &self.filename, cache)?;
+        if let Some(duration) = source.total_duration() {
+            self.duration = duration.as_secs_f32();
+        }
+        Ok(source)
+    }
+
+    fn total_duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn name(&self) -> String {
+        self.filename.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::storage::http::mock;
+
+    /// Mock server with two albums, one of them is starred.
+    fn serve(data: Vec<u8>) -> String {
+        let address = mock::serve(move |request| {
+            let url = request.url();
+            let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+            let token = format!("{:x}", md5::compute(format!("secret{}", params["s"])));
+            let ok = |body: &str| {
+                format!(r#"{{"subsonic-response":{{"status":"ok","version":"1.16.1"{body}}}}}"#)
+            };
+            if params["u"] != "bard" || params["t"] != token {
+                return (
+                    "200 OK",
+                    r#"{"subsonic-response":{"status":"failed","error":{"code":40,"message":"Wrong username or password"}}}"#
+                        .as_bytes()
+                        .to_vec(),
+                );
+            }
+            match url.path() {
+                "/navidrome/rest/getAlbumList2" => (
+                    "200 OK",
+                    ok(r#","albumList2":{"album":[{"id":"al-1"},{"id":"al-2"}]}"#).into_bytes(),
+                ),
+                "/navidrome/rest/getStarred2" => {
+                    ("200 OK", ok(r#","starred2":{"album":[{"id":"al-2"}]}"#).into_bytes())
+                }
+                "/navidrome/rest/getAlbum" if params["id"] == "al-1" => (
+                    "200 OK",
+                    ok(&format!(
+                        r#","album":{{"song":[{{"id":"s 1","title":"Tavern","artist":"Bard",
+                        "album":"Inns","genre":"Folk; Celtic","duration":95,"size":{},
+                        "suffix":"mp3","path":"Bard/Inns/01 Tavern.mp3","albumId":"al-1"}}]}}"#,
+                        data.len()
+                    ))
+                    .into_bytes(),
+                ),
+                "/navidrome/rest/getAlbum" => (
+                    "200 OK",
+                    ok(r#","album":{"song":[{"id":"s2","title":"Storm","genres":[{"name":"Ambient"}],
+                        "suffix":"ogg","albumId":"al-2"}]}"#)
+                    .into_bytes(),
+                ),
+                "/navidrome/rest/stream" if params["id"] == "s 1" => {
+                    mock::ranged(&data, request.range)
+                }
+                _ => ("404 Not Found", vec![]),
+            }
+        });
+        format!("http://{address}/navidrome")
+    }
+
+    #[test]
+    fn import_songs() {
+        let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+        let url = serve(data.clone());
+        let cache = tempfile::tempdir().unwrap().path().to_path_buf();
+
+        let client = SubsonicClient::new(&url, "bard", "wrong", cache.clone(), None).unwrap();
+        assert_eq!(
+            client.scan(&|_, _| true).err().unwrap(),
+            "wrong user name or password"
+        );
+
+        let client = SubsonicClient::new(&url, "bard", "secret", cache, None).unwrap();
+        let files = client.scan(&|_, _| true).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].title, "Tavern");
+        assert_eq!(files[0].metadata.artist, "Bard");
+        assert_eq!(files[0].metadata.duration, 95.0);
+        assert_eq!(files[0].storage_tags, vec!["Folk", "Celtic"]);
+        assert_eq!(files[1].storage_tags, vec!["Ambient", "starred"]);
+        assert_eq!(
+            files[0].filename,
+            "subsonic://s%201/Bard/Inns/01 Tavern.mp3"
+        );
+        assert_eq!(song_id(&files[0].filename).unwrap(), "s 1");
+
+        let mut stream = client.file("s 1").reader(data.len() as u64);
+        let mut read = vec![];
+        stream.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+    }
+}
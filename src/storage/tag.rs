@@ -18,11 +18,22 @@ use serde::{Deserialize, Serialize};
 
 use crate::colors::rand_color;
 
+/// Separator of hierarchical tag path, like `location/forest/night`.
+pub const SEPARATOR: char = '/';
+/// Suggested tag categories.
+pub const CATEGORIES: [&str; 4] = ["mood", "location", "instrument", "intensity"];
+/// Max chars of tag path part and category.
+const MAX_LENGTH: usize = 30;
+
 /// Tag structures. Used to Sources in Storage classification.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Text could be a path of parent tags, so searching a parent tag
+/// also finds sources with its children.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Tag {
     text: String,
     color: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    category: String,
 }
 
 impl Tag {
@@ -30,6 +41,7 @@ impl Tag {
         let mut tag = Tag {
             text: String::new(),
             color: String::new(),
+            category: String::new(),
         };
         tag.set_text(text);
         tag.set_color(rand_color());
@@ -41,6 +53,7 @@ impl Tag {
         Tag {
             text: txt.clone(),
             color: txt.clone(),
+            category: String::new(),
         }
     }
 
@@ -48,9 +61,46 @@ impl Tag {
         self.text.clone()
     }
 
-    /// unicode string 30 chars max.
+    /// Path of unicode strings 30 chars max each.
     pub fn set_text(&mut self, text: String) {
-        self.text = text.chars().take(30).collect()
+        self.text = text
+            .split(SEPARATOR)
+            .map(|part| part.chars().take(MAX_LENGTH).collect::<String>())
+            .collect::<Vec<String>>()
+            .join(&SEPARATOR.to_string())
+    }
+
+    /// Last part of tag path.
+    pub fn get_name(&self) -> String {
+        match self.text.rsplit_once(SEPARATOR) {
+            Some((_, name)) => name.to_string(),
+            None => self.text.clone(),
+        }
+    }
+
+    /// Parents count.
+    pub fn depth(&self) -> usize {
+        self.text.matches(SEPARATOR).count()
+    }
+
+    pub fn get_category(&self) -> String {
+        self.category.clone()
+    }
+
+    /// Lowercase string 30 chars max, empty for no category.
+    pub fn set_category(&mut self, category: String) {
+        self.category = category.trim().to_lowercase().chars().take(MAX_LENGTH).collect()
+    }
+
+    /// Category to group the tag: the chosen one or the root of tag path.
+    pub fn group(&self) -> String {
+        if !self.category.is_empty() {
+            return self.category.clone();
+        }
+        match self.text.split_once(SEPARATOR) {
+            Some((root, _)) => root.trim().to_lowercase(),
+            None => String::new(),
+        }
     }
 
     pub fn get_color(&self) -> String {
@@ -82,6 +132,15 @@ impl Tag {
     }
 }
 
+/// True if `tag` is `parent` or its child, like `location/forest/night`
+/// for `location/forest`.
+pub fn is_tag_or_child(tag: &str, parent: &str) -> bool {
+    match tag.strip_prefix(parent) {
+        Some(rest) => rest.is_empty() || rest.starts_with(SEPARATOR),
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,6 +157,26 @@ mod tests {
         // utf8 30 chars max
         tag.set_text("абвгд ёЁ 123 __ 0 jlkdsjg".to_string());
         assert_eq!("абвгд ёЁ 123 __ 0 jlkdsjg", tag.get_text());
+
+        // 30 chars max of every path part
+        tag.set_text("location/1234567890123456789012345678901234567890".to_string());
+        assert_eq!("location/123456789012345678901234567890", tag.get_text());
+        assert_eq!("123456789012345678901234567890", tag.get_name());
+        assert_eq!(1, tag.depth());
+    }
+
+    #[test]
+    fn tag_hierarchy() {
+        let mut tag = Tag::new("Location/forest/night".to_string());
+        assert_eq!("night", tag.get_name());
+        assert_eq!("location", tag.group());
+        tag.set_category(" Mood ".to_string());
+        assert_eq!("mood", tag.group());
+
+        assert!(is_tag_or_child("location/forest/night", "location/forest"));
+        assert!(is_tag_or_child("location/forest", "location/forest"));
+        assert!(!is_tag_or_child("location/forest-fire", "location/forest"));
+        assert!(!is_tag_or_child("location", "location/forest"));
     }
 
     #[test]
@@ -0,0 +1,277 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tag assignments in CSV and JSON files, to share tagging of the same
+//! packs and to edit tags in a spreadsheet.
+//!
+//! CSV columns are `path,id,title,tags,colors`, tags and their colors are
+//! separated by `;` in one cell. `;` and `\` in tags are escaped by `\`.
+
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+const CSV_HEADER: [&str; 5] = ["path", "id", "title", "tags", "colors"];
+const LIST_SEPARATOR: char = ';';
+const LIST_ESCAPE: char = '\\';
+
+/// Tags of one source.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct TagRecord {
+    /// Path relative to the storage root.
+    pub path: String,
+    /// Content fingerprint, could be empty.
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Colors of tags in the same order, could be shorter or empty for
+    /// some tags.
+    #[serde(default)]
+    pub colors: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    /// Format by file extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+pub fn write_records(records: &[TagRecord], format: Format) -> Result<String, String> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(records).map_err(|e| e.to_string()),
+        Format::Csv => {
+            let mut text = csv_line(&CSV_HEADER.map(String::from));
+            for record in records {
+                text += &csv_line(&[
+                    record.path.clone(),
+                    record.id.clone(),
+                    record.title.clone(),
+                    join_list(&record.tags),
+                    join_list(&record.colors),
+                ]);
+            }
+            Ok(text)
+        }
+    }
+}
+
+pub fn read_records(text: &str, format: Format) -> Result<Vec<TagRecord>, String> {
+    match format {
+        Format::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        Format::Csv => {
+            let mut rows = parse_csv(text)?.into_iter();
+            let header = rows.next().unwrap_or_default();
+            let column = |name: &str| header.iter().position(|h| h.trim() == name);
+            let path = column("path").ok_or("no path column")?;
+            let (id, title) = (column("id"), column("title"));
+            let (tags, colors) = (column("tags"), column("colors"));
+
+            let cell = |row: &[String], column: Option<usize>| {
+                column
+                    .and_then(|c| row.get(c))
+                    .map(|c| c.trim().to_string())
+                    .unwrap_or_default()
+            };
+            Ok(rows
+                .filter(|row| row.iter().any(|c| !c.trim().is_empty()))
+                .map(|row| {
+                    let (tags, colors) = tags_and_colors(
+                        split_list(&cell(&row, tags)),
+                        split_list(&cell(&row, colors)),
+                    );
+                    TagRecord {
+                        path: cell(&row, Some(path)),
+                        id: cell(&row, id),
+                        title: cell(&row, title),
+                        tags,
+                        colors,
+                    }
+                })
+                .collect())
+        }
+    }
+}
+
+/// Save records to CSV or JSON file by its extension.
+pub fn save_records(path: &Path, records: &[TagRecord]) -> Result<(), String> {
+    let format = Format::from_path(path).ok_or("unknown file format")?;
+    let text = write_records(records, format)?;
+    fs::write(path, text).map_err(|e| e.to_string())
+}
+
+/// Items in one cell with escaped separators.
+fn join_list(items: &[String]) -> String {
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| {
+            item.replace(LIST_ESCAPE, &format!("{LIST_ESCAPE}{LIST_ESCAPE}"))
+                .replace(LIST_SEPARATOR, &format!("{LIST_ESCAPE}{LIST_SEPARATOR}"))
+        })
+        .collect();
+    items.join(&LIST_SEPARATOR.to_string())
+}
+
+/// Items of one cell. Empty items are kept, so items of two lists stay in
+/// the same places.
+fn split_list(cell: &str) -> Vec<String> {
+    if cell.is_empty() {
+        return vec![];
+    }
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut chars = cell.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            LIST_ESCAPE => item.extend(chars.next()),
+            LIST_SEPARATOR => items.push(std::mem::take(&mut item).trim().to_string()),
+            c => item.push(c),
+        }
+    }
+    items.push(item.trim().to_string());
+    items
+}
+
+/// Not empty tags with colors in the same places. Colors list is cut
+/// after the last color.
+fn tags_and_colors(tags: Vec<String>, colors: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let mut colors = colors.into_iter();
+    let mut result = (vec![], vec![]);
+    for tag in tags {
+        let color = colors.next().unwrap_or_default();
+        if !tag.is_empty() {
+            result.0.push(tag);
+            result.1.push(color);
+        }
+    }
+    while result.1.last().is_some_and(|c| c.is_empty()) {
+        result.1.pop();
+    }
+    result
+}
+
+/// Line of cells, quoted if needed.
+fn csv_line(cells: &[String]) -> String {
+    let cells: Vec<String> = cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        })
+        .collect();
+    cells.join(",") + "\n"
+}
+
+/// Rows of RFC 4180 CSV: quoted cells could contain commas, quotes and
+/// line breaks.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => cell.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut cell)),
+            (false, '\r') => (),
+            (false, '\n') => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err("unclosed quote".to_string());
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_and_json() {
+        let records = vec![
+            TagRecord {
+                path: "Forest/owl, night.ogg".to_string(),
+                id: "abc".to_string(),
+                title: "Owl \"hoot\"".to_string(),
+                tags: vec!["forest".to_string(), "location/forest/night".to_string()],
+                colors: vec!["#112233".to_string(), "#445566".to_string()],
+            },
+            TagRecord {
+                path: "Tavern.mp3".to_string(),
+                ..Default::default()
+            },
+        ];
+        for format in [Format::Csv, Format::Json] {
+            let text = write_records(&records, format).unwrap();
+            assert_eq!(read_records(&text, format).unwrap(), records);
+        }
+
+        // Spreadsheet export with other columns order and CRLF.
+        let text = "title,tags,path\r\nRain,\"calm; weather\",Rain.ogg\r\n,,\r\n";
+        let records = read_records(text, Format::Csv).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].path, "Rain.ogg");
+        assert_eq!(records[0].tags, vec!["calm", "weather"]);
+        assert!(read_records("path\n\"x", Format::Csv).is_err());
+
+        // Colors of some tags only, tags with separator.
+        let text = "path,tags,colors\nRain.ogg,calm;weather; ;night,;#fff;#f00;#000\n";
+        let records = read_records(text, Format::Csv).unwrap();
+        assert_eq!(records[0].tags, vec!["calm", "weather", "night"]);
+        assert_eq!(records[0].colors, vec!["", "#fff", "#000"]);
+        let records = vec![TagRecord {
+            path: "Rain.ogg".to_string(),
+            tags: vec!["mood;calm".to_string(), "a\\b".to_string()],
+            ..Default::default()
+        }];
+        let text = write_records(&records, Format::Csv).unwrap();
+        assert_eq!(read_records(&text, Format::Csv).unwrap(), records);
+        assert!(read_records("title\nx", Format::Csv).is_err());
+    }
+}
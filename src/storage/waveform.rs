@@ -0,0 +1,147 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>
+
+//! Compact waveform overview of the whole track: peak and RMS levels of
+//! a fixed number of bins. Waveforms are cached in the storage cache
+//! directory by source id.
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::stream::Opener;
+
+/// Bins of waveform.
+pub const BINS: usize = 100;
+/// Frames per block, blocks are joined to bins.
+const BLOCK: usize = 1024;
+const CACHE_FILE: &str = "waveforms.yaml";
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Waveform {
+    /// Max level of every bin from 0 to 255.
+    #[serde(with = "hex_bytes")]
+    pub peaks: Vec<u8>,
+    /// RMS level of every bin from 0 to 255.
+    #[serde(with = "hex_bytes")]
+    pub rms: Vec<u8>,
+}
+
+impl Waveform {
+    /// Waveform of interleaved samples.
+    pub fn from_samples(samples: impl Iterator<Item = f32>, channels: usize) -> Waveform {
+        let block_len = BLOCK * channels.max(1);
+        // Peak, sum of squares and count of samples.
+        let mut blocks: Vec<(f32, f32, usize)> = vec![];
+        let mut block = (0.0f32, 0.0, 0);
+        for sample in samples {
+            block = (
+                block.0.max(sample.abs()),
+                block.1 + sample * sample,
+                block.2 + 1,
+            );
+            if block.2 == block_len {
+                blocks.push(block);
+                block = (0.0, 0.0, 0);
+            }
+        }
+        if block.2 > 0 {
+            blocks.push(block);
+        }
+
+        let bins = BINS.min(blocks.len());
+        let mut waveform = Waveform::default();
+        for bin in 0..bins {
+            let part = &blocks[bin * blocks.len() / bins..(bin + 1) * blocks.len() / bins];
+            let peak = part.iter().map(|b| b.0).fold(0.0, f32::max);
+            let (sum, count) = part.iter().fold((0.0, 0), |a, b| (a.0 + b.1, a.1 + b.2));
+            waveform.peaks.push(to_byte(peak));
+            waveform
+                .rms
+                .push(to_byte((sum / count.max(1) as f32).sqrt()));
+        }
+        waveform
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peaks.is_empty()
+    }
+}
+
+fn to_byte(level: f32) -> u8 {
+    (level.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Decode the whole track and make its waveform.
+pub fn generate(opener: &mut dyn Opener) -> Result<Waveform, String> {
+    let source = opener.source().map_err(|e| e.to_string())?;
+    let channels = source.channels().max(1) as usize;
+    Ok(Waveform::from_samples(source, channels))
+}
+
+/// Cached waveforms of storage by source key.
+pub fn load_cache(dir: &Path) -> HashMap<String, Waveform> {
+    fs::read_to_string(dir.join(CACHE_FILE))
+        .ok()
+        .and_then(|s| serde_yaml::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_cache(dir: &Path, waveforms: &HashMap<String, Waveform>) -> io::Result<()> {
+    let s = serde_yaml::to_string(waveforms).map_err(io::Error::other)?;
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(CACHE_FILE), s)
+}
+
+/// Bytes as hex string, much shorter than YAML list.
+mod hex_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        hex::decode(text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_intro_and_cache() {
+        // Stereo: 10 silent blocks, then loud ones.
+        let frames = 100 * BLOCK;
+        let samples = (0..frames * 2).map(|i| if i < frames / 5 { 0.0 } else { -0.5 });
+        let waveform = Waveform::from_samples(samples, 2);
+        assert_eq!(waveform.peaks.len(), BINS);
+        assert_eq!(waveform.peaks[0], 0);
+        assert_eq!(waveform.peaks[BINS - 1], 128);
+        assert_eq!(waveform.rms[BINS - 1], 128);
+
+        let short = Waveform::from_samples([0.1, 1.0, -0.2].into_iter(), 1);
+        assert_eq!(short.peaks, vec![255]);
+        assert!(Waveform::from_samples(std::iter::empty(), 1).is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let waveforms = HashMap::from([("abc".to_string(), waveform)]);
+        save_cache(dir.path(), &waveforms).unwrap();
+        assert_eq!(load_cache(dir.path()), waveforms);
+    }
+}
@@ -0,0 +1,423 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use rodio::Source;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::storage::cache::{CacheEntry, MetadataCache};
+use crate::storage::fingerprint::fingerprint_reader;
+use crate::storage::http::{CachedFile, HttpFile, error_text};
+use crate::storage::localstorage::{ScannedFile, default_title};
+use crate::storage::metadata::read_metadata_from;
+use crate::storage::scanner::CANCELLED;
+use crate::storage::{extension, is_music_file, undecodable_format};
+use crate::stream::Opener;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:"><prop>
+<resourcetype/><getcontentlength/><getlastmodified/><getetag/>
+</prop></propfind>"#;
+
+lazy_static::lazy_static! {
+    /// Connected remote storages by storage id, openers of sources use them.
+    static ref CLIENTS: Mutex<HashMap<String, WebDavClient>> = Mutex::new(HashMap::new());
+}
+
+/// Make storage client available to openers of its sources.
+pub fn register(storage_id: &str, client: WebDavClient) {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .insert(storage_id.to_string(), client);
+}
+
+fn registered(storage_id: &str) -> Option<WebDavClient> {
+    CLIENTS.lock().unwrap().get(storage_id).cloned()
+}
+
+/// File or folder from PROPFIND response.
+struct DavEntry {
+    url: Url,
+    is_folder: bool,
+    size: u64,
+    /// ETag and Last-Modified, changed with file content.
+    version: String,
+}
+
+#[derive(Clone)]
+pub struct WebDavClient {
+    root: Url,
+    authorization: Option<String>,
+    /// Directory of played files.
+    cache: PathBuf,
+    /// Size limit of played files kept on disk, they are not kept if None.
+    cache_limit: Option<u64>,
+    agent: ureq::Agent,
+}
+
+impl WebDavClient {
+    pub fn new(
+        url: &str,
+        user: &str,
+        password: &str,
+        cache: PathBuf,
+        cache_limit: Option<u64>,
+    ) -> Result<WebDavClient, String> {
+        let mut root = Url::parse(url).map_err(|e| format!("{url}: {e}"))?;
+        if !root.path().ends_with('/') {
+            let path = format!("{}/", root.path());
+            root.set_path(&path);
+        }
+        let authorization = (!user.is_empty()).then(|| {
+            let token =
+                base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+            format!("Basic {token}")
+        });
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(Duration::from_secs(30))
+            .build();
+        Ok(WebDavClient {
+            root,
+            authorization,
+            cache,
+            cache_limit,
+            agent,
+        })
+    }
+
+    fn request(&self, method: &str, url: &Url) -> ureq::Request {
+        let request = self.agent.request_url(method, url);
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    /// All music files under the root. Folders are listed one level per
+    /// request, because many servers forbid infinite depth.
+    fn list(&self) -> Result<Vec<DavEntry>, String> {
+        let mut folders = vec![self.root.clone()];
+        let mut files = vec![];
+        while let Some(folder) = folders.pop() {
+            let body = self
+                .request("PROPFIND", &folder)
+                .set("Depth", "1")
+                .set("Content-Type", "application/xml; charset=utf-8")
+                .send_string(PROPFIND_BODY)
+                .map_err(error_text)?
+                .into_string()
+                .map_err(|e| e.to_string())?;
+
+            for entry in parse_multistatus(&folder, &body)? {
+                let path = entry.url.path();
+                // Skip the folder itself and links outside the root.
+                if path.trim_end_matches('/') == folder.path().trim_end_matches('/')
+                    || !path.starts_with(self.root.path())
+                {
+                    continue;
+                }
+                if entry.is_folder {
+                    folders.push(entry.url);
+                } else if is_music_file(path) {
+                    files.push(entry);
+                }
+            }
+        }
+        files.sort_by(|a, b| a.url.path().cmp(b.url.path()));
+        Ok(files)
+    }
+
+    /// Path relative to the storage root, decoded.
+    fn relative_path(&self, url: &Url) -> PathBuf {
+        let path = url.path().strip_prefix(self.root.path()).unwrap_or("");
+        PathBuf::from(percent_decode_str(path).decode_utf8_lossy().to_string())
+    }
+
+    /// Read all music files of the storage. Unchanged files are taken from
+    /// the metadata cache in `cache_root`, others are read partially with
+    /// range requests.
+    pub fn scan(
+        &self,
+        cache_root: &Path,
+        progress: &dyn Fn(usize, usize) -> bool,
+    ) -> Result<Vec<ScannedFile>, String> {
+        let remote = self.list()?;
+        let mut cache = MetadataCache::load(cache_root);
+        let mut keys = Vec::with_capacity(remote.len());
+        let mut files = Vec::with_capacity(remote.len());
+
+        for (i, file) in remote.iter().enumerate() {
+            if !progress(i, remote.len()) {
+                return Err(CANCELLED.to_string());
+            }
+            let path = self.relative_path(&file.url);
+            let key = path.to_string_lossy().to_string();
+            // Remote files have no mtime, version hash is cached instead.
+            let version = version_number(&file.version);
+
+            let entry = match cache.get(&key, version, file.size) {
+                Some(entry) => entry.clone(),
+                None => {
+                    let reader = self.file(file.url.clone()).reader(file.size);
+                    let (title, metadata) = read_metadata_from(reader);
+                    let reader = self.file(file.url.clone()).reader(file.size);
+                    let entry = CacheEntry {
+                        mtime: version,
+                        size: file.size,
+                        id: fingerprint_reader(reader, file.size).unwrap_or_default(),
+                        title,
+                        metadata,
+                    };
+                    cache.insert(key.clone(), entry.clone());
+                    entry
+                }
+            };
+
+            files.push(ScannedFile {
+                filename: file.url.to_string(),
+                title: entry.title.unwrap_or_else(|| default_title(&path)),
+                metadata: entry.metadata,
+                id: entry.id,
+                size: file.size,
+                path,
+                storage_tags: vec![],
+            });
+            keys.push(key);
+        }
+        progress(remote.len(), remote.len());
+
+        cache.retain(&keys);
+        if let Err(e) = cache.save(cache_root) {
+            eprintln!("Error saving metadata cache: {}", e);
+        }
+        Ok(files)
+    }
+
+    fn file(&self, url: Url) -> HttpFile {
+        HttpFile::new(self.agent.clone(), url, self.authorization.clone())
+    }
+
+    /// Local copy of played file.
+    fn cached_path(&self, url: &Url) -> PathBuf {
+        let hash = Sha256::digest(url.as_str().as_bytes());
+        let name = format!("{}.{}", hex::encode(&hash[..16]), extension(url.path()));
+        self.cache.join("audio").join(name)
+    }
+}
+
+/// Stream file of remote storage. Played files are cached on disk if the
+/// storage allows it.
+pub struct WebDavOpener {
+    storage: String,
+    url: String,
+    size: u64,
+    duration: f32,
+}
+
+impl WebDavOpener {
+    /// Size and duration could be 0 if unknown.
+    pub fn new(storage: String, url: String, size: u64, duration: f32) -> WebDavOpener {
+        WebDavOpener {
+            storage,
+            url,
+            size,
+            duration,
+        }
+    }
+}
+
+impl Opener for WebDavOpener {
+    fn source(&mut self) -> Result<Box<dyn rodio::Source + Send>, Box<dyn std::error::Error>> {
+        if let Some(format) = undecodable_format(&self.url) {
+            return Err(format!("{format} playback is not supported").into());
+        }
+        let client = registered(&self.storage).ok_or("remote storage is not connected")?;
+        let url = Url::parse(&self.url)?;
+
+        let cache = client.cache_limit.map(|limit| CachedFile {
+            path: client.cached_path(&url),
+            limit,
+        });
+        let source = client.file(url).open(self.size, &self.url, cache)?;
+        if let Some(duration) = source.total_duration() {
+            self.duration = duration.as_secs_f32();
+        }
+        Ok(source)
+    }
+
+    fn total_duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn name(&self) -> String {
+        percent_decode_str(&self.url)
+            .decode_utf8_lossy()
+            .to_string()
+    }
+
+    fn filename(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// Stable number of file version string.
+fn version_number(version: &str) -> u64 {
+    let hash = Sha256::digest(version.as_bytes());
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+/// Files and folders of PROPFIND response.
+fn parse_multistatus(base: &Url, xml: &str) -> Result<Vec<DavEntry>, String> {
+    let document = roxmltree::Document::parse(xml).map_err(|e| e.to_string())?;
+    let is_dav = |node: &roxmltree::Node, name: &str| {
+        node.is_element()
+            && node.tag_name().name() == name
+            && node.tag_name().namespace() == Some("DAV:")
+    };
+
+    let mut entries = vec![];
+    for response in document.descendants().filter(|n| is_dav(n, "response")) {
+        let text = |name: &str| {
+            response
+                .descendants()
+                .find(|n| is_dav(n, name))
+                .and_then(|n| n.text())
+                .unwrap_or("")
+                .trim()
+        };
+        // Only path of absolute href is used, host could be an internal
+        // name behind a proxy.
+        let href = text("href");
+        let path = Url::parse(href).map_or(href.to_string(), |u| u.path().to_string());
+        let Ok(url) = base.join(&path) else {
+            continue;
+        };
+        entries.push(DavEntry {
+            url,
+            is_folder: response.descendants().any(|n| is_dav(&n, "collection")),
+            size: text("getcontentlength").parse().unwrap_or(0),
+            version: format!("{} {}", text("getetag"), text("getlastmodified")),
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Seek, SeekFrom},
+    };
+
+    use super::*;
+    use crate::storage::{fingerprint::fingerprint, http::mock};
+
+    fn response(href: &str, props: &str) -> String {
+        format!(
+            "<d:response><d:href>{href}</d:href><d:propstat><d:prop>{props}</d:prop>\
+             <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+        )
+    }
+
+    /// Stand-in WebDAV server with one folder and one file.
+    fn serve(data: Vec<u8>) -> String {
+        let address = mock::serve(move |request| {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("PROPFIND", "/music/") => {
+                    let xml = format!(
+                        "<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\">{}{}{}</d:multistatus>",
+                        response(
+                            "/music/",
+                            "<d:resourcetype><d:collection/></d:resourcetype>"
+                        ),
+                        response(
+                            "/music/Forest%20night/",
+                            "<d:resourcetype><d:collection/></d:resourcetype>"
+                        ),
+                        response(
+                            "/music/notes.txt",
+                            "<d:getcontentlength>4</d:getcontentlength>"
+                        ),
+                    );
+                    ("207 Multi-Status", xml.into_bytes())
+                }
+                ("PROPFIND", "/music/Forest%20night/") => {
+                    let xml = format!(
+                        "<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\">{}{}</d:multistatus>",
+                        response(
+                            "/music/Forest%20night/",
+                            "<d:resourcetype><d:collection/></d:resourcetype>"
+                        ),
+                        response(
+                            "http://ignored.host/music/Forest%20night/owl.ogg",
+                            &format!(
+                                "<d:resourcetype/><d:getcontentlength>{}</d:getcontentlength>\
+                                 <d:getetag>\"1\"</d:getetag>",
+                                data.len()
+                            ),
+                        ),
+                    );
+                    ("207 Multi-Status", xml.into_bytes())
+                }
+                ("GET", "/music/Forest%20night/owl.ogg") => mock::ranged(&data, request.range),
+                _ => ("404 Not Found", vec![]),
+            }
+        });
+        format!("http://{address}/music")
+    }
+
+    #[test]
+    fn scan_and_read_ranges() {
+        let data: Vec<u8> = (0..600_000).map(|i| (i % 251) as u8).collect();
+        let url = serve(data.clone());
+        let cache = tempfile::tempdir().unwrap();
+        let client = WebDavClient::new(&url, "", "", cache.path().to_path_buf(), None).unwrap();
+
+        let files = client.scan(cache.path(), &|_, _| true).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].title, "owl");
+        assert_eq!(files[0].path, std::path::Path::new("Forest night/owl.ogg"));
+        assert!(files[0].filename.ends_with("/music/Forest%20night/owl.ogg"));
+        assert!(files[0].filename.starts_with(&url));
+
+        // Remote and local copies have the same id.
+        let local = cache.path().join("owl.ogg");
+        fs::write(&local, &data).unwrap();
+        assert_eq!(files[0].id, fingerprint(&local).unwrap());
+
+        let url = Url::parse(&files[0].filename).unwrap();
+        let mut reader = client.file(url).reader(data.len() as u64);
+        let mut buffer = vec![0; 10];
+        reader.seek(SeekFrom::Start(300_000)).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data[300_000..300_010]);
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[data.len() - 5..]);
+    }
+}
@@ -0,0 +1,444 @@
+//   Cyberbard music player for board role-playing games.
+//   Copyright (C) 2025  Aleksandr Dovydenkov <asd@altlinux.org>
+//
+//   This program is free software: you can redistribute it and/or modify
+//   it under the terms of the GNU General Public License as published by
+//   the Free Software Foundation, either version 3 of the License, or
+//   (at your option) any later version.
+//
+//   This program is distributed in the hope that it will be useful,
+//   but WITHOUT ANY WARRANTY; without even the implied warranty of
+//   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//   GNU General Public License for more details.
+//
+//   You should have received a copy of the GNU General Public License
+//   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use rodio::Source;
+use zip::{CompressionMethod, ZipArchive, read::ZipFile};
+
+use crate::storage::cache::{self, CacheEntry, MetadataCache};
+use crate::storage::fingerprint::fingerprint_reader;
+use crate::storage::localstorage::{ScannedFile, decode, default_title};
+use crate::storage::metadata::read_metadata_from;
+use crate::storage::scanner::CANCELLED;
+use crate::storage::{is_music_file, undecodable_format};
+use crate::stream::Opener;
+
+/// Filename prefix of archive entries, filename is `zip://<archive>!/<entry>`.
+pub const SCHEME: &str = "zip://";
+const SEPARATOR: &str = "!/";
+/// Larger compressed entries are unpacked to temporary file instead of memory.
+const MEMORY_LIMIT: u64 = 64 * 1024 * 1024;
+/// Kept start and end of compressed entry on scanning, tags are there.
+const SAMPLE_SIZE: u64 = 1024 * 1024;
+
+pub fn entry_filename(archive: &Path, entry: &str) -> String {
+    format!("{SCHEME}{}{SEPARATOR}{entry}", archive.to_string_lossy())
+}
+
+/// Archive path and entry name of source filename.
+pub fn split_filename(filename: &str) -> Option<(PathBuf, String)> {
+    let (archive, entry) = filename.strip_prefix(SCHEME)?.split_once(SEPARATOR)?;
+    Some((PathBuf::from(archive), entry.to_string()))
+}
+
+trait ReadSeek: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> ReadSeek for T {}
+
+/// Stored (not compressed) entry, read directly from the archive file.
+struct FileSlice {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for FileSlice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        if left == 0 {
+            return Ok(0);
+        }
+        let count = (buf.len() as u64).min(left) as usize;
+        self.file.seek(SeekFrom::Start(self.start + self.pos))?;
+        let count = self.file.read(&mut buf[..count])?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for FileSlice {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the entry start",
+            )),
+        }
+    }
+}
+
+/// Stored (not compressed) entry read in place.
+fn stored_slice(entry: &ZipFile, archive: &Path) -> io::Result<Option<Box<dyn ReadSeek>>> {
+    if entry.compression() != CompressionMethod::Stored || entry.encrypted() {
+        return Ok(None);
+    }
+    let slice = FileSlice {
+        file: File::open(archive)?,
+        start: entry.data_start(),
+        len: entry.size(),
+        pos: 0,
+    };
+    Ok(Some(Box::new(BufReader::new(slice))))
+}
+
+/// Seekable data of archive entry and its size. Stored entries are read in
+/// place, compressed ones are unpacked.
+fn open_entry(archive: &Path, name: &str) -> io::Result<(Box<dyn ReadSeek>, u64)> {
+    let mut zip = ZipArchive::new(BufReader::new(File::open(archive)?))?;
+    let mut entry = zip.by_name(name)?;
+    let size = entry.size();
+    if let Some(slice) = stored_slice(&entry, archive)? {
+        return Ok((slice, size));
+    }
+
+    if size <= MEMORY_LIMIT {
+        let mut data = Vec::with_capacity(size as usize);
+        entry.read_to_end(&mut data)?;
+        Ok((Box::new(Cursor::new(data)), size))
+    } else {
+        let mut file = tempfile::tempfile()?;
+        io::copy(&mut entry, &mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok((Box::new(BufReader::new(file)), size))
+    }
+}
+
+/// Start and end of compressed entry, enough to read its tags and
+/// fingerprint. The middle is read as zeros.
+struct EntrySample {
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    size: u64,
+    pos: u64,
+}
+
+impl EntrySample {
+    /// Unpack the entry once keeping only its start and end.
+    fn read(mut entry: impl Read, size: u64) -> io::Result<EntrySample> {
+        let mut head = vec![];
+        (&mut entry).take(SAMPLE_SIZE).read_to_end(&mut head)?;
+        let mut tail = vec![];
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let count = entry.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            tail.extend_from_slice(&buffer[..count]);
+            if tail.len() as u64 > 2 * SAMPLE_SIZE {
+                tail.drain(..tail.len() - SAMPLE_SIZE as usize);
+            }
+        }
+        if tail.len() as u64 > SAMPLE_SIZE {
+            tail.drain(..tail.len() - SAMPLE_SIZE as usize);
+        }
+        Ok(EntrySample {
+            head,
+            tail,
+            size,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for EntrySample {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size {
+            return Ok(0);
+        }
+        let head_len = self.head.len() as u64;
+        let tail_start = self.size - self.tail.len() as u64;
+        let (data, start, end): (&[u8], u64, u64) = if self.pos < head_len {
+            (&self.head, 0, head_len)
+        } else if self.pos >= tail_start {
+            (&self.tail, tail_start, self.size)
+        } else {
+            (&[], head_len, tail_start)
+        };
+        let count = (buf.len() as u64).min(end - self.pos) as usize;
+        if data.is_empty() {
+            buf[..count].fill(0);
+        } else {
+            let offset = (self.pos - start) as usize;
+            buf[..count].copy_from_slice(&data[offset..offset + count]);
+        }
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for EntrySample {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the entry start",
+            )),
+        }
+    }
+}
+
+/// Data of entry to read its tags and fingerprint. Compressed entries are
+/// not kept whole.
+fn sample_entry<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    archive: &Path,
+    name: &str,
+) -> io::Result<Box<dyn ReadSeek>> {
+    let mut entry = zip.by_name(name)?;
+    let size = entry.size();
+    if let Some(slice) = stored_slice(&entry, archive)? {
+        return Ok(slice);
+    }
+    if size <= 2 * SAMPLE_SIZE {
+        let mut data = Vec::with_capacity(size as usize);
+        entry.read_to_end(&mut data)?;
+        return Ok(Box::new(Cursor::new(data)));
+    }
+    Ok(Box::new(EntrySample::read(entry, size)?))
+}
+
+/// Fingerprint of archive entry given by source filename, the same as
+/// fingerprint of the entry on scanning.
+pub fn fingerprint_entry(filename: &str) -> io::Result<String> {
+    let (archive, name) = split_filename(filename).ok_or(io::ErrorKind::InvalidInput)?;
+    let file = File::open(&archive)?;
+    let mut zip = ZipArchive::new(BufReader::new(file))?;
+    let size = zip.by_name(&name)?.size();
+    fingerprint_reader(sample_entry(&mut zip, &archive, &name)?, size)
+}
+
+/// Play entry of zip archive.
+pub struct ZipOpener {
+    filename: String,
+    duration: f32,
+}
+
+impl ZipOpener {
+    /// Duration is updated on source opening, could be 0 if unknown.
+    pub fn new(filename: String, duration: f32) -> ZipOpener {
+        ZipOpener { filename, duration }
+    }
+}
+
+impl Opener for ZipOpener {
+    fn source(&mut self) -> Result<Box<dyn rodio::Source + Send>, Box<dyn std::error::Error>> {
+        if let Some(format) = undecodable_format(&self.filename) {
+            return Err(format!("{format} playback is not supported").into());
+        }
+        let (archive, name) = split_filename(&self.filename).ok_or("wrong archive entry")?;
+        let (data, size) = open_entry(&archive, &name)?;
+        let decoder = decode(data, size, &name)?;
+        if let Some(duration) = decoder.total_duration() {
+            self.duration = duration.as_secs_f32();
+        }
+        Ok(Box::new(decoder))
+    }
+
+    fn total_duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn name(&self) -> String {
+        self.filename.clone()
+    }
+}
+
+/// Read music entries of archives. Entries of unchanged archives are taken
+/// from the metadata cache in `cache_root`.
+pub fn scan_zip_files(
+    archives: &[PathBuf],
+    cache_root: &Path,
+    progress: &dyn Fn(usize, usize) -> bool,
+) -> Result<Vec<ScannedFile>, String> {
+    // Archive, its mtime, entry name and size.
+    let mut entries = vec![];
+    for archive in archives {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", archive.display(), e);
+        let file = File::open(archive).map_err(|e| error(&e))?;
+        let mtime = file.metadata().map(|m| cache::mtime(&m)).unwrap_or(0);
+        let mut zip = ZipArchive::new(BufReader::new(file)).map_err(|e| error(&e))?;
+        for i in 0..zip.len() {
+            let entry = zip.by_index_raw(i).map_err(|e| error(&e))?;
+            if entry.is_file() && is_music_file(entry.name()) {
+                entries.push((archive, mtime, entry.name().to_string(), entry.size()));
+            }
+        }
+    }
+
+    let mut cache = MetadataCache::load(cache_root);
+    let mut keys = Vec::with_capacity(entries.len());
+    let mut files = Vec::with_capacity(entries.len());
+    // Entries are grouped by archive, so it is opened once.
+    let mut zip: Option<(&PathBuf, ZipArchive<BufReader<File>>)> = None;
+
+    for (i, (archive, mtime, name, size)) in entries.iter().enumerate() {
+        if !progress(i, entries.len()) {
+            return Err(CANCELLED.to_string());
+        }
+        let filename = entry_filename(archive, name);
+        let key = filename.clone();
+
+        let entry = match cache.get(&key, *mtime, *size) {
+            Some(entry) => entry.clone(),
+            None => {
+                if zip.as_ref().is_none_or(|(path, _)| path != archive) {
+                    zip = File::open(archive)
+                        .ok()
+                        .and_then(|file| ZipArchive::new(BufReader::new(file)).ok())
+                        .map(|zip| (*archive, zip));
+                }
+                let data = match zip.as_mut() {
+                    Some((_, zip)) => sample_entry(zip, archive, name),
+                    None => Err(io::Error::other("archive is not opened")),
+                };
+                let (title, metadata, id) = match data {
+                    Ok(mut data) => {
+                        let (title, metadata) = read_metadata_from(&mut data);
+                        let id = data
+                            .seek(SeekFrom::Start(0))
+                            .and_then(|_| fingerprint_reader(&mut data, *size))
+                            .unwrap_or_default();
+                        (title, metadata, id)
+                    }
+                    Err(_) => (None, Default::default(), String::new()),
+                };
+                let entry = CacheEntry {
+                    mtime: *mtime,
+                    size: *size,
+                    id,
+                    title,
+                    metadata,
+                };
+                cache.insert(key.clone(), entry.clone());
+                entry
+            }
+        };
+
+        // Archive name is the root folder of entries.
+        let stem = archive.file_stem().unwrap_or_default();
+        let path = Path::new(stem).join(name);
+        files.push(ScannedFile {
+            filename,
+            title: entry.title.unwrap_or_else(|| default_title(&path)),
+            metadata: entry.metadata,
+            id: entry.id,
+            size: *size,
+            path,
+            storage_tags: vec![],
+        });
+        keys.push(key);
+    }
+    progress(entries.len(), entries.len());
+
+    cache.retain(&keys);
+    if let Err(e) = cache.save(cache_root) {
+        eprintln!("Error saving metadata cache: {}", e);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    #[test]
+    fn stored_and_deflated_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("Dungeon pack.zip");
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file("drops.wav", stored).unwrap();
+        zip.write_all(&data).unwrap();
+        zip.start_file("Caves/Deep/wind.ogg", deflated).unwrap();
+        zip.write_all(&data).unwrap();
+        let large: Vec<u8> = (0..3_000_000).map(|i| (i * 7 % 253) as u8).collect();
+        zip.start_file("Caves/river.ogg", deflated).unwrap();
+        zip.write_all(&large).unwrap();
+        zip.start_file("Caves/readme.txt", deflated).unwrap();
+        zip.write_all(b"text").unwrap();
+        zip.finish().unwrap();
+
+        let cache = dir.path().join("cache");
+        let files = scan_zip_files(std::slice::from_ref(&archive), &cache, &|_, _| true).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].title, "drops");
+        assert_eq!(files[0].path, Path::new("Dungeon pack/drops.wav"));
+        assert_eq!(files[1].title, "wind");
+        assert_eq!(files[1].path, Path::new("Dungeon pack/Caves/Deep/wind.ogg"));
+        assert_eq!(files[0].id, files[1].id);
+        // Only start and end of large compressed entry are unpacked for it.
+        let id = fingerprint_reader(Cursor::new(&large), large.len() as u64).unwrap();
+        assert_eq!(files[2].id, id);
+        let mut sample = EntrySample::read(Cursor::new(&large), large.len() as u64).unwrap();
+        let mut buffer = vec![1; 4];
+        sample.seek(SeekFrom::Start(1_500_000)).unwrap();
+        sample.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0; 4]);
+        sample.seek(SeekFrom::End(-4)).unwrap();
+        sample.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, large[large.len() - 4..]);
+
+        let (archive_path, name) = split_filename(&files[1].filename).unwrap();
+        assert_eq!(archive_path, archive);
+        assert_eq!(name, "Caves/Deep/wind.ogg");
+
+        for file in &files[..2] {
+            let (archive, name) = split_filename(&file.filename).unwrap();
+            let (mut entry, size) = open_entry(&archive, &name).unwrap();
+            assert_eq!(size, data.len() as u64);
+            let mut buffer = vec![0; 10];
+            entry.seek(SeekFrom::Start(150_000)).unwrap();
+            entry.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, data[150_000..150_010]);
+            entry.seek(SeekFrom::End(-3)).unwrap();
+            buffer.clear();
+            entry.read_to_end(&mut buffer).unwrap();
+            assert_eq!(buffer, data[data.len() - 3..]);
+        }
+    }
+}
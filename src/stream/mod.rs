@@ -20,13 +20,32 @@ mod trackstream;
 pub trait Opener {
     fn source(&mut self) -> Result<Box<dyn rodio::Source + Send>, Box<dyn std::error::Error>>;
     fn total_duration(&self) -> f32;
+    /// Name for error messages, e.g. file name.
+    fn name(&self) -> String;
+    /// Filename of played source for the play history.
+    fn filename(&self) -> String {
+        self.name()
+    }
 }
 
+use crate::history::{PlayHistory, PlayRecord};
 use crate::stream::trackstream::TrackStream;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use std::time::Duration;
 use threadstream::ThreadStream;
 
+/// Order of tracks in thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum ThreadOrder {
+    #[default]
+    Sequential,
+    Shuffle,
+    /// Never played tracks first, then the least recently played.
+    LeastRecent,
+}
+
 lazy_static::lazy_static! {
 static ref OSTREAM: Mutex<rodio::OutputStream> =
     Mutex::new(rodio::OutputStreamBuilder::open_default_stream().unwrap());
@@ -35,6 +54,12 @@ static ref OSTREAM: Mutex<rodio::OutputStream> =
 pub struct Stream {
     threads: Vec<ThreadStream>,
     total_volume: f32,
+    /// Messages about sources failed to open.
+    errors: Vec<String>,
+    /// Finished playing of threads removed by sync.
+    plays: Vec<PlayRecord>,
+    /// Playlist id, playlist and scene titles for the play history.
+    context: (String, String, String),
 }
 
 impl Stream {
@@ -42,20 +67,25 @@ impl Stream {
         Stream {
             threads,
             total_volume,
+            errors: vec![],
+            plays: vec![],
+            context: Default::default(),
         }
     }
 
     pub fn from_source(src: Box<dyn Opener + Send>, volume: f32) -> Stream {
-        let pl = ThreadStream::new(
-            &mut OSTREAM.lock().unwrap(),
-            vec![TrackStream::new(src, volume)],
-            1.0,
-        )
-        .unwrap();
-        Stream {
-            threads: vec![pl],
-            total_volume: 0.0,
-        }
+        Stream::from_sources(vec![src], volume)
+    }
+
+    /// One thread playing sources in turn. Thread without sources is kept,
+    /// so threads of playlist stream keep their indexes.
+    pub fn from_sources(sources: Vec<Box<dyn Opener + Send>>, volume: f32) -> Stream {
+        let tracks = sources
+            .into_iter()
+            .map(|source| TrackStream::new(source, volume))
+            .collect();
+        let thread = ThreadStream::new(&mut OSTREAM.lock().unwrap(), tracks, 1.0);
+        Stream::new(vec![thread], 0.0)
     }
 
     pub fn set_total_volume(&mut self, volume: f32) {
@@ -66,7 +96,9 @@ impl Stream {
     }
 
     pub fn set_partial_volume(&mut self, volume: f32, thread_index: usize, audio_index: usize) {
-        self.threads[thread_index].set_partial_volume(volume, audio_index);
+        if let Some(thread) = self.threads.get_mut(thread_index) {
+            thread.set_partial_volume(volume, audio_index);
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -77,6 +109,55 @@ impl Stream {
         self.threads
     }
 
+    /// Take messages about tracks failed to open.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        let mut errors = std::mem::take(&mut self.errors);
+        for thread in self.threads.iter_mut() {
+            errors.append(&mut thread.errors);
+        }
+        errors
+    }
+
+    /// Set playlist id, playlist and scene titles of play records.
+    pub fn set_context(&mut self, playlist_id: String, playlist: String, scene: String) {
+        self.context = (playlist_id, playlist, scene);
+    }
+
+    /// Take records of finished playing.
+    pub fn take_plays(&mut self) -> Vec<PlayRecord> {
+        let mut plays = std::mem::take(&mut self.plays);
+        for thread in self.threads.iter_mut() {
+            plays.append(&mut thread.plays);
+        }
+        for play in plays.iter_mut() {
+            (play.playlist_id, play.playlist, play.scene) = self.context.clone();
+        }
+        plays
+    }
+
+    /// Finish play records of current tracks, e.g. before stream replacing.
+    pub fn finish_plays(&mut self) {
+        for thread in self.threads.iter_mut() {
+            thread.finish_play(false);
+        }
+    }
+
+    pub fn set_order(&mut self, order: ThreadOrder, history: &PlayHistory) {
+        for thread in self.threads.iter_mut() {
+            let mut indexes: Vec<usize> = (0..thread.tracks.len()).collect();
+            match order {
+                ThreadOrder::Sequential => (),
+                ThreadOrder::Shuffle => indexes.shuffle(&mut rand::rng()),
+                ThreadOrder::LeastRecent => {
+                    let filenames: Vec<String> =
+                        thread.tracks.iter().map(|t| t.filename()).collect();
+                    indexes = history.least_recent_first(&filenames);
+                }
+            }
+            thread.set_order(indexes);
+        }
+    }
+
     pub fn get_current_playing(&self) -> Vec<usize> {
         let mut res = vec![];
         for th in &self.threads {
@@ -86,6 +167,7 @@ impl Stream {
     }
 
     pub fn merge(&mut self, other: Stream) {
+        self.errors.extend(other.errors);
         for (i, pl) in other.threads.into_iter().enumerate() {
             if i < self.threads.len() {
                 self.threads[i].extend(pl);
@@ -95,20 +177,47 @@ impl Stream {
         }
     }
 
+    /// Mark threads by caption of playlist thread. Stream of empty thread
+    /// gets a thread without tracks, so playlist threads keep their indexes.
+    pub fn set_thread_id(&mut self, id: &str) {
+        if self.threads.is_empty() {
+            self.threads
+                .push(ThreadStream::new(&mut OSTREAM.lock().unwrap(), vec![], 1.0));
+        }
+        for thread in self.threads.iter_mut() {
+            thread.id = id.to_string();
+        }
+    }
+
+    /// Replace sources of playing threads matched by id, so they keep
+    /// playing. Threads not found in the new stream are removed.
     pub fn sync(&mut self, new: Stream) {
         self.total_volume = new.total_volume;
-        for (i, pl) in new.threads.into_iter().enumerate() {
-            if i < self.threads.len() {
-                self.threads[i].replace_sources(pl.tracks);
-                self.threads[i].update_volume(self.total_volume);
-            } else {
-                self.threads.extend(vec![pl]);
-                self.threads[i].update_volume(self.total_volume);
-            }
+        self.errors.extend(new.errors);
+        let mut old = std::mem::take(&mut self.threads);
+        for mut thread in new.threads {
+            self.errors.append(&mut thread.errors);
+            let thread = match old.iter().position(|th| th.id == thread.id) {
+                Some(i) => {
+                    let mut kept = old.remove(i);
+                    kept.replace_sources(thread.tracks, thread.order);
+                    kept
+                }
+                None => thread,
+            };
+            self.threads.push(thread);
+        }
+        for thread in self.threads.iter_mut() {
+            thread.update_volume(self.total_volume);
+        }
+        for mut thread in old {
+            thread.finish_play(false);
+            self.plays.append(&mut thread.plays);
         }
     }
 
     pub fn merge_parallel(&mut self, other: Stream) {
+        self.errors.extend(other.errors);
         self.threads.extend(other.threads);
     }
 
@@ -137,6 +246,19 @@ impl Stream {
         }
     }
 
+    /// Position of current track of every thread from 0 to 1.
+    pub fn get_positions(&self) -> Vec<f32> {
+        self.threads.iter().map(|th| th.get_position()).collect()
+    }
+
+    /// Filename of current track of every thread.
+    pub fn get_current_files(&self) -> Vec<String> {
+        self.threads
+            .iter()
+            .map(|th| th.current_filename())
+            .collect()
+    }
+
     pub fn update(&mut self) {
         for thread in self.threads.iter_mut() {
             thread.update();
@@ -145,6 +267,8 @@ impl Stream {
     }
 
     pub fn goto_track(&mut self, thread: usize, track: usize) {
-        self.threads[thread].goto(track);
+        if let Some(thread) = self.threads.get_mut(thread) {
+            thread.goto(track);
+        }
     }
 }
@@ -18,44 +18,58 @@ use rodio::OutputStream;
 use rodio::Sink;
 
 use super::trackstream::TrackStream;
+use crate::history::{self, PlayRecord};
 
 pub struct ThreadStream {
+    /// Caption of playlist thread, threads of synced streams are matched by it.
+    pub id: String,
+    /// Empty if the thread has nothing to play, e.g. smart thread matched
+    /// no sources.
     pub tracks: Vec<TrackStream>,
     pub current: usize,
     pub sink: Sink,
     pub is_stopped: bool,
     pub volume: f32,
+    /// Messages about tracks failed to open.
+    pub errors: Vec<String>,
+    /// Play order of track indexes, tracks order if empty.
+    pub order: Vec<usize>,
+    /// Filename and start time of current track playing.
+    started: Option<(String, u64)>,
+    /// Finished playing of tracks.
+    pub plays: Vec<PlayRecord>,
 }
 
 impl ThreadStream {
-    pub fn new(
-        ostream: &mut OutputStream,
-        tracks: Vec<TrackStream>,
-        volume: f32,
-    ) -> Option<ThreadStream> {
-        if tracks.is_empty() {
-            None
-        } else {
-            let mut ts = ThreadStream {
-                tracks,
-                current: 0,
-                sink: Sink::connect_new(ostream.mixer()),
-                is_stopped: true,
-                volume,
-            };
-            match ts.goto_next_avaliable() {
-                Ok(_) => Some(ts),
-                Err(e) => {
-                    eprintln!("No available tracks in thread stream. {}", e);
-                    None
-                }
-            }
+    pub fn new(ostream: &mut OutputStream, tracks: Vec<TrackStream>, volume: f32) -> ThreadStream {
+        let mut ts = ThreadStream {
+            id: String::new(),
+            tracks,
+            current: 0,
+            sink: Sink::connect_new(ostream.mixer()),
+            is_stopped: true,
+            volume,
+            errors: vec![],
+            order: vec![],
+            started: None,
+            plays: vec![],
+        };
+        // Thread of tracks failed to open plays nothing, errors are kept.
+        if !ts.tracks.is_empty() && ts.goto_next_avaliable().is_err() {
+            ts.tracks.clear();
         }
+        ts
     }
 
-    pub fn replace_sources(&mut self, sources: Vec<TrackStream>) {
+    pub fn replace_sources(&mut self, sources: Vec<TrackStream>, order: Vec<usize>) {
         if sources.is_empty() {
-            unreachable!("Empty sources replacement");
+            // Playing state is kept, so thread continues with new sources.
+            self.finish_play(false);
+            self.sink.clear();
+            self.tracks.clear();
+            self.order.clear();
+            self.current = 0;
+            return;
         }
 
         while self.current >= sources.len() {
@@ -63,13 +77,15 @@ impl ThreadStream {
         }
 
         self.tracks = sources;
+        self.order = order;
+        let filename = self.tracks[self.current].filename();
+        if self.started.as_ref().is_some_and(|(started, _)| *started != filename) {
+            self.finish_play(false);
+        }
         let pos = self.sink.get_pos();
-        if self.tracks[self.current]
-            .reset_sink(&mut self.sink)
-            .is_err()
-        {
-            // TODO: Show error message.
-            self.goto_next_avaliable().unwrap();
+        if self.goto_next_avaliable().is_err() {
+            self.is_stopped = true;
+            return;
         }
         let _ = self.sink.try_seek(pos); // Just go from begin
 
@@ -79,16 +95,24 @@ impl ThreadStream {
     }
 
     pub fn play(&mut self) {
+        if self.started.is_none()
+            && let Some(track) = self.tracks.get(self.current)
+        {
+            self.started = Some((track.filename(), history::now()));
+        }
         self.sink.play();
         self.is_stopped = false;
     }
 
     fn next_sink_if_need(&mut self) {
-        if !self.is_stopped && self.sink.empty() {
+        if !self.is_stopped && self.sink.empty() && !self.tracks.is_empty() {
             self.sink.stop();
-            self.current = (self.current + 1) % self.tracks.len();
-            // TODO: show error message
-            self.goto_next_avaliable().unwrap();
+            self.finish_play(true);
+            self.current = self.next_index();
+            if self.goto_next_avaliable().is_err() {
+                self.is_stopped = true;
+                return;
+            }
             self.play();
             self.update_volume(self.volume);
         }
@@ -106,28 +130,47 @@ impl ThreadStream {
     pub fn stop(&mut self) {
         self.sink.stop();
         self.sink.clear();
-        self.goto(0);
+        self.goto(self.order.first().copied().unwrap_or(0));
+        self.started = None;
         self.is_stopped = true;
     }
 
     pub fn get_position(&self) -> f32 {
-        self.sink.get_pos().as_secs_f32() / self.tracks[self.current].total_duration()
+        match self.tracks.get(self.current) {
+            Some(track) => self.sink.get_pos().as_secs_f32() / track.total_duration(),
+            None => 0.0,
+        }
     }
 
     pub fn update_volume(&mut self, volume: f32) {
         self.volume = volume;
-        self.sink
-            .set_volume(volume * self.tracks[self.current].get_volume());
+        let track_volume = self
+            .tracks
+            .get(self.current)
+            .map_or(1.0, |t| t.get_volume());
+        self.sink.set_volume(volume * track_volume);
+    }
+
+    /// Filename of current track, empty if thread has no tracks.
+    pub fn current_filename(&self) -> String {
+        self.tracks
+            .get(self.current)
+            .map(|t| t.filename())
+            .unwrap_or_default()
     }
 
     pub fn set_partial_volume(&mut self, vol: f32, index: usize) {
-        self.tracks[index].set_volume(vol);
+        let Some(track) = self.tracks.get_mut(index) else {
+            return;
+        };
+        track.set_volume(vol);
         if index == self.current {
             self.update_volume(self.volume);
         }
     }
 
     pub fn goto(&mut self, index: usize) {
+        self.finish_play(false);
         self.current = index;
         let _ = self.goto_next_avaliable();
         self.play();
@@ -135,21 +178,69 @@ impl ThreadStream {
     }
 
     pub fn extend(&mut self, other: ThreadStream) {
+        let was_empty = self.tracks.is_empty();
+        self.errors.extend(other.errors);
         self.tracks.extend(other.tracks);
+        self.order.clear();
+        if was_empty && self.goto_next_avaliable().is_err() {
+            self.tracks.clear();
+        }
+    }
+
+    /// Set play order, stopped thread goes to the first track.
+    pub fn set_order(&mut self, order: Vec<usize>) {
+        self.order = order;
+        if self.is_stopped
+            && self.started.is_none()
+            && let Some(&first) = self.order.first()
+        {
+            self.current = first;
+            let _ = self.goto_next_avaliable();
+        }
+    }
+
+    /// Add record of current track playing to plays.
+    pub fn finish_play(&mut self, completed: bool) {
+        if let Some((filename, start)) = self.started.take() {
+            self.plays.push(PlayRecord {
+                filename,
+                start,
+                end: history::now(),
+                completed,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Track after current one by play order.
+    fn next_index(&self) -> usize {
+        if self.order.len() != self.tracks.len() {
+            return (self.current + 1) % self.tracks.len();
+        }
+        let position = self.order.iter().position(|&i| i == self.current);
+        position.map_or(0, |p| self.order[(p + 1) % self.order.len()])
     }
 
+    /// Open current track or next one which could be opened.
+    /// Failed tracks are reported to errors.
     fn goto_next_avaliable(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let mut tries_counter = 0;
 
-        while self.current < self.tracks.len()
-            && self.tracks[self.current]
-                .reset_sink(&mut self.sink)
-                .is_err()
-        {
-            self.current = (self.current + 1) % self.tracks.len();
+        while self.current < self.tracks.len() {
+            match self.tracks[self.current].reset_sink(&mut self.sink) {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    let message = format!("{}: {}", self.tracks[self.current].name(), e);
+                    if !self.errors.contains(&message) {
+                        self.errors.push(message);
+                    }
+                }
+            }
+
+            self.current = self.next_index();
             tries_counter += 1;
             if tries_counter == self.tracks.len() {
-                return Err("Something went wrong with running next track".into());
+                return Err("No available tracks in thread stream".into());
             }
         }
         Ok(())
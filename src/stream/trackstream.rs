@@ -46,4 +46,12 @@ impl TrackStream {
     pub fn total_duration(&self) -> f32 {
         self.source.total_duration()
     }
+
+    pub fn name(&self) -> String {
+        self.source.name()
+    }
+
+    pub fn filename(&self) -> String {
+        self.source.filename()
+    }
 }